@@ -0,0 +1,83 @@
+// Compares proof creation time across witness backends.
+// The native backend is skipped unless its generator has been built with `make`.
+
+use blockchain_rust::proofs::inclusion::{InclusionInput, ProofOfInclusion};
+use blockchain_rust::proofs::liabilities::{LiabilitiesInput, MerkleSumTreeChange, ProofOfLiabilities};
+use blockchain_rust::proofs::setup::CircuitSetup;
+use blockchain_rust::proofs::witness::WitnessBackend;
+use criterion::{criterion_group, criterion_main, Criterion};
+use merkle_sum_tree::{Leaf, MerkleSumTree};
+use std::sync::Arc;
+
+const BACKENDS: [WitnessBackend; 3] = [
+    WitnessBackend::Wasm,
+    WitnessBackend::Native,
+    WitnessBackend::Rust,
+];
+
+fn setup_trees() -> (Arc<MerkleSumTree>, Arc<MerkleSumTree>) {
+    let leaf_empty = Leaf::new("0".to_string(), 0);
+    let old_leafs = vec![
+        Leaf::new("alice".to_string(), 50),
+        Leaf::new("bob".to_string(), 20),
+        leaf_empty.clone(),
+        leaf_empty.clone(),
+    ];
+    let new_leafs = vec![
+        Leaf::new("alice".to_string(), 100),
+        Leaf::new("bob".to_string(), 20),
+        leaf_empty.clone(),
+        leaf_empty,
+    ];
+    (
+        Arc::new(MerkleSumTree::new(old_leafs).unwrap()),
+        Arc::new(MerkleSumTree::new(new_leafs).unwrap()),
+    )
+}
+
+fn bench_inclusion(c: &mut Criterion) {
+    let (old_tree, new_tree) = setup_trees();
+    let circuit_setup = CircuitSetup::new("inclusion");
+    let mut group = c.benchmark_group("inclusion_proof");
+    group.sample_size(10);
+    for backend in BACKENDS {
+        if backend != WitnessBackend::Rust && circuit_setup.get_witness_generator(backend).is_err() {
+            println!("skipping {} backend, generator not built", backend.name());
+            continue;
+        }
+        group.bench_function(backend.name(), |b| {
+            b.iter(|| {
+                let inputs = vec![
+                    InclusionInput::new(&old_tree, 1).unwrap(),
+                    InclusionInput::new(&new_tree, 1).unwrap(),
+                ];
+                ProofOfInclusion::new_with_backend(inputs, &circuit_setup, backend).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_liabilities(c: &mut Criterion) {
+    let (old_tree, new_tree) = setup_trees();
+    let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+    let mut group = c.benchmark_group("liabilities_proof");
+    group.sample_size(10);
+    for backend in BACKENDS {
+        if backend != WitnessBackend::Rust && circuit_setup.get_witness_generator(backend).is_err() {
+            println!("skipping {} backend, generator not built", backend.name());
+            continue;
+        }
+        group.bench_function(backend.name(), |b| {
+            b.iter(|| {
+                let change = MerkleSumTreeChange::new(0, Arc::clone(&old_tree), Arc::clone(&new_tree));
+                let inputs = vec![LiabilitiesInput::new(vec![change]).unwrap()];
+                ProofOfLiabilities::new_with_backend(inputs, &circuit_setup, backend).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_inclusion, bench_liabilities);
+criterion_main!(benches);
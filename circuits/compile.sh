@@ -0,0 +1,73 @@
+#!/usr/bin/env bash
+# Compiles the inclusion and liabilities circuits for every supported tree depth.
+# Artifacts are written to circuits/compile/depth_<N>/, which is where
+# CircuitRegistry looks for them at runtime.
+#
+# Usage: ./circuits/compile.sh [min_depth] [max_depth]
+# Set BUILD_NATIVE=1 to also build the C++ witness generators (needs make, nasm and gmp).
+set -euo pipefail
+
+cd "$(dirname "$0")"
+
+MIN_DEPTH=${1:-2}
+MAX_DEPTH=${2:-10}
+
+for depth in $(seq "$MIN_DEPTH" "$MAX_DEPTH"); do
+    out="compile/depth_${depth}"
+    mkdir -p "$out"
+    for circuit in inclusion liabilities_changes_folding; do
+        echo "Compiling ${circuit} for depth ${depth}"
+        circom "main/${circuit}_${depth}.circom" --r1cs --wasm --sym --c --prime vesta --output "$out"
+        # circom names outputs after the main file, the registry expects the circuit name
+        mv "$out/${circuit}_${depth}.r1cs" "$out/${circuit}.r1cs"
+        mv "$out/${circuit}_${depth}.sym" "$out/${circuit}.sym"
+        rm -rf "$out/${circuit}_js" "$out/${circuit}_cpp"
+        mv "$out/${circuit}_${depth}_js" "$out/${circuit}_js"
+        mv "$out/${circuit}_js/${circuit}_${depth}.wasm" "$out/${circuit}_js/${circuit}.wasm"
+        mv "$out/${circuit}_${depth}_cpp" "$out/${circuit}_cpp"
+        # Native witness generator, used by the `native` witness backend. The binary
+        # reads <binary>.dat, so the sources, data and make target are renamed after the circuit.
+        mv "$out/${circuit}_cpp/${circuit}_${depth}.cpp" "$out/${circuit}_cpp/${circuit}.cpp"
+        mv "$out/${circuit}_cpp/${circuit}_${depth}.dat" "$out/${circuit}_cpp/${circuit}.dat"
+        sed -i.bak "s/${circuit}_${depth}/${circuit}/g" "$out/${circuit}_cpp/Makefile" && rm "$out/${circuit}_cpp/Makefile.bak"
+        if [ -n "${BUILD_NATIVE:-}" ]; then
+            (cd "$out/${circuit}_cpp" && make)
+        fi
+    done
+done
+
+# Regenerate the manifest from every build present, verified at load time
+echo "Writing compile/manifest.json"
+{
+    echo '{'
+    echo '  "version": 1,'
+    echo '  "circuits": ['
+    first=1
+    for dir in $(ls -d compile/depth_* | sort -t_ -k2 -n); do
+        depth=${dir##*_}
+        for circuit in inclusion liabilities_changes_folding; do
+            [ -f "$dir/${circuit}.r1cs" ] || continue
+            r1cs_hash=$(sha256sum "$dir/${circuit}.r1cs" | cut -d' ' -f1)
+            wasm_hash=$(sha256sum "$dir/${circuit}_js/${circuit}.wasm" | cut -d' ' -f1)
+            sym_hash=$(sha256sum "$dir/${circuit}.sym" | cut -d' ' -f1)
+            native_hash=$(cat "$dir/${circuit}_cpp/${circuit}.cpp" "$dir/${circuit}_cpp/${circuit}.dat" | sha256sum | cut -d' ' -f1)
+            [ $first -eq 1 ] || echo '    },'
+            first=0
+            echo '    {'
+            echo "      \"name\": \"${circuit}\","
+            echo "      \"depth\": ${depth},"
+            echo '      "changes_per_step": 1,'
+            echo "      \"r1cs_hash\": \"${r1cs_hash}\","
+            echo "      \"witness_generator_hash\": \"${wasm_hash}\","
+            echo "      \"symbols_hash\": \"${sym_hash}\","
+            echo "      \"native_witness_generator_hash\": \"${native_hash}\""
+        done
+    done
+    echo '    }'
+    echo '  ]'
+    echo '}'
+} > compile/manifest.json
+
+# Parameter digests let verifiers check public parameters without generating them
+echo "Recording public parameter digests"
+(cd .. && cargo run --release -- circuit-digests)
@@ -25,11 +25,21 @@ template inclusion(levels) {
     merklesumi.sumL <== neighborsSum[0];
     merklesumi.sumR <== userBalance;
 
+    // step_out is the latest root and leaf, and step_in[3] is an accumulator
+    // every claim is chained onto, so that all steps are bound, not only the last
+    component acc = MiMCSponge(5, 220, 1);
+    acc.ins[0] <== step_in[3];
+    acc.ins[1] <== sum;
+    acc.ins[2] <== rootHash;
+    acc.ins[3] <== userBalance;
+    acc.ins[4] <== userHash;
+    acc.k <== 0;
+
     signal output step_out[4];
     step_out[0] <== sum;
-    step_out[1] <== rootHash; 
-    step_out[2] <== userBalance;
-    step_out[3] <== userHash;
+    step_out[1] <== rootHash;
+    step_out[2] <== userHash;
+    step_out[3] <== acc.outs[0];
 
     // Initialize sum and hash nodes
     signal sumNodes[levels+1];
@@ -72,6 +82,3 @@ template inclusion(levels) {
     // Assert sum is valid
     sumNodes[levels] === sum;
 }
-
-// Define main component
-component main {public [step_in]}= inclusion(2);
@@ -137,5 +137,3 @@ template liabilities(levels,changes) {
     step_out[3] <== sumNodes[1][changes-1][levels];
 
 }
-
-component main {public [step_in]}= liabilities(2,1);
\ No newline at end of file
@@ -0,0 +1,4 @@
+pragma circom 2.0.0;
+include "../inclusion.circom";
+
+component main {public [step_in]} = inclusion(3);
@@ -0,0 +1,4 @@
+pragma circom 2.0.0;
+include "../liabilities_changes_folding.circom";
+
+component main {public [step_in]} = liabilities(2, 1);
@@ -1,4 +1,5 @@
 pub type Result<T> = std::result::Result<T, failure::Error>;
+use crate::proofs::util::block_hash;
 use chrono;
 use merkle_sum_tree::MerkleSumTree;
 use sha2::{Digest, Sha256};
@@ -46,10 +47,20 @@ impl Block {
         &self.prev_block_hash
     }
 
+    /// Digest of the transactions, which the block hash covers in their place.
+    pub fn get_transactions_hash(&self) -> String {
+        Self::hash_transactions(&self.transactions)
+    }
+
     pub fn get_merkle_sum_tree(&self) -> &MerkleSumTree {
         &self.merkle_sum_tree
     }
 
+    /// The same tree, shared rather than borrowed from the block.
+    pub fn get_shared_merkle_sum_tree(&self) -> Arc<MerkleSumTree> {
+        Arc::clone(&self.merkle_sum_tree)
+    }
+
     pub fn get_block_number(&self) -> i32 {
         self.block_number
     }
@@ -58,6 +69,12 @@ impl Block {
         &self.timestamp
     }
 
+    /// Index of `address`'s leaf in this block's tree. Leaves move as others
+    /// are inserted before them, so this can differ between blocks.
+    pub fn get_leaf_index(&self, address: &str) -> Option<usize> {
+        self.leaf_index.get(address).copied()
+    }
+
     pub fn new(
         block_number: i32,
         transactions: Vec<Transaction>,
@@ -96,26 +113,23 @@ impl Block {
         timestamp: &str,
         merkle_sum_tree: &Arc<MerkleSumTree>,
     ) -> String {
-        let mut hasher = Sha256::new();
-
-        hasher.update(block_number.to_be_bytes());
-        hasher.update(prev_block_hash.as_bytes());
-        hasher.update(timestamp.as_bytes());
+        block_hash(
+            block_number,
+            prev_block_hash,
+            timestamp,
+            &Self::hash_transactions(transactions),
+            &merkle_sum_tree.get_root_hash().unwrap().to_string(),
+            merkle_sum_tree.get_root_sum().unwrap(),
+        )
+    }
 
+    fn hash_transactions(transactions: &[Transaction]) -> String {
+        let mut hasher = Sha256::new();
         for tx in transactions {
             hasher.update(tx.from.as_bytes());
             hasher.update(tx.to.as_bytes());
             hasher.update(tx.amount.to_be_bytes());
         }
-
-        if let Ok(root_hash) = merkle_sum_tree.get_root_hash() {
-            hasher.update(root_hash.to_string().as_bytes());
-        }
-
-        if let Ok(root_sum) = merkle_sum_tree.get_root_sum() {
-            hasher.update(root_sum.to_be_bytes());
-        }
-
         format!("{:x}", hasher.finalize())
     }
 }
@@ -2,13 +2,25 @@
 
 use crate::blockchain::block::Block;
 use crate::blockchain::block::Transaction;
-use crate::proofs::inclusion::{InclusionInput, ProofOfInclusion};
+use crate::errors::BlockchainError;
+use crate::proofs::bundle::{BlockWrapper, ProofOfLiabilitiesWrapper};
+use crate::proofs::encoding::{ProofEncoding, ProofFormat};
+use crate::proofs::inclusion::{InclusionFolding, InclusionInput, ProofOfInclusion};
 use crate::proofs::liabilities::{LiabilitiesInput, MerkleSumTreeChange, ProofOfLiabilities};
+use crate::proofs::native::{InclusionCircuit, LiabilitiesCircuit, NativeCircuitSetup};
+use crate::proofs::registry::{
+    tree_depth, CircuitKind, CircuitRegistry, CircuitSource, MAX_CIRCUIT_DEPTH, MIN_CIRCUIT_DEPTH,
+};
 use crate::proofs::setup::{CircuitSetup, PP};
+use crate::proofs::util::leaf_commitment;
+use crate::proofs::witness::WitnessBackend;
 use merkle_sum_tree::{Leaf, MerkleSumTree};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
 pub type Result<T> = std::result::Result<T, failure::Error>;
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet};
 
 pub const MAX_LEVELS: usize = 2;
 pub const MAX_USERS: usize = 4; //MAX_LEVELS^2
@@ -22,10 +34,66 @@ pub struct Blockchain {
     changes: Vec<MerkleSumTreeChange>,
     merkle_sum_tree: Arc<MerkleSumTree>,
     liabilities_proved: bool,
-    liabilities_proof: Option<ProofOfLiabilities>,
-    liabilities_circuit_setup: CircuitSetup,
-    inclusion_circuit_setup: CircuitSetup,
+    liabilities_proof: Option<LiabilitiesProof>,
+    circuit_registry: Arc<CircuitRegistry>,
     leaf_index: HashMap<String, usize>,
+    // per-address secret salts, leaves are committed to as H(address, salt)
+    salts: HashMap<String, String>,
+    // secret the salts are derived from, so an address has its salt before its first leaf
+    salt_key: [u8; 32],
+    // block hashes by block number, genesis first
+    block_hashes: Vec<String>,
+    // per-address block numbers where the address' leaf changed, oldest first
+    leaf_changes: HashMap<String, Vec<i32>>,
+    // addresses whose leaf changed since the last block
+    touched: HashSet<String>,
+    max_users: usize,
+    witness_backend: WitnessBackend,
+    circuit_source: CircuitSource,
+}
+
+#[derive(Clone)]
+enum LiabilitiesProof {
+    Compiled(ProofOfLiabilities),
+    Native(ProofOfLiabilities<LiabilitiesCircuit>),
+}
+
+/// The setup balance histories are proven with, see `CircuitSource`.
+#[derive(Clone)]
+pub enum InclusionSetup {
+    Compiled(Arc<CircuitSetup>),
+    Native(NativeCircuitSetup),
+}
+
+/// Balances as of the head block, copied out so reads can be answered
+/// without the blockchain lock.
+#[derive(Debug, Clone, Default)]
+pub struct StateSnapshot {
+    block_number: i32,
+    balances: HashMap<String, i32>,
+}
+
+/// Inclusive block number bounds on a balance history, open where `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BlockRange {
+    from: Option<i32>,
+    to: Option<i32>,
+}
+
+/// What proving a user's balance history needs from each block: its header,
+/// its tree and the user's leaf index in it. Captured while the blockchain lock
+/// is held so the proof itself can be built without it.
+#[derive(Clone)]
+pub struct InclusionSnapshot {
+    address: String,
+    range: BlockRange,
+    changes_only: bool,
+    // the user's leaf index in each of `trees`
+    indices: Vec<usize>,
+    head_hash: String,
+    headers: Vec<BlockWrapper>,
+    trees: Vec<Arc<MerkleSumTree>>,
+    witness_backend: WitnessBackend,
 }
 
 impl Blockchain {
@@ -44,15 +112,129 @@ impl Blockchain {
         &self.changes
     }
 
+    pub fn get_current_hash(&self) -> &str {
+        &self.current_hash
+    }
+
+    pub fn get_state_snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            block_number: self.current_block_number,
+            balances: self.state.clone(),
+        }
+    }
+
+    pub fn get_head_block(&self) -> Option<&Block> {
+        self.chain.get(&self.current_hash)
+    }
+
+    pub fn get_leaf_index(&self, address: &str) -> Option<usize> {
+        self.leaf_index.get(address).copied()
+    }
+
+    pub fn get_salt(&self, address: &str) -> Option<&str> {
+        self.salts.get(address).map(|salt| salt.as_str())
+    }
+
+    /// The ID of `address`'s leaf in the merkle sum tree.
+    pub fn get_leaf_id(&self, address: &str) -> Option<String> {
+        self.get_salt(address).map(|salt| leaf_commitment(address, salt))
+    }
+
+    /// The salt of `address`, the same before and after it gets its first leaf.
+    pub fn derive_salt(&self, address: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt_key);
+        hasher.update(address.as_bytes());
+        hex::encode(&hasher.finalize()[..16])
+    }
+
+    /// The ID the leaf of `address` has, or will have once it gets one.
+    pub fn derive_leaf_id(&self, address: &str) -> String {
+        leaf_commitment(address, &self.derive_salt(address))
+    }
+
+    /// Returns the salt of `address`, recording it on first use.
+    fn register(&mut self, address: &str) -> String {
+        let salt = self.derive_salt(address);
+        self.salts.entry(address.to_string()).or_insert(salt).clone()
+    }
+
+    /// The block with `block_number`, genesis being 1, looked up by its hash
+    /// in the block hash index.
+    pub fn get_block(&self, block_number: i32) -> Option<&Block> {
+        let position = usize::try_from(block_number).ok()?.checked_sub(1)?;
+        self.chain.get(self.block_hashes.get(position)?)
+    }
+
+    /// Block numbers where the leaf of `address` changed, oldest first.
+    pub fn get_leaf_changes(&self, address: &str) -> &[i32] {
+        self.leaf_changes.get(address).map_or(&[], |changes| changes.as_slice())
+    }
+
+    pub fn set_witness_backend(&mut self, witness_backend: WitnessBackend) {
+        self.witness_backend = witness_backend;
+    }
+
+    pub fn get_circuit_source(&self) -> CircuitSource {
+        self.circuit_source
+    }
+
+    /// The inclusion setup matching the tree depth and the circuit source.
+    pub fn get_inclusion_setup(&self) -> Result<InclusionSetup> {
+        match self.circuit_source {
+            CircuitSource::Compiled => Ok(InclusionSetup::Compiled(self.get_inclusion_circuit_setup()?)),
+            CircuitSource::Native => Ok(InclusionSetup::Native(self.get_native_setup(CircuitKind::Inclusion)?)),
+        }
+    }
+
+    fn get_native_setup(&self, kind: CircuitKind) -> Result<NativeCircuitSetup> {
+        // One change per folding step, as in `prove_merkle_tree`
+        NativeCircuitSetup::new(kind, tree_depth(&self.merkle_sum_tree), 1)
+    }
+
+    pub fn get_inclusion_circuit_setup(&self) -> Result<Arc<CircuitSetup>> {
+        self.circuit_registry
+            .for_tree(CircuitKind::Inclusion, &self.merkle_sum_tree)
+    }
+
+    fn get_liabilities_circuit_setup(&self) -> Result<Arc<CircuitSetup>> {
+        self.circuit_registry
+            .for_tree(CircuitKind::LiabilitiesChangesFolding, &self.merkle_sum_tree)
+    }
+
     pub fn create_blockchain() -> Result<Blockchain> {
+        Blockchain::create_blockchain_with_depth(MAX_LEVELS)
+    }
+
+    /// Creates a chain whose merkle sum tree has `levels` levels (2^levels users).
+    /// Fails if no compiled circuits exist for that depth.
+    pub fn create_blockchain_with_depth(levels: usize) -> Result<Blockchain> {
+        Blockchain::create_blockchain_with_source(levels, CircuitSource::Compiled)
+    }
+
+    /// Like `create_blockchain_with_depth`, proving with the circuits of
+    /// `circuit_source`. Native circuits need no compiled artifacts.
+    pub fn create_blockchain_with_source(levels: usize, circuit_source: CircuitSource) -> Result<Blockchain> {
+        if !(MIN_CIRCUIT_DEPTH..=MAX_CIRCUIT_DEPTH).contains(&levels) {
+            return Err(failure::format_err!(
+                "Unsupported tree depth {}, expected {}..={}",
+                levels,
+                MIN_CIRCUIT_DEPTH,
+                MAX_CIRCUIT_DEPTH
+            ));
+        }
+        let max_users = 1 << levels;
         let mut chain = HashMap::new();
         let mempool = Vec::new();
         let state = HashMap::new();
         let leaf_index = HashMap::new();
+        let salts = HashMap::new();
+        let mut salt_key = [0u8; 32];
+        OsRng.fill_bytes(&mut salt_key);
         let leaf_0 = Leaf::new("0".to_string(), 0);
-        let mut leafs = Vec::with_capacity(MAX_USERS);
+        let mut leafs = Vec::with_capacity(max_users);
         let changes = Vec::new();
-        for _ in 0..MAX_USERS {
+        for _ in 0..max_users {
             leafs.push(leaf_0.clone());
         }
         let merkle_sum_tree = Arc::new(MerkleSumTree::new(leafs.clone()).unwrap());
@@ -65,31 +247,14 @@ impl Blockchain {
             Arc::clone(&merkle_sum_tree),
         )?;
         let block_hash = block.get_hash().to_string();
+        let block_hashes = vec![block_hash.clone()];
         let liabilities_proof = None;
         chain.insert(block_hash.clone(), block);
         
-        println!("Initializing circuits in parallel...");
-        let start_time = std::time::Instant::now();
-        
-        // Load circuits in parallel using threads
-        let liabilities_handle = std::thread::spawn(|| {
-            let start = std::time::Instant::now();
-            let setup = CircuitSetup::new("liabilities_changes_folding");
-            println!("  Liabilities circuit ready in {:?}", start.elapsed());
-            setup
-        });
-        
-        let inclusion_handle = std::thread::spawn(|| {
-            let start = std::time::Instant::now();
-            let setup = CircuitSetup::new("inclusion");
-            println!("  Inclusion circuit ready in {:?}", start.elapsed());
-            setup
-        });
-        
-        let liabilities_circuit_setup = liabilities_handle.join().unwrap();
-        let inclusion_circuit_setup = inclusion_handle.join().unwrap();
-        
-        println!("All circuits initialized in {:?}", start_time.elapsed());
+        let circuit_registry = Arc::new(CircuitRegistry::new()?);
+        if circuit_source == CircuitSource::Compiled {
+            Blockchain::load_circuits(&circuit_registry, levels)?;
+        }
         
         let bc: Blockchain = Blockchain {
             current_block_number,
@@ -101,14 +266,49 @@ impl Blockchain {
             merkle_sum_tree,
             liabilities_proof,
             leaf_index,
-            liabilities_circuit_setup,
-            inclusion_circuit_setup,
+            salts,
+            salt_key,
+            block_hashes,
+            leaf_changes: HashMap::new(),
+            touched: HashSet::new(),
+            circuit_registry,
             liabilities_proved: true,
+            max_users,
+            witness_backend: WitnessBackend::default(),
+            circuit_source,
         };
 
         Ok(bc)
     }
 
+    fn load_circuits(circuit_registry: &Arc<CircuitRegistry>, levels: usize) -> Result<()> {
+        println!("Initializing circuits in parallel...");
+        let start_time = std::time::Instant::now();
+        
+        // Load the circuits matching the tree depth in parallel using threads
+        let registry = Arc::clone(circuit_registry);
+        let liabilities_handle = std::thread::spawn(move || {
+            let start = std::time::Instant::now();
+            let setup = registry.get(CircuitKind::LiabilitiesChangesFolding, levels);
+            println!("  Liabilities circuit ready in {:?}", start.elapsed());
+            setup
+        });
+
+        let registry = Arc::clone(circuit_registry);
+        let inclusion_handle = std::thread::spawn(move || {
+            let start = std::time::Instant::now();
+            let setup = registry.get(CircuitKind::Inclusion, levels);
+            println!("  Inclusion circuit ready in {:?}", start.elapsed());
+            setup
+        });
+
+        liabilities_handle.join().unwrap()?;
+        inclusion_handle.join().unwrap()?;
+        
+        println!("All circuits initialized in {:?}", start_time.elapsed());
+        Ok(())
+    }
+
     pub fn add_block(&mut self) -> Result<()> {
         let mempool_transactions = std::mem::take(&mut self.mempool);
         let transaction_count = mempool_transactions.len();
@@ -130,6 +330,13 @@ impl Blockchain {
             transaction_count
         );
         self.current_hash = block.get_hash().to_string();
+        self.block_hashes.push(self.current_hash.clone());
+        for address in std::mem::take(&mut self.touched) {
+            self.leaf_changes
+                .entry(address)
+                .or_default()
+                .push(self.current_block_number);
+        }
         self.chain.insert(block.get_hash().to_string(), block);
 
         Ok(())
@@ -171,35 +378,41 @@ impl Blockchain {
     fn update_state(&mut self, address: &str, amount: i32) -> Result<()> {
         let address_string = address.to_string();
         self.state.insert(address_string.clone(), amount);
-        let index_option = self.leaf_index.get(address);
-        let index: usize;
-        let leaf = Leaf::new(address_string.clone(), amount);
-        let old_merkle_tree = Arc::clone(&self.merkle_sum_tree);
+        let leaf_id = leaf_commitment(address, &self.register(address));
+        let leaf = Leaf::new(leaf_id.clone(), amount);
 
-        let mut new_tree = MerkleSumTree::new(self.merkle_sum_tree.get_leafs().to_vec()).unwrap();
-
-        if index_option.is_some() {
-            index = *index_option.unwrap();
-            _ = new_tree.set_leaf(leaf.clone(), index);
-        } else {
-            // Validate we haven't exceeded MAX_USERS
-            if self.leaf_index.len() >= MAX_USERS {
-                return Err(failure::format_err!(
-                    "Maximum number of users ({}) exceeded. Cannot add new user '{}'",
-                    MAX_USERS,
-                    address
-                ));
+        let index = match self.leaf_index.get(address) {
+            Some(&index) => index,
+            None => {
+                // Validate we haven't exceeded the tree capacity
+                if self.leaf_index.len() >= self.max_users {
+                    return Err(failure::format_err!(
+                        "Maximum number of users ({}) exceeded. Cannot add new user '{}'",
+                        self.max_users,
+                        address
+                    ));
+                }
+                // New leaves take the next free slot, so no other leaf moves
+                let index = self.leaf_index.len();
+                self.leaf_index.insert(address_string, index);
+                index
             }
-            index = new_tree.push(leaf.clone()).unwrap();
-            self.leaf_index.insert(address_string, index);
-        }
+        };
+        self.set_leaf(leaf, index);
+        self.touched.insert(address.to_string());
+        Ok(())
+    }
+
+    fn set_leaf(&mut self, leaf: Leaf, index: usize) {
+        let old_merkle_tree = Arc::clone(&self.merkle_sum_tree);
+        let mut new_tree = MerkleSumTree::new(self.merkle_sum_tree.get_leafs().to_vec()).unwrap();
+        _ = new_tree.set_leaf(leaf, index);
 
         let new_merkle_tree = Arc::new(new_tree);
         self.merkle_sum_tree = Arc::clone(&new_merkle_tree);
         let change = MerkleSumTreeChange::new(index, old_merkle_tree, new_merkle_tree);
         self.liabilities_proved = false;
         self.changes.push(change);
-        Ok(())
     }
 
     fn prove_merkle_tree(&mut self) -> Result<()> {
@@ -208,8 +421,22 @@ impl Blockchain {
         for change in changes {
             liabilities_inputs.push(LiabilitiesInput::new(vec![change]).unwrap())
         }
-        let circuit_setup = &self.liabilities_circuit_setup;
-        let (liabilities_proof, _pp) = ProofOfLiabilities::new(liabilities_inputs, circuit_setup)?;
+        let liabilities_proof = match self.circuit_source {
+            CircuitSource::Compiled => {
+                let circuit_setup = self.get_liabilities_circuit_setup()?;
+                let (liabilities_proof, _pp) = ProofOfLiabilities::new_with_backend(
+                    liabilities_inputs,
+                    &circuit_setup,
+                    self.witness_backend,
+                )?;
+                LiabilitiesProof::Compiled(liabilities_proof)
+            }
+            CircuitSource::Native => {
+                let circuit_setup = self.get_native_setup(CircuitKind::LiabilitiesChangesFolding)?;
+                let (liabilities_proof, _pp) = ProofOfLiabilities::new_native(liabilities_inputs, &circuit_setup)?;
+                LiabilitiesProof::Native(liabilities_proof)
+            }
+        };
         self.liabilities_proof = Some(liabilities_proof);
         self.liabilities_proved = true;
         Ok(())
@@ -222,74 +449,233 @@ impl Blockchain {
         Ok(())
     }
 
-    pub fn get_inclusion_proof(
+    /// The blocks proving the balance of `address` over `range`: every distinct
+    /// tree since its leaf appeared or, with `changes_only`, the blocks where its
+    /// leaf changed and the last block of the range to link them to its root.
+    /// Blocks are oldest first, so the snapshot of a longer history extends
+    /// the one of a shorter one.
+    pub fn get_inclusion_snapshot(
         &self,
         address: &str,
-    ) -> (Option<ProofOfInclusion>, Option<Vec<Block>>, Option<PP>) {
-        let index_option = self.leaf_index.get(address);
-        let index: usize;
-        let mut blocks = vec![];
-        if index_option.is_some() {
-            index = *index_option.unwrap();
-        } else {
-            return (None, None, None);
-        }
-        let mut current_block = self.chain.get(&self.current_hash).unwrap();
-        let mut inclusion_inputs = vec![];
-        let mut last_root_hash = "".to_string();
-
-        loop {
-            let user_leaf = current_block.get_merkle_sum_tree().get_leaf(index);
-            match user_leaf {
-                Ok(leaf) => {
-                    if leaf.get_id() != address {
-                        break;
-                    }
-                },
-                Err(_) => {
-                    break;
-                }
-            }
-            
-            let inclusion_input =
-                InclusionInput::new(current_block.get_merkle_sum_tree(), index).unwrap();
-            let current_root_hash = inclusion_input.get_root_hash().to_string();
-            
-            // Only include blocks with unique tree states (ignore consecutive blocks with same tree)
-            if current_root_hash != last_root_hash {
-                blocks.push(current_block.clone());
-                inclusion_inputs.push(inclusion_input.clone());
-                last_root_hash = current_root_hash;
+        range: BlockRange,
+        changes_only: bool,
+    ) -> Option<InclusionSnapshot> {
+        let changes = self.get_leaf_changes(address);
+        let first = *changes.first()?;
+        let start = range.get_from().map_or(first, |from| from.max(first));
+        let end = range
+            .get_to()
+            .map_or(self.current_block_number, |to| to.min(self.current_block_number));
+        let numbers: Vec<i32> = if changes_only {
+            let mut numbers: Vec<i32> = changes
+                .iter()
+                .copied()
+                .filter(|number| (start..=end).contains(number))
+                .collect();
+            if start <= end && numbers.last() != Some(&end) {
+                numbers.push(end);
             }
+            numbers
+        } else {
+            (start..=end).collect()
+        };
 
-            // Move to previous block
-            let prev_hash = current_block.get_previous_hash();
-            
-            if let Some(prev_block) = self.chain.get(prev_hash) {
-                current_block = prev_block;
-            } else {
-                // Previous block not found - this indicates we've reached genesis
-                break;
+        let mut headers = vec![];
+        let mut trees = vec![];
+        let mut indices = vec![];
+        let mut last_root_hash = "".to_string();
+        for number in numbers {
+            let block = self.get_block(number)?;
+            let index = block.get_leaf_index(address)?;
+            let root_hash = block.get_merkle_sum_tree().get_root_hash().unwrap().to_string();
+            // Only include blocks with unique tree states, represented by the
+            // first block with that tree so the history prefix stays stable
+            if root_hash != last_root_hash {
+                headers.push(BlockWrapper::from_block(block));
+                trees.push(block.get_shared_merkle_sum_tree());
+                indices.push(index);
+                last_root_hash = root_hash;
             }
         }
-        
-        let proof =
-            ProofOfInclusion::new(inclusion_inputs, &self.inclusion_circuit_setup);
-        
+
+        Some(InclusionSnapshot {
+            address: address.to_string(),
+            range,
+            changes_only,
+            indices,
+            head_hash: self.current_hash.clone(),
+            headers,
+            trees,
+            witness_backend: self.witness_backend,
+        })
+    }
+
+    /// Proves the balance history of `address` over the blocks in `range`,
+    /// see `get_inclusion_snapshot`.
+    pub fn get_inclusion_proof(
+        &self,
+        address: &str,
+        range: BlockRange,
+        changes_only: bool,
+    ) -> (Option<ProofOfInclusion>, Option<Vec<BlockWrapper>>, Option<PP>) {
+        let snapshot = match self.get_inclusion_snapshot(address, range, changes_only) {
+            Some(snapshot) => snapshot,
+            None => return (None, None, None),
+        };
+
+        let proof = self
+            .get_inclusion_circuit_setup()
+            .and_then(|circuit_setup| snapshot.prove(&circuit_setup));
         match proof {
-            Ok((proof, client_pp)) => {                                
-                return (Some(proof), Some(blocks), Some(client_pp));
-            },
+            Ok((proof, headers, client_pp)) => (Some(proof), Some(headers), Some(client_pp)),
             Err(e) => {
                 println!("Proof creation failed: {}", e);
-                return (None, None, None);
+                (None, None, None)
+            }
+        }
+    }
+
+    /// The latest liabilities proof with the parameters it verifies against
+    /// and the head header, or `None` before any change was proven.
+    pub fn get_liabilities_proof(&self, format: ProofFormat) -> Result<Option<String>> {
+        let header = self.get_head_block().map(BlockWrapper::from_block);
+        // Every earlier header, so that clients can check any block against the head
+        let chain: Vec<BlockWrapper> = self.block_hashes[..self.block_hashes.len() - 1]
+            .iter()
+            .filter_map(|hash| self.chain.get(hash))
+            .map(BlockWrapper::from_block)
+            .collect();
+        let encoded = match &self.liabilities_proof {
+            Some(LiabilitiesProof::Compiled(proof)) => {
+                let pp = PP::from_circuit_setup(&self.get_liabilities_circuit_setup()?);
+                let mut wrapper = ProofOfLiabilitiesWrapper::new(proof.clone(), pp, header);
+                wrapper.set_chain(chain);
+                wrapper.encode(format)?
+            }
+            Some(LiabilitiesProof::Native(proof)) => {
+                let pp = self
+                    .get_native_setup(CircuitKind::LiabilitiesChangesFolding)?
+                    .liabilities_params()?;
+                let mut wrapper = ProofOfLiabilitiesWrapper::new(proof.clone(), pp, header);
+                wrapper.set_chain(chain);
+                wrapper.encode(format)?
+            }
+            None => return Ok(None),
+        };
+        Ok(Some(encoded))
+    }
+}
+
+impl StateSnapshot {
+    pub fn get_block_number(&self) -> i32 {
+        self.block_number
+    }
+
+    pub fn get_balance(&self, address: &str) -> i32 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+}
+
+impl BlockRange {
+    pub fn new(from: Option<i32>, to: Option<i32>) -> Result<BlockRange> {
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(BlockchainError::InvalidRequest(format!("Empty block range {}..={}", from, to)).into());
             }
         }
+        Ok(BlockRange { from, to })
+    }
+
+    /// The whole history, from the user's first leaf to the head.
+    pub fn all() -> BlockRange {
+        BlockRange::default()
+    }
+
+    pub fn get_from(&self) -> Option<i32> {
+        self.from
+    }
+
+    pub fn get_to(&self) -> Option<i32> {
+        self.to
+    }
+}
+
+impl InclusionSnapshot {
+    pub fn get_address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn get_range(&self) -> BlockRange {
+        self.range
+    }
+
+    pub fn is_changes_only(&self) -> bool {
+        self.changes_only
+    }
+
+    pub fn get_head_hash(&self) -> &str {
+        &self.head_hash
+    }
+
+    pub fn get_headers(&self) -> &[BlockWrapper] {
+        &self.headers
+    }
+
+    /// Folds the history without compressing it, for the node to keep. When
+    /// `previous` folds a prefix of these blocks only the blocks after it are
+    /// folded onto it.
+    pub fn prove_from(
+        &self,
+        circuit_setup: &CircuitSetup,
+        previous: Option<(InclusionFolding, Vec<BlockWrapper>)>,
+    ) -> Result<(InclusionFolding, Vec<BlockWrapper>, PP)> {
+        let pp = PP::from_circuit_setup(circuit_setup);
+        let previous = previous.filter(|(_, proven_headers)| {
+            !proven_headers.is_empty()
+                && proven_headers.len() <= self.headers.len()
+                && proven_headers.iter().zip(&self.headers).all(|(proven, header)| {
+                    proven.get_block_number() == header.get_block_number()
+                        && proven.get_root_hash() == header.get_root_hash()
+                })
+        });
+        let folding = match previous {
+            Some((folding, proven_headers)) => {
+                let start = proven_headers.len();
+                let mut inclusion_inputs = Vec::with_capacity(self.trees.len() - start);
+                for (tree, &index) in self.trees[start..].iter().zip(&self.indices[start..]) {
+                    inclusion_inputs.push(InclusionInput::new(tree, index)?);
+                }
+                if inclusion_inputs.is_empty() {
+                    folding
+                } else {
+                    folding.extend(inclusion_inputs, circuit_setup, &pp)?
+                }
+            }
+            None => InclusionFolding::new_with_backend(self.inclusion_inputs()?, circuit_setup, &pp, self.witness_backend)?,
+        };
+        Ok((folding, self.headers.clone(), pp))
+    }
+
+    pub fn prove(&self, circuit_setup: &CircuitSetup) -> Result<(ProofOfInclusion, Vec<BlockWrapper>, PP)> {
+        let (folding, headers, pp) = self.prove_from(circuit_setup, None)?;
+        Ok((folding.compress(&pp)?, headers, pp))
     }
 
-    pub fn get_liabilities_proof(&self) -> (Option<ProofOfLiabilities>, PP) {
-        let pp = PP::from_circuit_setup(&self.liabilities_circuit_setup);
-        (self.liabilities_proof.clone(), pp)
+    /// Like `prove`, with the native inclusion circuit.
+    pub fn prove_native(
+        &self,
+        circuit_setup: &NativeCircuitSetup,
+    ) -> Result<(ProofOfInclusion<InclusionCircuit>, Vec<BlockWrapper>, PP<InclusionCircuit>)> {
+        let (proof, client_pp) = ProofOfInclusion::new_native(self.inclusion_inputs()?, circuit_setup)?;
+        Ok((proof, self.headers.clone(), client_pp))
+    }
+
+    fn inclusion_inputs(&self) -> Result<Vec<InclusionInput>> {
+        let mut inclusion_inputs = Vec::with_capacity(self.trees.len());
+        for (tree, &index) in self.trees.iter().zip(&self.indices) {
+            inclusion_inputs.push(InclusionInput::new(tree, index)?);
+        }
+        Ok(inclusion_inputs)
     }
 }
 
@@ -313,6 +699,18 @@ mod tests {
         assert_eq!(bc.get_balance("alice"), 100);
     }
 
+    #[test]
+    fn test_state_snapshot() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        bc.add_transaction("", "alice", 100).unwrap();
+        let before = bc.get_state_snapshot();
+        bc.add_block().unwrap();
+        let after = bc.get_state_snapshot();
+        assert_eq!(before.get_balance("alice"), 0);
+        assert_eq!(after.get_balance("alice"), 100);
+        assert_eq!(after.get_block_number(), before.get_block_number() + 1);
+    }
+
     #[test]
     fn test_transaction_in_mempool() {
         let mut bc = Blockchain::create_blockchain().unwrap();
@@ -327,4 +725,108 @@ mod tests {
         // Initial tree should have 0 sum (all empty leaves)
         assert_eq!(tree.get_root_sum().unwrap(), 0);
     }
+
+    #[test]
+    fn test_unsupported_depth_fails() {
+        assert!(Blockchain::create_blockchain_with_depth(11).is_err());
+    }
+
+    #[test]
+    fn test_inclusion_snapshot() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        assert!(bc.get_inclusion_snapshot("alice", BlockRange::all(), false).is_none());
+        bc.add_transaction("", "alice", 100).unwrap();
+        bc.add_block().unwrap();
+        let snapshot = bc.get_inclusion_snapshot("alice", BlockRange::all(), false).unwrap();
+        assert_eq!(snapshot.get_head_hash(), bc.get_current_hash());
+        assert_eq!(snapshot.get_headers().len(), 1);
+    }
+
+    #[test]
+    fn test_inclusion_snapshot_range() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        for amount in [100, 20, 30] {
+            bc.add_transaction("", "alice", amount).unwrap();
+            bc.add_block().unwrap();
+        }
+        // alice's leaf changes at blocks 2, 3 and 4
+        let range = BlockRange::new(Some(3), Some(3)).unwrap();
+        let snapshot = bc.get_inclusion_snapshot("alice", range, false).unwrap();
+        let numbers: Vec<i32> = snapshot.get_headers().iter().map(|block| block.get_block_number()).collect();
+        assert_eq!(numbers, vec![3]);
+        let snapshot = bc.get_inclusion_snapshot("alice", BlockRange::new(Some(3), None).unwrap(), false).unwrap();
+        assert_eq!(snapshot.get_headers().len(), 2);
+        assert!(BlockRange::new(Some(4), Some(2)).is_err());
+    }
+
+    #[test]
+    fn test_leaf_change_index() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        bc.add_transaction("", "alice", 100).unwrap();
+        bc.add_block().unwrap();
+        bc.add_transaction("", "bob", 50).unwrap();
+        bc.add_block().unwrap();
+        bc.add_block().unwrap();
+        bc.add_transaction("alice", "bob", 10).unwrap();
+        bc.add_block().unwrap();
+        assert_eq!(bc.get_leaf_changes("alice"), &[2, 5]);
+        assert_eq!(bc.get_leaf_changes("bob"), &[3, 5]);
+
+        // bob joining at block 3 changed the root but not alice's leaf
+        let all = bc.get_inclusion_snapshot("alice", BlockRange::all(), false).unwrap();
+        assert_eq!(all.get_headers().len(), 3);
+        let changes = bc.get_inclusion_snapshot("alice", BlockRange::all(), true).unwrap();
+        let numbers: Vec<i32> = changes.get_headers().iter().map(|block| block.get_block_number()).collect();
+        assert_eq!(numbers, vec![2, 5]);
+        let until = BlockRange::new(None, Some(4)).unwrap();
+        let changes = bc.get_inclusion_snapshot("alice", until, true).unwrap();
+        let numbers: Vec<i32> = changes.get_headers().iter().map(|block| block.get_block_number()).collect();
+        assert_eq!(numbers, vec![2, 4]);
+    }
+
+    #[test]
+    fn test_leaves_are_salted() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        bc.add_transaction("", "alice", 100).unwrap();
+        bc.add_block().unwrap();
+        let leaf_id = bc.get_leaf_id("alice").unwrap();
+        assert_ne!(leaf_id, "alice");
+        assert_eq!(bc.get_merkle_sum_tree().get_leaf(0).unwrap().get_id(), leaf_id.as_str());
+        assert_eq!(bc.register("alice"), bc.get_salt("alice").unwrap());
+        // bob's leaf ID is known before bob has a leaf
+        let prospective = bc.derive_leaf_id("bob");
+        assert!(bc.get_leaf_id("bob").is_none());
+        bc.add_transaction("", "bob", 5).unwrap();
+        bc.add_block().unwrap();
+        assert_eq!(bc.get_leaf_id("bob").unwrap(), prospective);
+    }
+
+    #[test]
+    fn test_new_leaves_are_appended() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        bc.add_transaction("", "carol", 10).unwrap();
+        bc.add_block().unwrap();
+        for address in ["alice", "bob"] {
+            bc.add_transaction("", address, 10).unwrap();
+        }
+        bc.add_block().unwrap();
+        // The leaf that was there first keeps its slot
+        assert_eq!(bc.get_leaf_index("carol"), Some(0));
+        for address in ["carol", "alice", "bob"] {
+            let index = bc.get_leaf_index(address).unwrap();
+            assert_eq!(
+                bc.get_merkle_sum_tree().get_leaf(index).unwrap().get_id(),
+                bc.get_leaf_id(address).unwrap().as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_block_by_number() {
+        let mut bc = Blockchain::create_blockchain().unwrap();
+        bc.add_block().unwrap();
+        bc.add_block().unwrap();
+        assert_eq!(bc.get_block(2).unwrap().get_block_number(), 2);
+        assert!(bc.get_block(4).is_none());
+    }
 }
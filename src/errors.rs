@@ -26,6 +26,33 @@ pub enum BlockchainError {
     
     #[error("Circuit setup error: {0}")]
     CircuitSetup(String),
+
+    #[error("Invalid proof encoding: {0}")]
+    ProofFormat(String),
+
+    #[error("Reserves statement rejected: {0}")]
+    ReservesStatement(String),
+
+    #[error("Unsupported proof format version {found}, this build reads version {supported}")]
+    UnsupportedFormatVersion { found: u16, supported: u16 },
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Framing error: {0}")]
+    Framing(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
 }
 
 // Keep backward compatibility during migration
@@ -1,4 +1,6 @@
+#[cfg(feature = "node")]
 pub mod blockchain;
 pub mod errors;
 pub mod proofs;
-pub mod stream;
\ No newline at end of file
+#[cfg(feature = "node")]
+pub mod stream;
@@ -1,4 +1,23 @@
+#[cfg(feature = "verifier")]
+pub mod batch;
+pub mod bundle;
+pub mod encoding;
+#[cfg(feature = "verifier")]
+pub mod gadgets;
 pub mod inclusion;
 pub mod liabilities;
+pub mod manifest;
+pub mod mimc;
+#[cfg(feature = "verifier")]
+pub mod native;
+pub mod registry;
 pub mod setup;
+#[cfg(feature = "verifier")]
+pub mod non_membership;
+#[cfg(feature = "verifier")]
+pub mod solvency;
+#[cfg(feature = "verifier")]
+pub mod threshold;
 pub mod util;
+#[cfg(feature = "node")]
+pub mod witness;
@@ -0,0 +1,171 @@
+use crate::errors::BlockchainError;
+use crate::proofs::bundle::BlockWrapper;
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::inclusion::InclusionClaim;
+use crate::proofs::manifest::CircuitId;
+use crate::proofs::native::{BatchInclusionCircuit, NativeCircuitSetup};
+use crate::proofs::registry::CircuitKind;
+use crate::proofs::setup::{verify_compressed, CompressedProof};
+use crate::proofs::util::field_from_hex;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[cfg(feature = "node")]
+use crate::proofs::inclusion::InclusionInput;
+#[cfg(feature = "node")]
+use crate::proofs::setup::{compress, fold_steps};
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Inclusion of several leaves in the tree of one block, folded into a
+/// single proof. Every claim is bound through the accumulator, not only the last.
+/// The folded proof is compressed so that the sibling paths are not sent along.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProofOfBatchInclusion {
+    compressed_snark: CompressedProof<BatchInclusionCircuit>,
+    circuit_id: CircuitId,
+    header: BlockWrapper,
+    claims: Vec<InclusionClaim>,
+}
+
+impl ProofOfBatchInclusion {
+    #[cfg(feature = "node")]
+    /// Every input must be taken from the tree of `header`.
+    pub fn new(
+        inclusion_inputs: &[InclusionInput],
+        header: BlockWrapper,
+        circuit_setup: &NativeCircuitSetup,
+    ) -> Result<ProofOfBatchInclusion> {
+        if circuit_setup.get_kind() != CircuitKind::BatchInclusion {
+            return Err(failure::format_err!(
+                "Expected a batch inclusion setup, got {}",
+                circuit_setup.get_kind().name()
+            ));
+        }
+        if inclusion_inputs.is_empty() {
+            return Err(BlockchainError::ProofCreation("A batch needs at least one leaf".to_string()).into());
+        }
+        if let Some(input) = inclusion_inputs
+            .iter()
+            .find(|input| input.get_root_hash() != header.get_root_hash())
+        {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Inclusion input opens root {} but block {} has root {}",
+                input.get_root_hash(),
+                header.get_block_number(),
+                header.get_root_hash()
+            ))
+            .into());
+        }
+        let pp = circuit_setup.batch_inclusion_params()?;
+        let start_proof = Instant::now();
+        let circuits: Vec<BatchInclusionCircuit> = inclusion_inputs
+            .iter()
+            .map(|input| BatchInclusionCircuit::new(circuit_setup.get_depth(), input.to_native_step()))
+            .collect();
+        let recursive_snark = fold_steps(pp.get_pp(), &circuits, start_public_input(&header).to_vec())?;
+        let compressed_snark = compress(&pp, &recursive_snark)?;
+        println!("CompressedSNARK::proof took {:?}", start_proof.elapsed());
+
+        Ok(ProofOfBatchInclusion {
+            compressed_snark,
+            circuit_id: pp.get_circuit_id().clone(),
+            header,
+            claims: inclusion_inputs.iter().map(InclusionInput::get_claim).collect(),
+        })
+    }
+
+    /// Rebuilds the parameters for the proof's depth and checks every claim
+    /// against the root of `header`, the trusted block the proof must be for.
+    pub fn verify(&self, header: &BlockWrapper) -> Result<&Vec<InclusionClaim>> {
+        if self.header.get_block_number() != header.get_block_number()
+            || self.header.get_root_hash() != header.get_root_hash()
+            || self.header.get_root_sum() != header.get_root_sum()
+        {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof is for root {} of block {}, the trusted block {} has root {}",
+                    self.header.get_root_hash(),
+                    self.header.get_block_number(),
+                    header.get_block_number(),
+                    header.get_root_hash()
+                ),
+            }
+            .into());
+        }
+        let start = Instant::now();
+        let circuit_setup = NativeCircuitSetup::new(CircuitKind::BatchInclusion, self.circuit_id.get_depth(), 1)?;
+        // The identity of locally generated parameters carries their digest
+        let pp = circuit_setup.batch_inclusion_params()?;
+        if &self.circuit_id != pp.get_circuit_id() {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof was made with batch inclusion circuit {}, this build has {}",
+                    self.circuit_id,
+                    pp.get_circuit_id()
+                ),
+            }
+            .into());
+        }
+        if self.claims.is_empty() {
+            return Err(BlockchainError::ProofVerification {
+                reason: "Batch proof carries no claims".to_string(),
+            }
+            .into());
+        }
+        if self.claims.iter().any(|claim| {
+            claim.get_root_hash() != header.get_root_hash() || claim.get_root_sum() != header.get_root_sum()
+        }) {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!("Claims are not all under root {}", header.get_root_hash()),
+            }
+            .into());
+        }
+        let z0 = start_public_input(header);
+        let zn = verify_compressed(&pp, &self.compressed_snark, self.claims.len(), &z0).map_err(|e| {
+            BlockchainError::ProofVerification {
+                reason: format!("Batch inclusion proof rejected: {}", e),
+            }
+        })?;
+
+        let acc = self.claims.iter().fold(Fq::from(0), |acc, claim| {
+            BatchInclusionCircuit::chain(acc, field_from_hex(claim.get_user_hash()), claim.get_user_balance() as u64)
+        });
+        if zn != [z0[0], z0[1], acc] {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof output does not match the claimed leaves under root {}",
+                    header.get_root_hash()
+                ),
+            }
+            .into());
+        }
+        println!("Batch inclusion proof verified successfully in {:?}", start.elapsed());
+        Ok(&self.claims)
+    }
+
+    pub fn get_header(&self) -> &BlockWrapper {
+        &self.header
+    }
+
+    pub fn get_claims(&self) -> &Vec<InclusionClaim> {
+        &self.claims
+    }
+}
+
+fn start_public_input(header: &BlockWrapper) -> [Fq; 3] {
+    [
+        field_from_hex(header.get_root_hash()),
+        Fq::from(header.get_root_sum() as u64),
+        Fq::from(0),
+    ]
+}
+
+impl ProofEncoding for ProofOfBatchInclusion {
+    const KIND: PayloadKind = PayloadKind::BatchInclusionProof;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+}
@@ -0,0 +1,415 @@
+#[cfg(feature = "node")]
+use crate::blockchain::block::Block;
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::encoding::{PayloadKind, ProofEncoding, ProofFormat, PROOF_MAGIC};
+use crate::proofs::inclusion::ProofOfInclusion;
+use crate::proofs::liabilities::{LiabilitiesOutput, ProofOfLiabilities};
+use crate::proofs::manifest::{CircuitId, CircuitManifest};
+use crate::proofs::setup::{DefaultCircuit, PP};
+use crate::proofs::util::block_hash;
+use nova_snark::traits::circuit::StepCircuit;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockInclusion {
+    user_hash: String,
+    user_balance: i32,
+    root_hash: String,
+    root_sum: i32,
+    block_number: i32,
+    timestamp: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockWrapper {
+    root_hash: String,
+    root_sum: i32,
+    block_number: i32,
+    timestamp: String,
+    // empty for headers not taken from a block, which link to no other
+    prev_hash: String,
+    transactions_hash: String,
+}
+
+/// The circuit type only matters while proving: proofs and parameters of the
+/// native circuits encode like circom ones, so clients decode the default type
+/// and tell them apart by circuit ID.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ProofOfInclusionWrapper<C: StepCircuit<Fq> = DefaultCircuit> {
+    proof: ProofOfInclusion<C>,
+    wrap_blocks: Vec<BlockWrapper>,
+    pp: PP<C>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ProofOfLiabilitiesWrapper<C: StepCircuit<Fq> = DefaultCircuit> {
+    proof: ProofOfLiabilities<C>,
+    pp: PP<C>,
+    // head block when the proof was served
+    header: Option<BlockWrapper>,
+    // the blocks before it, oldest first, each linked to the next by its hash
+    chain: Vec<BlockWrapper>,
+}
+
+impl BlockWrapper {
+    pub fn new(root_hash: String, root_sum: i32, block_number: i32, timestamp: String) -> BlockWrapper {
+        BlockWrapper {
+            root_hash,
+            root_sum,
+            block_number,
+            timestamp,
+            prev_hash: String::new(),
+            transactions_hash: String::new(),
+        }
+    }
+
+    #[cfg(feature = "node")]
+    pub fn from_block(block: &Block) -> BlockWrapper {
+        BlockWrapper {
+            root_hash: block
+                .get_merkle_sum_tree()
+                .get_root_hash()
+                .unwrap()
+                .to_string(),
+            root_sum: block.get_merkle_sum_tree().get_root_sum().unwrap(),
+            block_number: block.get_block_number(),
+            timestamp: block.get_timestamp().to_string(),
+            prev_hash: block.get_previous_hash().to_string(),
+            transactions_hash: block.get_transactions_hash(),
+        }
+    }
+
+    /// The hash of the block, recomputed from the header.
+    pub fn get_hash(&self) -> String {
+        block_hash(
+            self.block_number,
+            &self.prev_hash,
+            &self.timestamp,
+            &self.transactions_hash,
+            &self.root_hash,
+            self.root_sum,
+        )
+    }
+
+    /// Whether this is the block right after `previous`.
+    pub fn follows(&self, previous: &BlockWrapper) -> bool {
+        self.block_number == previous.block_number + 1 && !self.prev_hash.is_empty() && self.prev_hash == previous.get_hash()
+    }
+
+    pub fn get_root_hash(&self) -> &str {
+        &self.root_hash
+    }
+
+    pub fn get_root_sum(&self) -> i32 {
+        self.root_sum
+    }
+
+    pub fn get_block_number(&self) -> i32 {
+        self.block_number
+    }
+
+    pub fn get_timestamp(&self) -> &str {
+        &self.timestamp
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofOfInclusionWrapper<C> {
+    pub fn new(proof: ProofOfInclusion<C>, wrap_blocks: Vec<BlockWrapper>, pp: PP<C>) -> ProofOfInclusionWrapper<C> {
+        ProofOfInclusionWrapper {
+            proof,
+            wrap_blocks,
+            pp,
+        }
+    }
+
+    pub fn get_proof(&self) -> &ProofOfInclusion<C> {
+        &self.proof
+    }
+
+    pub fn into_parts(self) -> (ProofOfInclusion<C>, Vec<BlockWrapper>, PP<C>) {
+        (self.proof, self.wrap_blocks, self.pp)
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofEncoding for ProofOfInclusionWrapper<C> {
+    const KIND: PayloadKind = PayloadKind::InclusionBundle;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        self.proof.get_circuit_id()
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofOfLiabilitiesWrapper<C> {
+    pub fn new(proof: ProofOfLiabilities<C>, pp: PP<C>, header: Option<BlockWrapper>) -> ProofOfLiabilitiesWrapper<C> {
+        ProofOfLiabilitiesWrapper {
+            proof,
+            pp,
+            header,
+            chain: Vec::new(),
+        }
+    }
+
+    /// Sets the headers of the blocks before the head, oldest first, so that
+    /// older blocks can be checked against the proven head.
+    pub fn set_chain(&mut self, chain: Vec<BlockWrapper>) {
+        self.chain = chain;
+    }
+
+    pub fn get_proof(&self) -> ProofOfLiabilities<C> {
+        self.proof.clone()
+    }
+
+    pub fn get_pp(self) -> PP<C> {
+        self.pp
+    }
+
+    pub fn into_parts(self) -> (ProofOfLiabilities<C>, Vec<BlockWrapper>, PP<C>) {
+        let mut headers = self.chain;
+        headers.extend(self.header);
+        (self.proof, headers, self.pp)
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofEncoding for ProofOfLiabilitiesWrapper<C> {
+    const KIND: PayloadKind = PayloadKind::LiabilitiesBundle;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        self.proof.get_circuit_id()
+    }
+}
+
+impl BlockInclusion {
+    pub fn new(
+        user_hash: String,
+        user_balance: i32,
+        root_hash: String,
+        root_sum: i32,
+        block_number: i32,
+        timestamp: String,
+    ) -> BlockInclusion {
+        BlockInclusion {
+            user_hash,
+            user_balance,
+            root_hash,
+            root_sum,
+            block_number,
+            timestamp,
+        }
+    }
+    
+    pub fn user_hash(&self) -> &str {
+        &self.user_hash
+    }
+
+    pub fn user_balance(&self) -> i32 {
+        self.user_balance
+    }
+    
+    pub fn root_sum(&self) -> i32 {
+        self.root_sum
+    }
+    
+    pub fn block_number(&self) -> i32 {
+        self.block_number
+    }
+    
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum BundledProof {
+    Liabilities(ProofOfLiabilities),
+    Inclusion(ProofOfInclusion),
+}
+
+/// A proof exported for offline auditing: the proof, the block headers it
+/// refers to, and the public parameters together with their digest.
+#[derive(Serialize, Deserialize)]
+pub struct ProofBundle {
+    proof: BundledProof,
+    headers: Vec<BlockWrapper>,
+    pp: PP,
+    pp_digest: String,
+}
+
+/// What a bundle was verified to prove.
+#[derive(Debug)]
+pub enum BundleReport {
+    Liabilities(LiabilitiesOutput),
+    Inclusion(Vec<BlockInclusion>),
+}
+
+impl ProofBundle {
+    pub fn from_liabilities(wrapper: ProofOfLiabilitiesWrapper) -> Result<ProofBundle> {
+        let (proof, headers, pp) = wrapper.into_parts();
+        Ok(ProofBundle {
+            proof: BundledProof::Liabilities(proof),
+            headers,
+            pp_digest: pp.params_digest()?,
+            pp,
+        })
+    }
+
+    pub fn from_inclusion(wrapper: ProofOfInclusionWrapper) -> Result<ProofBundle> {
+        let (proof, headers, pp) = wrapper.into_parts();
+        Ok(ProofBundle {
+            proof: BundledProof::Inclusion(proof),
+            headers,
+            pp_digest: pp.params_digest()?,
+            pp,
+        })
+    }
+
+    pub fn get_headers(&self) -> &Vec<BlockWrapper> {
+        &self.headers
+    }
+
+    /// The header of `block_number`. Only trusted once the bundle is verified.
+    pub fn get_header(&self, block_number: i32) -> Option<&BlockWrapper> {
+        self.headers.iter().find(|header| header.get_block_number() == block_number)
+    }
+
+    pub fn get_pp_digest(&self) -> &str {
+        &self.pp_digest
+    }
+
+    pub fn write(&self, path: &Path, format: ProofFormat) -> Result<()> {
+        let data = match format {
+            ProofFormat::Binary => self.to_bytes()?,
+            ProofFormat::Json => self.to_json()?.into_bytes(),
+        };
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Reads a bundle written by `write` in either format.
+    pub fn read(path: &Path) -> Result<ProofBundle> {
+        let data = fs::read(path)?;
+        if data.starts_with(&PROOF_MAGIC) {
+            ProofBundle::from_bytes(&data)
+        } else {
+            ProofBundle::from_json(std::str::from_utf8(&data)?)
+        }
+    }
+
+    /// Verifies against the manifest under `circuits/compile`.
+    pub fn verify(self) -> Result<BundleReport> {
+        let manifest = CircuitManifest::load_default()?;
+        self.verify_with_manifest(&manifest)
+    }
+
+    /// Checks that the parameters hash to the digest the manifest trusts for
+    /// their build and that the bundle lists that digest, the proof itself,
+    /// then that the proven roots are the ones in the bundled headers, oldest first.
+    pub fn verify_with_manifest(self, manifest: &CircuitManifest) -> Result<BundleReport> {
+        let circuit_id = manifest.check_params(&self.pp)?;
+        let trusted = manifest.trusted_params_digest(&circuit_id)?;
+        if trusted != self.pp_digest {
+            return Err(verification_error(format!(
+                "Public parameter digest mismatch: bundle lists {}, manifest trusts {}",
+                self.pp_digest, trusted
+            )));
+        }
+        if &circuit_id != self.get_circuit_id() {
+            return Err(verification_error(format!(
+                "Proof was made with {} but the parameters are for {}",
+                self.get_circuit_id(),
+                circuit_id
+            )));
+        }
+
+        match self.proof {
+            BundledProof::Liabilities(proof) => {
+                let output = proof.verify(self.pp)?;
+                let header = self
+                    .headers
+                    .last()
+                    .ok_or_else(|| verification_error("Liabilities bundle has no block header".to_string()))?;
+                if header.get_root_hash() != proof.get_final_root_hash()
+                    || header.get_root_sum() != proof.get_final_root_sum()
+                {
+                    return Err(verification_error(format!(
+                        "Proof ends at root {} but block {} has root {}",
+                        proof.get_final_root_hash(),
+                        header.get_block_number(),
+                        header.get_root_hash()
+                    )));
+                }
+                // Earlier headers are only believed through the hashes linking them to the proven one
+                if let Some(pair) = self.headers.windows(2).find(|pair| !pair[1].follows(&pair[0])) {
+                    return Err(verification_error(format!(
+                        "Block {} does not link to block {}",
+                        pair[1].get_block_number(),
+                        pair[0].get_block_number()
+                    )));
+                }
+                Ok(BundleReport::Liabilities(output))
+            }
+            BundledProof::Inclusion(proof) => {
+                proof.verify(self.pp)?;
+                let claims = proof.get_claims();
+                if claims.len() != self.headers.len() {
+                    return Err(verification_error(format!(
+                        "Proof covers {} trees but the bundle has {} headers",
+                        claims.len(),
+                        self.headers.len()
+                    )));
+                }
+                // The accumulator fixes the order of the roots, the headers must follow the chain's
+                if let Some(pair) = self
+                    .headers
+                    .windows(2)
+                    .find(|pair| pair[0].get_block_number() >= pair[1].get_block_number())
+                {
+                    return Err(verification_error(format!(
+                        "Block {} is listed before block {}",
+                        pair[0].get_block_number(),
+                        pair[1].get_block_number()
+                    )));
+                }
+                let mut history = Vec::with_capacity(self.headers.len());
+                for (inclusion, header) in claims.iter().zip(self.headers.iter()) {
+                    if inclusion.get_root_hash() != header.get_root_hash() {
+                        return Err(verification_error(format!(
+                            "Block {} has root {} but the proof opens root {}",
+                            header.get_block_number(),
+                            header.get_root_hash(),
+                            inclusion.get_root_hash()
+                        )));
+                    }
+                    history.push(BlockInclusion::new(
+                        inclusion.get_user_hash().to_string(),
+                        inclusion.get_user_balance(),
+                        inclusion.get_root_hash().to_string(),
+                        inclusion.get_root_sum(),
+                        header.get_block_number(),
+                        header.get_timestamp().to_string(),
+                    ));
+                }
+                Ok(BundleReport::Inclusion(history))
+            }
+        }
+    }
+}
+
+impl ProofEncoding for ProofBundle {
+    const KIND: PayloadKind = PayloadKind::ProofBundle;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        match &self.proof {
+            BundledProof::Liabilities(proof) => proof.get_circuit_id(),
+            BundledProof::Inclusion(proof) => proof.get_circuit_id(),
+        }
+    }
+}
+
+fn verification_error(reason: String) -> failure::Error {
+    BlockchainError::ProofVerification { reason }.into()
+}
@@ -0,0 +1,259 @@
+use crate::errors::BlockchainError;
+use crate::proofs::manifest::CircuitId;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const PROOF_MAGIC: [u8; 4] = *b"BSOL";
+pub const FORMAT_VERSION: u16 = 2;
+const HEADER_LEN: usize = 6;
+/// Largest envelope body or payload read or written, well above the parameters
+/// of the deepest supported circuit. Bounds what a forged length can allocate.
+pub const MAX_ENVELOPE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// What an envelope carries, so a parameter file cannot be read as a proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayloadKind {
+    LiabilitiesProof,
+    InclusionProof,
+    PublicParams,
+    LiabilitiesBundle,
+    InclusionBundle,
+    ProofBundle,
+    SolvencyReport,
+    ThresholdProof,
+    NonMembershipProof,
+    BatchInclusionProof,
+}
+
+/// How proofs are written for clients: compact binary envelopes, or JSON for debugging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProofFormat {
+    #[default]
+    Binary,
+    Json,
+}
+
+/// Everything after the magic and version. Layout:
+/// `magic (4 bytes) | version (u16 LE) | bincode(EnvelopeBody)`.
+#[derive(Debug, Serialize, Deserialize)]
+struct EnvelopeBody {
+    kind: PayloadKind,
+    circuit_id: CircuitId,
+    payload: Vec<u8>,
+}
+
+/// The identifying part of an envelope, readable without decoding the payload.
+#[derive(Debug, Clone)]
+pub struct EnvelopeHeader {
+    version: u16,
+    kind: PayloadKind,
+    circuit_id: CircuitId,
+}
+
+impl ProofFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProofFormat::Binary => "binary",
+            ProofFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ProofFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<ProofFormat> {
+        match s {
+            "binary" => Ok(ProofFormat::Binary),
+            "json" => Ok(ProofFormat::Json),
+            _ => Err(failure::format_err!(
+                "Unknown proof format '{}', expected binary or json",
+                s
+            )),
+        }
+    }
+}
+
+impl EnvelopeHeader {
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn get_kind(&self) -> PayloadKind {
+        self.kind
+    }
+
+    pub fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+}
+
+/// Proof artifacts that can be written as versioned binary envelopes or as JSON.
+pub trait ProofEncoding: Serialize + DeserializeOwned {
+    const KIND: PayloadKind;
+
+    fn get_circuit_id(&self) -> &CircuitId;
+
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let body = EnvelopeBody {
+            kind: Self::KIND,
+            circuit_id: self.get_circuit_id().clone(),
+            payload: bincode_options().serialize(self)?,
+        };
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.payload.len());
+        bytes.extend_from_slice(&PROOF_MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend(bincode_options().serialize(&body)?);
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let body = read_body(bytes)?;
+        if body.kind != Self::KIND {
+            return Err(BlockchainError::ProofFormat(format!(
+                "Expected {:?}, found {:?}",
+                Self::KIND,
+                body.kind
+            ))
+            .into());
+        }
+        let value: Self = bincode_options().deserialize(&body.payload)?;
+        if value.get_circuit_id() != &body.circuit_id {
+            return Err(BlockchainError::ProofFormat(format!(
+                "Envelope is labelled {} but its payload was made with {}",
+                body.circuit_id,
+                value.get_circuit_id()
+            ))
+            .into());
+        }
+        Ok(value)
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    fn from_json(data: &str) -> Result<Self> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    /// Writes the value as text: hex-encoded envelope or JSON.
+    fn encode(&self, format: ProofFormat) -> Result<String> {
+        match format {
+            ProofFormat::Binary => Ok(hex::encode(self.to_bytes()?)),
+            ProofFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Reads either output of `encode`.
+    fn decode(data: &str) -> Result<Self> {
+        let data = data.trim();
+        if data.starts_with('{') {
+            Self::from_json(data)
+        } else {
+            let bytes = hex::decode(data)
+                .map_err(|e| BlockchainError::ProofFormat(format!("Invalid hex: {}", e)))?;
+            Self::from_bytes(&bytes)
+        }
+    }
+}
+
+/// The one bincode configuration for envelopes, used to write and to read them.
+/// Fixed-width integers keep the layout of version 2 envelopes.
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_ENVELOPE_SIZE)
+}
+
+fn read_body(bytes: &[u8]) -> Result<EnvelopeBody> {
+    if bytes.len() < HEADER_LEN || bytes[..4] != PROOF_MAGIC {
+        return Err(BlockchainError::ProofFormat("Not a proof envelope".to_string()).into());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(BlockchainError::UnsupportedFormatVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        }
+        .into());
+    }
+    Ok(bincode_options().deserialize(&bytes[HEADER_LEN..])?)
+}
+
+/// Reads the version, kind and circuit of an envelope without decoding its payload.
+pub fn read_header(bytes: &[u8]) -> Result<EnvelopeHeader> {
+    let body = read_body(bytes)?;
+    Ok(EnvelopeHeader {
+        version: FORMAT_VERSION,
+        kind: body.kind,
+        circuit_id: body.circuit_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::registry::CircuitKind;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        circuit_id: CircuitId,
+        values: Vec<u64>,
+    }
+
+    impl ProofEncoding for Sample {
+        const KIND: PayloadKind = PayloadKind::InclusionProof;
+
+        fn get_circuit_id(&self) -> &CircuitId {
+            &self.circuit_id
+        }
+    }
+
+    fn sample() -> Sample {
+        Sample {
+            circuit_id: CircuitId::native(CircuitKind::Inclusion, 2, 1),
+            values: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let bytes = sample().to_bytes().unwrap();
+        assert_eq!(&bytes[..4], &PROOF_MAGIC);
+        assert_eq!(Sample::from_bytes(&bytes).unwrap(), sample());
+        let header = read_header(&bytes).unwrap();
+        assert_eq!(header.get_kind(), PayloadKind::InclusionProof);
+
+        for format in [ProofFormat::Binary, ProofFormat::Json] {
+            let text = sample().encode(format).unwrap();
+            assert_eq!(Sample::decode(&text).unwrap(), sample());
+        }
+    }
+
+    #[test]
+    fn test_version_mismatch_is_reported() {
+        let mut bytes = sample().to_bytes().unwrap();
+        bytes[4] = 9;
+        let error = Sample::from_bytes(&bytes).unwrap_err();
+        assert!(error.to_string().contains("version 9"));
+
+        bytes[0] = b'X';
+        assert!(Sample::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_oversized_length_is_rejected() {
+        let mut bytes = PROOF_MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        // kind, then a circuit name claiming to be longer than any envelope
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&(MAX_ENVELOPE_SIZE + 1).to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 64]);
+        assert!(read_header(&bytes).is_err());
+    }
+}
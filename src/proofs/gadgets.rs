@@ -0,0 +1,232 @@
+use crate::proofs::mimc::{mimc_constants, MIMC_ROUNDS};
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use pasta_curves::Fq;
+
+/// Bits a balance may use, as in the circom `SafeRangeCheck` of the circuits.
+pub const BALANCE_BITS: usize = 100;
+
+// Bellpepper versions of the templates in `circuits/`.
+
+fn alloc<CS: ConstraintSystem<Fq>>(
+    cs: CS,
+    value: Option<Fq>,
+) -> Result<AllocatedNum<Fq>, SynthesisError> {
+    AllocatedNum::alloc(cs, || value.ok_or(SynthesisError::AssignmentMissing))
+}
+
+fn value2(
+    a: &AllocatedNum<Fq>,
+    b: &AllocatedNum<Fq>,
+    f: impl Fn(Fq, Fq) -> Fq,
+) -> Option<Fq> {
+    Some(f(a.get_value()?, b.get_value()?))
+}
+
+/// Allocates `a + b`.
+pub fn add<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    a: &AllocatedNum<Fq>,
+    b: &AllocatedNum<Fq>,
+) -> Result<AllocatedNum<Fq>, SynthesisError> {
+    let sum = alloc(cs.namespace(|| "sum"), value2(a, b, |a, b| a + b))?;
+    cs.enforce(
+        || "sum = a + b",
+        |lc| lc + a.get_variable() + b.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + sum.get_variable(),
+    );
+    Ok(sum)
+}
+
+/// Allocates a constant, fixed by a constraint.
+pub fn constant<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    value: Fq,
+) -> Result<AllocatedNum<Fq>, SynthesisError> {
+    let num = AllocatedNum::alloc(cs.namespace(|| "value"), || Ok(value))?;
+    cs.enforce(
+        || "num = constant",
+        |lc| lc + num.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (value, CS::one()),
+    );
+    Ok(num)
+}
+
+pub fn enforce_equal<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    a: &AllocatedNum<Fq>,
+    b: &AllocatedNum<Fq>,
+) {
+    cs.enforce(
+        || "a = b",
+        |lc| lc + a.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + b.get_variable(),
+    );
+}
+
+/// circomlib `MiMCFeistel(220)` with `k = 0`.
+fn mimc_feistel<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    xl_in: &AllocatedNum<Fq>,
+    xr_in: &AllocatedNum<Fq>,
+) -> Result<(AllocatedNum<Fq>, AllocatedNum<Fq>), SynthesisError> {
+    let mut xl = xl_in.clone();
+    let mut xr = xr_in.clone();
+    for (i, constant) in mimc_constants().iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("round {}", i));
+        // t = xl + c stays a linear combination
+        let t_value = xl.get_value().map(|xl| xl + constant);
+        let t2 = alloc(cs.namespace(|| "t2"), t_value.map(|t| t * t))?;
+        cs.enforce(
+            || "t2 = t * t",
+            |lc| lc + xl.get_variable() + (*constant, CS::one()),
+            |lc| lc + xl.get_variable() + (*constant, CS::one()),
+            |lc| lc + t2.get_variable(),
+        );
+        let t4 = t2.square(cs.namespace(|| "t4"))?;
+        let next_value = match (xr.get_value(), t4.get_value(), t_value) {
+            (Some(xr), Some(t4), Some(t)) => Some(xr + t4 * t),
+            _ => None,
+        };
+        let next = alloc(cs.namespace(|| "next"), next_value)?;
+        cs.enforce(
+            || "next = xr + t4 * t",
+            |lc| lc + t4.get_variable(),
+            |lc| lc + xl.get_variable() + (*constant, CS::one()),
+            |lc| lc + next.get_variable() - xr.get_variable(),
+        );
+        if i < MIMC_ROUNDS - 1 {
+            xr = xl;
+            xl = next;
+        } else {
+            xr = next;
+        }
+    }
+    Ok((xl, xr))
+}
+
+/// circomlib `MiMCSponge(ins.len(), 220, 1)` with `k = 0`.
+pub fn mimc_sponge<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    ins: &[AllocatedNum<Fq>],
+) -> Result<AllocatedNum<Fq>, SynthesisError> {
+    let mut xl = constant(cs.namespace(|| "xl"), Fq::from(0))?;
+    let mut xr = constant(cs.namespace(|| "xr"), Fq::from(0))?;
+    for (i, input) in ins.iter().enumerate() {
+        let xl_in = add(cs.namespace(|| format!("absorb {}", i)), &xl, input)?;
+        (xl, xr) = mimc_feistel(cs.namespace(|| format!("feistel {}", i)), &xl_in, &xr)?;
+    }
+    Ok(xl)
+}
+
+/// `MerkleSum`, returns `(root, sum)`.
+pub fn merkle_sum<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    l: &AllocatedNum<Fq>,
+    r: &AllocatedNum<Fq>,
+    sum_l: &AllocatedNum<Fq>,
+    sum_r: &AllocatedNum<Fq>,
+) -> Result<(AllocatedNum<Fq>, AllocatedNum<Fq>), SynthesisError> {
+    let root = mimc_sponge(
+        cs.namespace(|| "hasher"),
+        &[l.clone(), sum_l.clone(), r.clone(), sum_r.clone()],
+    )?;
+    let sum = add(cs.namespace(|| "sum"), sum_l, sum_r)?;
+    Ok((root, sum))
+}
+
+/// `Switcher`, returns `(outL, outR)`. Unlike the circom template `sel` is also
+/// constrained to be a bit.
+pub fn switcher<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    sel: &AllocatedNum<Fq>,
+    l: &AllocatedNum<Fq>,
+    r: &AllocatedNum<Fq>,
+) -> Result<(AllocatedNum<Fq>, AllocatedNum<Fq>), SynthesisError> {
+    cs.enforce(
+        || "sel is a bit",
+        |lc| lc + sel.get_variable(),
+        |lc| lc + CS::one() - sel.get_variable(),
+        |lc| lc,
+    );
+    let aux_value = match (l.get_value(), r.get_value(), sel.get_value()) {
+        (Some(l), Some(r), Some(sel)) => Some((r - l) * sel),
+        _ => None,
+    };
+    let aux = alloc(cs.namespace(|| "aux"), aux_value)?;
+    cs.enforce(
+        || "aux = (R - L) * sel",
+        |lc| lc + r.get_variable() - l.get_variable(),
+        |lc| lc + sel.get_variable(),
+        |lc| lc + aux.get_variable(),
+    );
+    let out_l = add(cs.namespace(|| "outL"), &aux, l)?;
+    let out_r = alloc(cs.namespace(|| "outR"), value2(r, &aux, |r, aux| r - aux))?;
+    cs.enforce(
+        || "outR = R - aux",
+        |lc| lc + r.get_variable() - aux.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + out_r.get_variable(),
+    );
+    Ok((out_l, out_r))
+}
+
+/// `SafeRangeCheck(bits)`: `value` fits in `bits` bits.
+pub fn range_check<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    value: &AllocatedNum<Fq>,
+    raw_value: Option<u64>,
+    bits: usize,
+) -> Result<(), SynthesisError> {
+    let mut coefficient = Fq::from(1);
+    let mut packed = bellpepper_core::LinearCombination::zero();
+    for i in 0..bits {
+        let bit_value = raw_value.map(|v| if i < 64 { Fq::from((v >> i) & 1) } else { Fq::from(0) });
+        let bit = alloc(cs.namespace(|| format!("bit {}", i)), bit_value)?;
+        cs.enforce(
+            || format!("bit {} is boolean", i),
+            |lc| lc + bit.get_variable(),
+            |lc| lc + CS::one() - bit.get_variable(),
+            |lc| lc,
+        );
+        packed = packed + (coefficient, bit.get_variable());
+        coefficient = coefficient.double();
+    }
+    cs.enforce(
+        || "bits pack to value",
+        |_| packed,
+        |lc| lc + CS::one(),
+        |lc| lc + value.get_variable(),
+    );
+    Ok(())
+}
+
+#[cfg(all(test, feature = "node"))]
+mod tests {
+    use super::*;
+    use crate::proofs::witness::{self, Signals};
+    use bellpepper_core::test_cs::TestConstraintSystem;
+
+    #[test]
+    fn test_merkle_sum_matches_witness_model() {
+        let mut cs = TestConstraintSystem::<Fq>::new();
+        let values = [Fq::from(11), Fq::from(22), Fq::from(5), Fq::from(7)];
+        let nums: Vec<AllocatedNum<Fq>> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| AllocatedNum::alloc(cs.namespace(|| format!("in {}", i)), || Ok(*v)).unwrap())
+            .collect();
+        let (root, sum) =
+            merkle_sum(cs.namespace(|| "merkle"), &nums[0], &nums[1], &nums[2], &nums[3]).unwrap();
+        assert!(cs.is_satisfied());
+
+        let mut signals = Signals::new();
+        let (expected_root, expected_sum) =
+            witness::merkle_sum(&mut signals, "main", values[0], values[1], values[2], values[3]);
+        assert_eq!(root.get_value().unwrap(), expected_root);
+        assert_eq!(sum.get_value().unwrap(), expected_sum);
+    }
+}
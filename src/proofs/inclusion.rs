@@ -1,22 +1,50 @@
 pub type Result<T> = std::result::Result<T, failure::Error>;
-use crate::proofs::setup::{CircuitSetup, PP};
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::manifest::CircuitId;
+use crate::proofs::mimc::mimc_sponge;
+use crate::proofs::setup::{verify_compressed, CompressedProof, DefaultCircuit, PP};
+use crate::proofs::util::field_from_hex;
+use nova_snark::traits::circuit::StepCircuit;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[cfg(feature = "node")]
+use crate::proofs::native::{InclusionCircuit, InclusionStep, NativeCircuitSetup};
+#[cfg(feature = "node")]
+use crate::proofs::registry::CircuitKind;
+#[cfg(feature = "node")]
+use crate::proofs::setup::{compress, extend_steps, fold_steps, CircuitSetup};
+#[cfg(feature = "node")]
 use crate::proofs::util::convert_hex_to_dec;
+#[cfg(feature = "node")]
+use crate::proofs::witness::{
+    merkle_sum, mimc_sponge as mimc_sponge_signals, prove_with_witnesses, switcher, Signals, SymbolTable,
+    WitnessBackend,
+};
+#[cfg(feature = "node")]
 use merkle_sum_tree::{MerkleSumTree, Position};
+#[cfg(feature = "node")]
 use nova_scotia::circom::circuit::CircomCircuit;
-use nova_scotia::{create_recursive_circuit, FileLocation, F};
+#[cfg(feature = "node")]
+use nova_scotia::{create_recursive_circuit, FileLocation};
+#[cfg(feature = "node")]
 use nova_snark::traits::circuit::TrivialTestCircuit;
+#[cfg(feature = "node")]
 use nova_snark::RecursiveSNARK;
-use pasta_curves::{Ep, Eq, Fp, Fq};
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "node")]
+use pasta_curves::{Ep, Eq, Fp};
+#[cfg(feature = "node")]
 use serde_json::json;
-use std::{collections::HashMap, time::Instant};
-
-type G1 = pasta_curves::pallas::Point;
-type G2 = pasta_curves::vesta::Point;
-
-use crate::blockchain::blockchain::MAX_LEVELS;
+#[cfg(feature = "node")]
+use std::collections::HashMap;
 
+/// Every history starts from an empty accumulator.
+const START_PUBLIC_INPUT: [Fq; 4] = [Fq::zero(), Fq::zero(), Fq::zero(), Fq::zero()];
 
+/// Private witness of one inclusion step. The sibling hashes and sums stay
+/// on the node, only the `InclusionClaim` is sent with the proof.
+#[cfg(feature = "node")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InclusionInput {
     user_hash: String,
@@ -28,21 +56,42 @@ pub struct InclusionInput {
     neighbors_binary: Vec<String>,
 }
 
+/// What one inclusion step shows: the user's own leaf under a public root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionClaim {
+    user_hash: String,
+    user_balance: i32,
+    root_hash: String,
+    root_sum: i32,
+}
+
+/// Inclusion of a leaf in one tree per claim. The folded proof is compressed
+/// so that the sibling hashes and sums, its witness, are not sent along.
+/// Every claim is chained through the accumulator, not only the last.
 #[derive(Serialize, Deserialize, Clone)]
-pub struct ProofOfInclusion {
-    recursive_snark: RecursiveSNARK<Ep, Eq, CircomCircuit<Fq>, TrivialTestCircuit<Fp>>,
-    iteration_count: usize,
-    start_public_input: [Fq; 4],
-    z0_secondary: [Fp; 1],
-    inclusion_inputs: Vec<InclusionInput>,
+#[serde(bound = "")]
+pub struct ProofOfInclusion<C: StepCircuit<Fq> = DefaultCircuit> {
+    compressed_snark: CompressedProof<C>,
+    circuit_id: CircuitId,
+    claims: Vec<InclusionClaim>,
+}
+
+/// A history folded on the node and kept uncompressed, so that later trees
+/// can be folded onto it. Its `RecursiveSNARK` carries the witness of the
+/// steps and never leaves the node, clients get the `compress`ed proof.
+#[cfg(feature = "node")]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct InclusionFolding<C: StepCircuit<Fq> = DefaultCircuit> {
+    recursive_snark: RecursiveSNARK<Ep, Eq, C, TrivialTestCircuit<Fp>>,
+    circuit_id: CircuitId,
+    claims: Vec<InclusionClaim>,
 }
 
 
+#[cfg(feature = "node")]
 impl InclusionInput {
     pub fn new(merkle_sum_tree: &MerkleSumTree, index: usize) -> Result<InclusionInput> {
-        let mut neighbors_sum = Vec::with_capacity(MAX_LEVELS);
-        let mut neighbor_hash = Vec::with_capacity(MAX_LEVELS);
-        let mut neighbors_binary = Vec::with_capacity(MAX_LEVELS);
         let node = merkle_sum_tree.get_leaf(index).unwrap().get_node();
         let user_hash = node.get_hash().to_string();
         let user_balance = node.get_value();
@@ -52,6 +101,9 @@ impl InclusionInput {
             .get_proof(index)
             .unwrap();
         let merkle_path = proof.get_path();
+        let mut neighbors_sum = Vec::with_capacity(merkle_path.len());
+        let mut neighbor_hash = Vec::with_capacity(merkle_path.len());
+        let mut neighbors_binary = Vec::with_capacity(merkle_path.len());
 
         
         for (_i, neighbor) in merkle_path.iter().enumerate() {
@@ -87,31 +139,215 @@ impl InclusionInput {
         self.root_sum
     }
 
-    #[allow(dead_code)]
     pub fn get_user_hash(&self) -> &str {
         &self.user_hash
     }
+
+    pub fn get_claim(&self) -> InclusionClaim {
+        InclusionClaim {
+            user_hash: self.user_hash.clone(),
+            user_balance: self.user_balance,
+            root_hash: self.root_hash.clone(),
+            root_sum: self.root_sum,
+        }
+    }
+
+    /// Converts the input for the native `InclusionCircuit`.
+    pub fn to_native_step(&self) -> InclusionStep {
+        InclusionStep {
+            user_hash: field_from_hex(&self.user_hash),
+            user_balance: self.user_balance as u64,
+            root_hash: field_from_hex(&self.root_hash),
+            root_sum: Fq::from(self.root_sum as u64),
+            neighbors_sum: self.neighbors_sum.iter().map(|value| Fq::from(*value as u64)).collect(),
+            neighbors_hash: self.neighbor_hash.iter().map(|hash| field_from_hex(hash)).collect(),
+            neighbors_binary: self
+                .neighbors_binary
+                .iter()
+                .map(|bit| Fq::from(if bit == "1" { 1 } else { 0 }))
+                .collect(),
+        }
+    }
+
+    /// Evaluates the `inclusion` circuit in Rust, returning its signals and `step_out`.
+    pub fn compute_witness(&self, step_in: &[Fq]) -> Result<(Signals, Vec<Fq>)> {
+        let mut signals = Signals::new();
+        let neighbors_sum: Vec<Fq> = self
+            .neighbors_sum
+            .iter()
+            .map(|value| Fq::from(*value as u64))
+            .collect();
+        let neighbors_hash: Vec<Fq> = self.neighbor_hash.iter().map(|hash| field_from_hex(hash)).collect();
+        let neighbors_binary: Vec<Fq> = self
+            .neighbors_binary
+            .iter()
+            .map(|bit| Fq::from(if bit == "1" { 1 } else { 0 }))
+            .collect();
+        let user_balance = Fq::from(self.user_balance as u64);
+        let user_hash = field_from_hex(&self.user_hash);
+        let root_hash = field_from_hex(&self.root_hash);
+        let sum = Fq::from(self.root_sum as u64);
+
+        signals.set_array("main.step_in", step_in);
+        signals.set_array("main.neighborsSum", &neighbors_sum);
+        signals.set_array("main.neighborsHash", &neighbors_hash);
+        signals.set_array("main.neighborsBinary", &neighbors_binary);
+        signals.set("main.sum".to_string(), sum);
+        signals.set("main.rootHash".to_string(), root_hash);
+        signals.set("main.userBalance".to_string(), user_balance);
+        signals.set("main.userHash".to_string(), user_hash);
+
+        merkle_sum(
+            &mut signals,
+            "main.merklesumi",
+            neighbors_hash[0],
+            user_hash,
+            neighbors_sum[0],
+            user_balance,
+        );
+
+        let mut sum_node = user_balance;
+        let mut hash_node = user_hash;
+        signals.set("main.sumNodes[0]".to_string(), sum_node);
+        signals.set("main.hashNodes[0]".to_string(), hash_node);
+        for i in 0..neighbors_hash.len() {
+            let (hash_l, hash_r) = switcher(
+                &mut signals,
+                &format!("main.switcherHash[{}]", i),
+                neighbors_binary[i],
+                hash_node,
+                neighbors_hash[i],
+            );
+            let (sum_l, sum_r) = switcher(
+                &mut signals,
+                &format!("main.switcherSum[{}]", i),
+                neighbors_binary[i],
+                sum_node,
+                neighbors_sum[i],
+            );
+            (hash_node, sum_node) = merkle_sum(
+                &mut signals,
+                &format!("main.merklesum[{}]", i),
+                hash_l,
+                hash_r,
+                sum_l,
+                sum_r,
+            );
+            signals.set(format!("main.sumNodes[{}]", i + 1), sum_node);
+            signals.set(format!("main.hashNodes[{}]", i + 1), hash_node);
+        }
+
+        if hash_node != root_hash || sum_node != sum {
+            return Err(failure::format_err!(
+                "Inclusion path does not open to root {}",
+                self.root_hash
+            ));
+        }
+
+        let acc = mimc_sponge_signals(
+            &mut signals,
+            "main.acc",
+            &[step_in[3], sum, root_hash, user_balance, user_hash],
+            Fq::from(0),
+        );
+        let step_out = vec![sum, root_hash, user_hash, acc];
+        signals.set_array("main.step_out", &step_out);
+        Ok((signals, step_out))
+    }
+}
+
+impl InclusionClaim {
+    pub fn get_user_hash(&self) -> &str {
+        &self.user_hash
+    }
+
+    pub fn get_user_balance(&self) -> i32 {
+        self.user_balance
+    }
+
+    pub fn get_root_hash(&self) -> &str {
+        &self.root_hash
+    }
+
+    pub fn get_root_sum(&self) -> i32 {
+        self.root_sum
+    }
+
+    /// `acc` after the inclusion step for this claim.
+    fn chain(&self, acc: Fq) -> Fq {
+        mimc_sponge(&[
+            acc,
+            Fq::from(self.root_sum as u64),
+            field_from_hex(&self.root_hash),
+            Fq::from(self.user_balance as u64),
+            field_from_hex(&self.user_hash),
+        ])
+    }
 }
 
+/// The `step_out` of the inclusion circuit after one step per claim, or
+/// `None` when there are no claims.
+fn history_output(claims: &[InclusionClaim]) -> Option<Vec<Fq>> {
+    let last = claims.last()?;
+    let acc = claims.iter().fold(START_PUBLIC_INPUT[3], |acc, claim| claim.chain(acc));
+    Some(vec![
+        Fq::from(last.root_sum as u64),
+        field_from_hex(&last.root_hash),
+        field_from_hex(&last.user_hash),
+        acc,
+    ])
+}
 
-impl ProofOfInclusion {
 
+#[cfg(feature = "node")]
+impl ProofOfInclusion {
     pub fn new(
         inclusion_inputs: Vec<InclusionInput>,
         circuit_setup: &CircuitSetup,
     ) -> Result<(ProofOfInclusion, PP)> {
-        use nova_scotia::create_public_params;
+        ProofOfInclusion::new_with_backend(inclusion_inputs, circuit_setup, WitnessBackend::default())
+    }
+
+    pub fn new_with_backend(
+        inclusion_inputs: Vec<InclusionInput>,
+        circuit_setup: &CircuitSetup,
+        witness_backend: WitnessBackend,
+    ) -> Result<(ProofOfInclusion, PP)> {
+        let pp = PP::from_circuit_setup(circuit_setup);
+        let folding = InclusionFolding::new_with_backend(inclusion_inputs, circuit_setup, &pp, witness_backend)?;
+        Ok((folding.compress(&pp)?, pp))
+    }
+}
+
+#[cfg(feature = "node")]
+impl ProofOfInclusion<InclusionCircuit> {
+    /// Proves inclusion with the native bellpepper circuit instead of circom artifacts.
+    pub fn new_native(
+        inclusion_inputs: Vec<InclusionInput>,
+        circuit_setup: &NativeCircuitSetup,
+    ) -> Result<(ProofOfInclusion<InclusionCircuit>, PP<InclusionCircuit>)> {
+        let pp = circuit_setup.inclusion_params()?;
+        let folding = InclusionFolding::new_native(inclusion_inputs, circuit_setup, &pp)?;
+        Ok((folding.compress(&pp)?, pp))
+    }
+}
+
+#[cfg(feature = "node")]
+impl InclusionFolding {
+    /// Folds one step per input with `pp`, the parameters of `circuit_setup`.
+    pub fn new_with_backend(
+        inclusion_inputs: Vec<InclusionInput>,
+        circuit_setup: &CircuitSetup,
+        pp: &PP,
+        witness_backend: WitnessBackend,
+    ) -> Result<InclusionFolding> {
         let r1cs = circuit_setup.get_r1cs();
-        let pp = create_public_params(r1cs.clone());
-        
-        let iteration_count = inclusion_inputs.len();
         let start_proof = Instant::now();
         let mut private_inputs = Vec::new();
-        for (_, inclusion_input) in inclusion_inputs.iter().enumerate() {
-            
+        for inclusion_input in &inclusion_inputs {
             let mut private_input = HashMap::new();
             private_input.insert("neighborsSum".to_string(), json!(&inclusion_input.neighbors_sum));
-            
+
             // Convert hex hashes to decimal strings like liabilities proof does
             let mut neighbors_hash_dec = Vec::new();
             for hex_hash in &inclusion_input.neighbor_hash {
@@ -123,55 +359,174 @@ impl ProofOfInclusion {
             private_input.insert("rootHash".to_string(), json!(convert_hex_to_dec(inclusion_input.root_hash.to_string())));
             private_input.insert("userBalance".to_string(), json!(&inclusion_input.user_balance));
             private_input.insert("userHash".to_string(), json!(convert_hex_to_dec(inclusion_input.user_hash.to_string())));
-            
+
             private_inputs.push(private_input);
         }
 
-        let start_public_input = [F::<G1>::from(0), F::<G1>::from(0), F::<G1>::from(0), F::<G1>::from(0)];
-        
-        let recursive_snark = create_recursive_circuit(
-            FileLocation::PathBuf(circuit_setup.get_witness_generator_file().to_path_buf()),
-            r1cs.clone(),
-            private_inputs,
-            start_public_input.to_vec(),
-            &pp,
-        ).unwrap();
-        
+        let recursive_snark = match witness_backend {
+            WitnessBackend::Rust => {
+                let symbols = SymbolTable::load(circuit_setup.get_symbols_file())?;
+                let mut step_in = START_PUBLIC_INPUT.to_vec();
+                let mut witnesses = Vec::with_capacity(inclusion_inputs.len());
+                for inclusion_input in &inclusion_inputs {
+                    let (signals, step_out) = inclusion_input.compute_witness(&step_in)?;
+                    witnesses.push(symbols.witness(&signals, &r1cs)?);
+                    step_in = step_out;
+                }
+                prove_with_witnesses(pp.get_pp(), &r1cs, witnesses, START_PUBLIC_INPUT.to_vec())?
+            }
+            WitnessBackend::Wasm | WitnessBackend::Native => create_recursive_circuit(
+                FileLocation::PathBuf(circuit_setup.get_witness_generator(witness_backend)?),
+                r1cs,
+                private_inputs,
+                START_PUBLIC_INPUT.to_vec(),
+                pp.get_pp(),
+            )
+            .map_err(|e| failure::format_err!("Witness generation failed: {}", e))?,
+        };
         println!("RecursiveSNARK::proof took {:?}", start_proof.elapsed());
-        let z0_secondary = [F::<G2>::from(0)];
 
-        let inclusion_proof = ProofOfInclusion {
+        Ok(InclusionFolding {
             recursive_snark,
-            iteration_count,
-            start_public_input,
-            z0_secondary,
-            inclusion_inputs,
-        };
-        
-        // Create a PP wrapper for the client using the same r1cs  
-        let client_pp = PP::new(r1cs);
-        Ok((inclusion_proof, client_pp))
+            circuit_id: circuit_setup.get_circuit_id().clone(),
+            claims: inclusion_inputs.iter().map(InclusionInput::get_claim).collect(),
+        })
     }
 
-    pub fn verify(&self, pp: PP) -> Result<()> {
-        let start = Instant::now();
-        let res = self.recursive_snark.verify(
-            pp.get_pp(),
-            self.iteration_count,
-            &self.start_public_input,
-            &self.z0_secondary,
+    /// Folds `inclusion_inputs` onto this history as later steps, so a history
+    /// only needs proving for the trees added since. The new steps are always
+    /// witnessed in-process, as the circom generators can only start a proof.
+    pub fn extend(
+        mut self,
+        inclusion_inputs: Vec<InclusionInput>,
+        circuit_setup: &CircuitSetup,
+        pp: &PP,
+    ) -> Result<InclusionFolding> {
+        if &self.circuit_id != circuit_setup.get_circuit_id() {
+            return Err(failure::format_err!(
+                "Cannot extend a proof made with {} using {}",
+                self.circuit_id,
+                circuit_setup.get_circuit_id()
+            ));
+        }
+        let mut step_in = history_output(&self.claims)
+            .ok_or_else(|| failure::format_err!("Cannot extend a proof with no steps"))?;
+        let r1cs = circuit_setup.get_r1cs();
+        let symbols = SymbolTable::load(circuit_setup.get_symbols_file())?;
+
+        let start_proof = Instant::now();
+        let mut circuits = Vec::with_capacity(inclusion_inputs.len());
+        for inclusion_input in &inclusion_inputs {
+            let (signals, step_out) = inclusion_input.compute_witness(&step_in)?;
+            circuits.push(CircomCircuit {
+                r1cs: r1cs.clone(),
+                witness: Some(symbols.witness(&signals, &r1cs)?),
+            });
+            step_in = step_out;
+        }
+        extend_steps(pp.get_pp(), &mut self.recursive_snark, &circuits, START_PUBLIC_INPUT.to_vec())?;
+        println!(
+            "RecursiveSNARK::proof extended by {} steps in {:?}",
+            inclusion_inputs.len(),
+            start_proof.elapsed()
         );
-        
-        if res.is_err() {
-            return Err(failure::format_err!("Final inclusion proof verification failed: {:?}", res.err()));
+
+        self.claims.extend(inclusion_inputs.iter().map(InclusionInput::get_claim));
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "node")]
+impl InclusionFolding<InclusionCircuit> {
+    /// Like `new_with_backend`, with the native bellpepper circuit.
+    pub fn new_native(
+        inclusion_inputs: Vec<InclusionInput>,
+        circuit_setup: &NativeCircuitSetup,
+        pp: &PP<InclusionCircuit>,
+    ) -> Result<InclusionFolding<InclusionCircuit>> {
+        if circuit_setup.get_kind() != CircuitKind::Inclusion {
+            return Err(failure::format_err!(
+                "Expected an inclusion setup, got {}",
+                circuit_setup.get_kind().name()
+            ));
         }
-        
-        println!("Inclusion folding verified successfully in {:?}", start.elapsed());
-        
+        let start_proof = Instant::now();
+        let circuits: Vec<InclusionCircuit> = inclusion_inputs
+            .iter()
+            .map(|input| InclusionCircuit::new(circuit_setup.get_depth(), input.to_native_step()))
+            .collect();
+        let recursive_snark = fold_steps(pp.get_pp(), &circuits, START_PUBLIC_INPUT.to_vec())?;
+        println!("RecursiveSNARK::proof took {:?}", start_proof.elapsed());
+
+        Ok(InclusionFolding {
+            recursive_snark,
+            circuit_id: pp.get_circuit_id().clone(),
+            claims: inclusion_inputs.iter().map(InclusionInput::get_claim).collect(),
+        })
+    }
+}
+
+#[cfg(feature = "node")]
+impl<C: StepCircuit<Fq>> InclusionFolding<C> {
+    /// The proof sent to clients, `pp` must be the parameters it was folded with.
+    pub fn compress(&self, pp: &PP<C>) -> Result<ProofOfInclusion<C>> {
+        let start = Instant::now();
+        let compressed_snark = compress(pp, &self.recursive_snark)?;
+        println!("CompressedSNARK::proof took {:?}", start.elapsed());
+        Ok(ProofOfInclusion {
+            compressed_snark,
+            circuit_id: self.circuit_id.clone(),
+            claims: self.claims.clone(),
+        })
+    }
+
+    pub fn get_claims(&self) -> &Vec<InclusionClaim> {
+        &self.claims
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofOfInclusion<C> {
+    /// Verifies against `pp`, which must come from a trusted source or be
+    /// checked with `CircuitManifest::check_params` first.
+    pub fn verify(&self, pp: PP<C>) -> Result<()> {
+        let start = Instant::now();
+        if &self.circuit_id != pp.get_circuit_id() {
+            return Err(failure::format_err!(
+                "Circuit mismatch: proof made with {}, parameters for {}",
+                self.circuit_id,
+                pp.get_circuit_id()
+            ));
+        }
+        let expected = history_output(&self.claims)
+            .ok_or_else(|| failure::format_err!("Inclusion proof carries no claims"))?;
+
+        let step_out = verify_compressed(&pp, &self.compressed_snark, self.claims.len(), &START_PUBLIC_INPUT)
+            .map_err(|e| failure::format_err!("Final inclusion proof verification failed: {}", e))?;
+        if step_out != expected {
+            return Err(failure::format_err!(
+                "Proof output does not match the {} claimed leaves",
+                self.claims.len()
+            ));
+        }
+
+        println!("Inclusion proof verified successfully in {:?}", start.elapsed());
+
         Ok(())
     }
 
-    pub fn get_inclusion_inputs(&self) -> Vec<InclusionInput> {
-        self.inclusion_inputs.clone()
+    pub fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+
+    pub fn get_claims(&self) -> &Vec<InclusionClaim> {
+        &self.claims
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofEncoding for ProofOfInclusion<C> {
+    const KIND: PayloadKind = PayloadKind::InclusionProof;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
     }
 }
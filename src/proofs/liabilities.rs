@@ -1,22 +1,44 @@
 pub type Result<T> = std::result::Result<T, failure::Error>;
-use crate::proofs::setup::{CircuitSetup, PP};
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::manifest::CircuitId;
+use crate::proofs::setup::PP;
 use crate::proofs::util::convert_hex_to_dec;
 use ff::PrimeField;
-use merkle_sum_tree::{MerkleSumTree, Position};
-use std::sync::Arc;
-use nova_scotia::circom::circuit::CircomCircuit;
-use nova_scotia::{create_public_params, create_recursive_circuit, FileLocation, F};
-use nova_snark::traits::circuit::TrivialTestCircuit;
+use crate::proofs::setup::DefaultCircuit;
+use nova_snark::traits::circuit::{StepCircuit, TrivialTestCircuit};
 use nova_snark::RecursiveSNARK;
 use pasta_curves::{Ep, Eq, Fp, Fq};
 use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[cfg(feature = "node")]
+use crate::proofs::native::{LiabilitiesCircuit, LiabilitiesStep, NativeCircuitSetup};
+#[cfg(feature = "node")]
+use crate::proofs::registry::CircuitKind;
+#[cfg(feature = "node")]
+use crate::proofs::setup::{fold_steps, CircuitSetup};
+#[cfg(feature = "node")]
+use crate::proofs::util::field_from_hex;
+#[cfg(feature = "node")]
+use crate::proofs::witness::{
+    merkle_sum, prove_with_witnesses, safe_range_check, switcher, Signals, SymbolTable,
+    WitnessBackend, BALANCE_BITS,
+};
+#[cfg(feature = "node")]
+use merkle_sum_tree::{MerkleSumTree, Position};
+#[cfg(feature = "node")]
+use nova_scotia::{create_public_params, create_recursive_circuit, FileLocation, F};
+#[cfg(feature = "node")]
 use serde_json::json;
-use std::{collections::HashMap, time::Instant};
+#[cfg(feature = "node")]
+use std::{collections::HashMap, sync::Arc};
 
+#[cfg(feature = "node")]
 type G1 = pasta_curves::pallas::Point;
+#[cfg(feature = "node")]
 type G2 = pasta_curves::vesta::Point;
 
-
+#[cfg(feature = "node")]
 #[derive(Debug, Clone)]
 pub struct MerkleSumTreeChange {
     index: usize,
@@ -33,6 +55,7 @@ pub struct LiabilitiesOutput {
     all_small_range: Fq,
 }
 
+#[cfg(feature = "node")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LiabilitiesInput {
     old_user_hash: Vec<String>,
@@ -47,11 +70,13 @@ pub struct LiabilitiesInput {
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-pub struct ProofOfLiabilities {
-    recursive_snark: RecursiveSNARK<Ep, Eq, CircomCircuit<Fq>, TrivialTestCircuit<Fp>>,
+#[serde(bound = "")]
+pub struct ProofOfLiabilities<C: StepCircuit<Fq> = DefaultCircuit> {
+    recursive_snark: RecursiveSNARK<Ep, Eq, C, TrivialTestCircuit<Fp>>,
     iteration_count: usize,
     start_public_input: [Fq; 4],
     z0_secondary: [Fp; 1],
+    circuit_id: CircuitId,
     final_root_hash: String,
     final_root_sum: i32,
 }
@@ -72,6 +97,7 @@ impl LiabilitiesOutput {
     }
 }
 
+#[cfg(feature = "node")]
 impl LiabilitiesInput {
     pub fn new(changes: Vec<MerkleSumTreeChange>) -> Result<LiabilitiesInput> {
         let changes_len = changes.len();
@@ -142,12 +168,136 @@ impl LiabilitiesInput {
         };
         Ok(liabilities_input)
     }
+
+    /// Converts the input for the native `LiabilitiesCircuit`.
+    pub fn to_native_step(&self) -> LiabilitiesStep {
+        let to_field = |value: &i32| Fq::from(*value as u64);
+        let to_fields = |hashes: &Vec<String>| -> Vec<Fq> {
+            hashes.iter().map(|hash| field_from_hex(hash)).collect()
+        };
+        LiabilitiesStep {
+            old_user_hash: to_fields(&self.old_user_hash),
+            old_values: self.old_values.iter().map(|value| *value as u64).collect(),
+            new_user_hash: to_fields(&self.new_user_hash),
+            new_values: self.new_values.iter().map(|value| *value as u64).collect(),
+            temp_hash: to_fields(&self.temp_hash),
+            temp_sum: self.temp_sum.iter().map(to_field).collect(),
+            neighbors_sum: self
+                .neighbors_sum
+                .iter()
+                .map(|sums| sums.iter().map(to_field).collect())
+                .collect(),
+            neighbors_hash: self.neighbor_hash.iter().map(to_fields).collect(),
+            neighbors_binary: self
+                .neighbors_binary
+                .iter()
+                .map(|bits| {
+                    bits.iter()
+                        .map(|bit| Fq::from(if bit == "1" { 1 } else { 0 }))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// Evaluates the `liabilities` circuit in Rust, returning its signals and `step_out`.
+    pub fn compute_witness(&self, step_in: &[Fq]) -> Result<(Signals, Vec<Fq>)> {
+        let mut signals = Signals::new();
+        let changes = self.old_values.len();
+        let to_field = |value: &i32| Fq::from(*value as u64);
+        let temp_hash: Vec<Fq> = self.temp_hash.iter().map(|hash| field_from_hex(hash)).collect() };
+        let temp_sum: Vec<Fq> = self.temp_sum.iter().map(to_field).collect();
+
+        signals.set_array("main.step_in", step_in);
+        signals.set_array("main.tempHash", &temp_hash);
+        signals.set_array("main.tempSum", &temp_sum);
+
+        for j in 0..changes {
+            let neighbors_sum: Vec<Fq> = self.neighbors_sum[j].iter().map(to_field).collect();
+            let neighbors_hash: Vec<Fq> = self.neighbor_hash[j].iter().map(|hash| field_from_hex(hash)).collect() };
+            let neighbors_binary: Vec<Fq> = self.neighbors_binary[j]
+                .iter()
+                .map(|bit| Fq::from(if bit == "1" { 1 } else { 0 }))
+                .collect();
+            signals.set_array(&format!("main.neighborsSum[{}]", j), &neighbors_sum);
+            signals.set_array(&format!("main.neighborsHash[{}]", j), &neighbors_hash);
+            signals.set_array(&format!("main.neighborsBinary[{}]", j), &neighbors_binary);
+
+            safe_range_check(
+                &mut signals,
+                &format!("main.balanceCheck[{}].rangeCheck", j),
+                self.new_values[j] as u64,
+                BALANCE_BITS,
+            );
+
+            // Side 0 opens the old leaf against tempHash[j], side 1 the new leaf against tempHash[j+1]
+            let leaves = [
+                ("old", &self.old_user_hash[j], self.old_values[j]),
+                ("new", &self.new_user_hash[j], self.new_values[j]),
+            ];
+            for (side, (name, user_hash, value)) in leaves.iter().enumerate() {
+                let mut hash_node = field_from_hex(user_hash);
+                let mut sum_node = to_field(value);
+                signals.set(format!("main.{}UserHash[{}]", name, j), hash_node);
+                signals.set(format!("main.{}Values[{}]", name, j), sum_node);
+                signals.set(format!("main.hashNodes[{}][{}][0]", side, j), hash_node);
+                signals.set(format!("main.sumNodes[{}][{}][0]", side, j), sum_node);
+                for i in 0..neighbors_hash.len() {
+                    let (hash_l, hash_r) = switcher(
+                        &mut signals,
+                        &format!("main.switcherHash[{}][{}][{}]", side, j, i),
+                        neighbors_binary[i],
+                        hash_node,
+                        neighbors_hash[i],
+                    );
+                    let (sum_l, sum_r) = switcher(
+                        &mut signals,
+                        &format!("main.switcherSum[{}][{}][{}]", side, j, i),
+                        neighbors_binary[i],
+                        sum_node,
+                        neighbors_sum[i],
+                    );
+                    (hash_node, sum_node) = merkle_sum(
+                        &mut signals,
+                        &format!("main.merklesum[{}][{}][{}]", side, j, i),
+                        hash_l,
+                        hash_r,
+                        sum_l,
+                        sum_r,
+                    );
+                    signals.set(format!("main.hashNodes[{}][{}][{}]", side, j, i + 1), hash_node);
+                    signals.set(format!("main.sumNodes[{}][{}][{}]", side, j, i + 1), sum_node);
+                }
+                if hash_node != temp_hash[j + side] || sum_node != temp_sum[j + side] {
+                    return Err(failure::format_err!(
+                        "Change {} does not open the {} leaf to root {}",
+                        j,
+                        name,
+                        self.temp_hash[j + side]
+                    ));
+                }
+            }
+        }
+
+        let step_out = vec![step_in[0], step_in[1], temp_hash[changes], temp_sum[changes]];
+        signals.set_array("main.step_out", &step_out);
+        Ok((signals, step_out))
+    }
 }
 
+#[cfg(feature = "node")]
 impl ProofOfLiabilities {
     pub fn new(
         liabilities_inputs: Vec<LiabilitiesInput>,
         circuit_setup: &CircuitSetup,
+    ) -> Result<(ProofOfLiabilities, PP)> {
+        ProofOfLiabilities::new_with_backend(liabilities_inputs, circuit_setup, WitnessBackend::default())
+    }
+
+    pub fn new_with_backend(
+        liabilities_inputs: Vec<LiabilitiesInput>,
+        circuit_setup: &CircuitSetup,
+        witness_backend: WitnessBackend,
     ) -> Result<(ProofOfLiabilities, PP)> {
         let iteration_count = liabilities_inputs.len();
         let initial_root_hash = liabilities_inputs[0].temp_hash[0].clone();
@@ -204,14 +354,27 @@ impl ProofOfLiabilities {
         let r1cs = circuit_setup.get_r1cs();
         let pp = create_public_params(r1cs.clone());
         
-        let recursive_snark = create_recursive_circuit(
-            FileLocation::PathBuf(circuit_setup.get_witness_generator_file().to_path_buf()),
-            r1cs,
-            private_inputs,
-            start_public_input.to_vec(),
-            &pp,
-        )
-        .unwrap();
+        let recursive_snark = match witness_backend {
+            WitnessBackend::Rust => {
+                let symbols = SymbolTable::load(circuit_setup.get_symbols_file())?;
+                let mut step_in = start_public_input.to_vec();
+                let mut witnesses = Vec::with_capacity(iteration_count);
+                for liabilities_input in &liabilities_inputs {
+                    let (signals, step_out) = liabilities_input.compute_witness(&step_in)?;
+                    witnesses.push(symbols.witness(&signals, &r1cs)?);
+                    step_in = step_out;
+                }
+                prove_with_witnesses(&pp, &r1cs, witnesses, start_public_input.to_vec())?
+            }
+            WitnessBackend::Wasm | WitnessBackend::Native => create_recursive_circuit(
+                FileLocation::PathBuf(circuit_setup.get_witness_generator(witness_backend)?),
+                r1cs,
+                private_inputs,
+                start_public_input.to_vec(),
+                &pp,
+            )
+            .map_err(|e| failure::format_err!("Witness generation failed: {}", e))?,
+        };
 
         println!("RecursiveSNARK::proof took {:?}", start_proof.elapsed());
         let z0_secondary = [F::<G2>::from(0)];
@@ -220,18 +383,86 @@ impl ProofOfLiabilities {
             iteration_count: iteration_count,
             start_public_input: start_public_input,
             z0_secondary: z0_secondary,
+            circuit_id: circuit_setup.get_circuit_id().clone(),
             final_root_hash: final_root_hash,
             final_root_sum: final_root_sum,
         };
         
-        let pp_wrapper = PP::from_public_params(pp);
+        let pp_wrapper = PP::from_public_params(pp, circuit_setup.get_circuit_id().clone());
         
         Ok((liabilities_proof, pp_wrapper))
     }
+}
 
+#[cfg(feature = "node")]
+impl ProofOfLiabilities<LiabilitiesCircuit> {
+    /// Proves the changes with the native bellpepper circuit, `changes_per_step`
+    /// changes per folding step as configured in `circuit_setup`.
+    pub fn new_native(
+        liabilities_inputs: Vec<LiabilitiesInput>,
+        circuit_setup: &NativeCircuitSetup,
+    ) -> Result<(ProofOfLiabilities<LiabilitiesCircuit>, PP<LiabilitiesCircuit>)> {
+        if circuit_setup.get_kind() != CircuitKind::LiabilitiesChangesFolding {
+            return Err(failure::format_err!(
+                "Expected a liabilities setup, got {}",
+                circuit_setup.get_kind().name()
+            ));
+        }
+        let changes = circuit_setup.get_changes_per_step();
+        if let Some(input) = liabilities_inputs.iter().find(|input| input.old_values.len() != changes) {
+            return Err(failure::format_err!(
+                "Setup folds {} changes per step but an input has {}",
+                changes,
+                input.old_values.len()
+            ));
+        }
+        let iteration_count = liabilities_inputs.len();
+        let initial_root_hash = &liabilities_inputs[0].temp_hash[0];
+        let initial_root_sum = liabilities_inputs[0].temp_sum[0];
+        let final_root_hash = liabilities_inputs[iteration_count - 1].temp_hash[changes].clone();
+        let final_root_sum = liabilities_inputs[iteration_count - 1].temp_sum[changes];
 
-    pub fn verify(&self, pp: PP) -> Result<LiabilitiesOutput> {
+        let pp = circuit_setup.liabilities_params()?;
+        let start_proof = Instant::now();
+        let circuits: Vec<LiabilitiesCircuit> = liabilities_inputs
+            .iter()
+            .map(|input| LiabilitiesCircuit::new(circuit_setup.get_depth(), changes, input.to_native_step()))
+            .collect();
+        let start_public_input = [
+            Fq::from(1),
+            Fq::from(1),
+            field_from_hex(initial_root_hash),
+            Fq::from(initial_root_sum as u64),
+        ];
+        let recursive_snark = fold_steps(pp.get_pp(), &circuits, start_public_input.to_vec())?;
+        println!("RecursiveSNARK::proof took {:?}", start_proof.elapsed());
+
+        let liabilities_proof = ProofOfLiabilities {
+            recursive_snark,
+            iteration_count,
+            start_public_input,
+            z0_secondary: [Fp::from(0)],
+            circuit_id: pp.get_circuit_id().clone(),
+            final_root_hash,
+            final_root_sum,
+        };
+        Ok((liabilities_proof, pp))
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofOfLiabilities<C> {
+    /// Verifies against `pp`, which must come from a trusted source or be
+    /// checked with `CircuitManifest::check_params` first.
+    pub fn verify(&self, pp: PP<C>) -> Result<LiabilitiesOutput> {
         let start = Instant::now();
+        if &self.circuit_id != pp.get_circuit_id() {
+            return Err(failure::format_err!(
+                "Circuit mismatch: proof made with {}, parameters for {}",
+                self.circuit_id,
+                pp.get_circuit_id()
+            ));
+        }
+
         let res = self.recursive_snark.verify(
             pp.get_pp(),
             self.iteration_count,
@@ -249,7 +480,7 @@ impl ProofOfLiabilities {
         let (final_output, _) = res.as_ref().unwrap();
         
         // Verify final root hash matches expected value
-        let expected_final_hash = F::<G1>::from_str_vartime(
+        let expected_final_hash = Fq::from_str_vartime(
             convert_hex_to_dec(self.final_root_hash.to_string()).as_str()
         ).unwrap();
         if final_output[2] != expected_final_hash {
@@ -261,7 +492,7 @@ impl ProofOfLiabilities {
         }
         
         // Verify final root sum matches expected value
-        let expected_final_sum = F::<G1>::from(self.final_root_sum as u64);
+        let expected_final_sum = Fq::from(self.final_root_sum as u64);
         if final_output[3] != expected_final_sum {
             return Err(failure::format_err!(
                 "Final root sum mismatch: expected {:?}, got {:?}", 
@@ -274,8 +505,28 @@ impl ProofOfLiabilities {
         liabilities_output
     }
 
+    pub fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+
+    pub fn get_final_root_hash(&self) -> &str {
+        &self.final_root_hash
+    }
+
+    pub fn get_final_root_sum(&self) -> i32 {
+        self.final_root_sum
+    }
+}
+
+impl<C: StepCircuit<Fq>> ProofEncoding for ProofOfLiabilities<C> {
+    const KIND: PayloadKind = PayloadKind::LiabilitiesProof;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
 }
 
+#[cfg(feature = "node")]
 impl MerkleSumTreeChange {
     pub fn new(
         index: usize,
@@ -0,0 +1,438 @@
+use crate::errors::BlockchainError;
+#[cfg(feature = "node")]
+use crate::proofs::native::NativeCircuitSetup;
+#[cfg(feature = "node")]
+use crate::proofs::registry::CircuitRegistry;
+use crate::proofs::registry::CircuitKind;
+use crate::proofs::setup::PP;
+use nova_snark::traits::circuit::StepCircuit;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "node")]
+use std::collections::HashMap;
+use std::env::current_dir;
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "node")]
+use std::sync::{Mutex, OnceLock};
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const DEFAULT_CIRCUITS_DIR: &str = "circuits/compile";
+pub const MANIFEST_VERSION: u32 = 1;
+/// Bumped whenever the native circuit constraints change, so old proofs stop verifying.
+pub const NATIVE_CIRCUIT_VERSION: u32 = 1;
+const NATIVE_MARKER: &str = "native-bellpepper";
+
+/// Identity of one compiled circuit build. It is recorded in the manifest,
+/// carried by every proof and public parameters, and compared on verification.
+/// Native circuits have no R1CS file, their `r1cs_hash` is the digest of the
+/// parameters synthesized from them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CircuitId {
+    name: String,
+    depth: usize,
+    changes_per_step: usize,
+    r1cs_hash: String,
+    witness_generator_hash: String,
+}
+
+/// A manifest line: the build's identity and the hashes of its other artifacts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    #[serde(flatten)]
+    circuit_id: CircuitId,
+    // `<name>.sym`, read by the rust witness backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symbols_hash: Option<String>,
+    // `<name>_cpp/<name>.cpp` followed by `<name>_cpp/<name>.dat`, the sources of the native generator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    native_witness_generator_hash: Option<String>,
+    // `PP::params_digest` of the public parameters generated from the R1CS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pp_digest: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitManifest {
+    version: u32,
+    circuits: Vec<ManifestEntry>,
+}
+
+impl CircuitId {
+    /// Identity of a circuit synthesized in Rust before its parameters are
+    /// generated. Parameters replace the version label with their digest.
+    pub fn native(kind: CircuitKind, depth: usize, changes_per_step: usize) -> CircuitId {
+        CircuitId {
+            name: kind.name().to_string(),
+            depth,
+            changes_per_step,
+            r1cs_hash: format!("{}-v{}", NATIVE_MARKER, NATIVE_CIRCUIT_VERSION),
+            witness_generator_hash: NATIVE_MARKER.to_string(),
+        }
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.witness_generator_hash == NATIVE_MARKER
+    }
+
+    pub(crate) fn with_r1cs_hash(self, r1cs_hash: String) -> CircuitId {
+        CircuitId { r1cs_hash, ..self }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get_changes_per_step(&self) -> usize {
+        self.changes_per_step
+    }
+
+    pub fn get_r1cs_hash(&self) -> &str {
+        &self.r1cs_hash
+    }
+
+    pub fn get_witness_generator_hash(&self) -> &str {
+        &self.witness_generator_hash
+    }
+}
+
+impl std::fmt::Display for CircuitId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}@depth{}x{} (r1cs {})",
+            self.name,
+            self.depth,
+            self.changes_per_step,
+            &self.r1cs_hash[..self.r1cs_hash.len().min(12)]
+        )
+    }
+}
+
+impl CircuitManifest {
+    pub fn load(path: &Path) -> Result<CircuitManifest> {
+        let data = fs::read_to_string(path).map_err(|e| {
+            BlockchainError::CircuitSetup(format!(
+                "Cannot read circuit manifest {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        CircuitManifest::from_json(&data)
+    }
+
+    /// The manifest under `circuits/compile` in the working directory.
+    pub fn load_default() -> Result<CircuitManifest> {
+        let root = current_dir()?.join(DEFAULT_CIRCUITS_DIR);
+        CircuitManifest::load(&root.join(MANIFEST_FILE))
+    }
+
+    /// Parses a manifest, e.g. one embedded with `include_str!` by a wallet.
+    pub fn from_json(data: &str) -> Result<CircuitManifest> {
+        let manifest: CircuitManifest = serde_json::from_str(data)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(BlockchainError::CircuitSetup(format!(
+                "Unsupported circuit manifest version {}, expected {}",
+                manifest.version, MANIFEST_VERSION
+            ))
+            .into());
+        }
+        Ok(manifest)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn get_circuits(&self) -> impl Iterator<Item = &CircuitId> {
+        self.circuits.iter().map(|entry| &entry.circuit_id)
+    }
+
+    pub fn find(&self, kind: CircuitKind, depth: usize) -> Option<&CircuitId> {
+        self.find_entry(kind, depth).map(|entry| &entry.circuit_id)
+    }
+
+    fn find_entry(&self, kind: CircuitKind, depth: usize) -> Option<&ManifestEntry> {
+        self.circuits
+            .iter()
+            .find(|entry| entry.circuit_id.name == kind.name() && entry.circuit_id.depth == depth)
+    }
+
+    /// Records the digest of the parameters generated from `circuit_id`'s R1CS.
+    pub fn set_params_digest(&mut self, circuit_id: &CircuitId, pp_digest: String) -> Result<()> {
+        let entry = self
+            .circuits
+            .iter_mut()
+            .find(|entry| &entry.circuit_id == circuit_id)
+            .ok_or_else(|| BlockchainError::CircuitSetup(format!("Circuit manifest does not list {}", circuit_id)))?;
+        entry.pp_digest = Some(pp_digest);
+        Ok(())
+    }
+
+    /// Hashes the artifacts on disk and checks them against the manifest entry.
+    /// The symbols and the native generator sources are checked when the entry lists them.
+    pub fn verify_artifacts(
+        &self,
+        kind: CircuitKind,
+        depth: usize,
+        circuit_file: &Path,
+        witness_generator_file: &Path,
+        symbols_file: &Path,
+        native_witness_generator_files: &[&Path],
+    ) -> Result<CircuitId> {
+        let entry = self.find_entry(kind, depth).ok_or_else(|| {
+            BlockchainError::CircuitSetup(format!(
+                "Circuit manifest has no entry for {} at depth {}",
+                kind.name(),
+                depth
+            ))
+        })?;
+        let expected = &entry.circuit_id;
+        let r1cs_hash = hash_file(circuit_file)?;
+        if r1cs_hash != expected.r1cs_hash {
+            return Err(BlockchainError::CircuitSetup(format!(
+                "R1CS hash mismatch for {}: manifest {}, file {}",
+                circuit_file.display(),
+                expected.r1cs_hash,
+                r1cs_hash
+            ))
+            .into());
+        }
+        let witness_generator_hash = hash_file(witness_generator_file)?;
+        if witness_generator_hash != expected.witness_generator_hash {
+            return Err(BlockchainError::CircuitSetup(format!(
+                "Witness generator hash mismatch for {}: manifest {}, file {}",
+                witness_generator_file.display(),
+                expected.witness_generator_hash,
+                witness_generator_hash
+            ))
+            .into());
+        }
+        if let Some(symbols_hash) = &entry.symbols_hash {
+            check_hash(symbols_hash, &hash_file(symbols_file)?, symbols_file)?;
+        }
+        if let Some(native_hash) = &entry.native_witness_generator_hash {
+            check_hash(native_hash, &hash_files(native_witness_generator_files)?, native_witness_generator_files[0])?;
+        }
+        Ok(expected.clone())
+    }
+
+    /// Rejects circuit identities that this manifest does not list. Native
+    /// circuits are also accepted when this binary synthesizes parameters
+    /// with the same digest.
+    pub fn check(&self, circuit_id: &CircuitId) -> Result<()> {
+        if self.get_circuits().any(|listed| listed == circuit_id) || is_local_native(circuit_id)? {
+            Ok(())
+        } else {
+            Err(BlockchainError::ProofVerification {
+                reason: format!("Proof was made with an unknown circuit build {}", circuit_id),
+            }
+            .into())
+        }
+    }
+
+    /// Identifies public parameters by their digest rather than by the label
+    /// they carry: the label must be a build this manifest trusts, and the
+    /// parameters must hash to the digest trusted for that build.
+    pub fn check_params<C: StepCircuit<Fq>>(&self, pp: &PP<C>) -> Result<CircuitId> {
+        let circuit_id = pp.get_circuit_id();
+        self.check(circuit_id)?;
+        let trusted = self.trusted_params_digest(circuit_id)?;
+        let digest = pp.params_digest()?;
+        if digest != trusted {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Public parameters for {} hash to {}, expected {}",
+                    circuit_id, digest, trusted
+                ),
+            }
+            .into());
+        }
+        Ok(circuit_id.clone())
+    }
+
+    /// The digest recorded for `circuit_id`, or when none is, the digest of the
+    /// parameters generated from the local artifacts.
+    pub fn trusted_params_digest(&self, circuit_id: &CircuitId) -> Result<String> {
+        let recorded = self
+            .circuits
+            .iter()
+            .find(|entry| &entry.circuit_id == circuit_id)
+            .and_then(|entry| entry.pp_digest.clone());
+        if let Some(recorded) = recorded {
+            return Ok(recorded);
+        }
+        if circuit_id.is_native() {
+            // `check` has regenerated them, their identity carries the digest
+            return Ok(circuit_id.r1cs_hash.clone());
+        }
+        local_params_digest(circuit_id)
+    }
+}
+
+fn check_hash(expected: &str, actual: &str, path: &Path) -> Result<()> {
+    if expected != actual {
+        return Err(BlockchainError::CircuitSetup(format!(
+            "Hash mismatch for {}: manifest {}, file {}",
+            path.display(),
+            expected,
+            actual
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+    hash_files(&[path])
+}
+
+/// SHA-256 of the files' contents, one after the other.
+pub fn hash_files(paths: &[&Path]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for path in paths {
+        let data = fs::read(path).map_err(|e| {
+            BlockchainError::CircuitSetup(format!("Cannot read circuit artifact {}: {}", path.display(), e))
+        })?;
+        hasher.update(&data);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Whether `circuit_id` names parameters this binary synthesizes itself.
+/// Regenerating them is slow, so the identities are cached.
+#[cfg(feature = "node")]
+fn is_local_native(circuit_id: &CircuitId) -> Result<bool> {
+    static NATIVE: OnceLock<Mutex<HashMap<(CircuitKind, usize, usize), CircuitId>>> = OnceLock::new();
+    let kind = match CircuitKind::from_name(&circuit_id.name) {
+        Some(kind) if circuit_id.is_native() => kind,
+        _ => return Ok(false),
+    };
+    let key = (kind, circuit_id.depth, circuit_id.changes_per_step);
+    let cache = NATIVE.get_or_init(|| Mutex::new(HashMap::new()));
+    let cached = cache.lock().map_err(|_| BlockchainError::MutexPoisoned)?.get(&key).cloned();
+    let local = match cached {
+        Some(local) => local,
+        None => {
+            let setup = match NativeCircuitSetup::new(kind, circuit_id.depth, circuit_id.changes_per_step) {
+                Ok(setup) => setup,
+                Err(_) => return Ok(false),
+            };
+            let local = setup.params_circuit_id()?;
+            cache.lock().map_err(|_| BlockchainError::MutexPoisoned)?.insert(key, local.clone());
+            local
+        }
+    };
+    Ok(&local == circuit_id)
+}
+
+/// Without the provers there is nothing to synthesize, native builds must be listed.
+#[cfg(not(feature = "node"))]
+fn is_local_native(_circuit_id: &CircuitId) -> Result<bool> {
+    Ok(false)
+}
+
+/// Generates the parameters of a compiled build from the local artifacts,
+/// which the registry checks against the manifest, and hashes them.
+#[cfg(feature = "node")]
+fn local_params_digest(circuit_id: &CircuitId) -> Result<String> {
+    static DIGESTS: OnceLock<Mutex<HashMap<CircuitId, String>>> = OnceLock::new();
+    let cache = DIGESTS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(digest) = cache.lock().map_err(|_| BlockchainError::MutexPoisoned)?.get(circuit_id) {
+        return Ok(digest.clone());
+    }
+    let kind = CircuitKind::from_name(&circuit_id.name)
+        .ok_or_else(|| failure::format_err!("Unknown circuit {}", circuit_id.name))?;
+    let setup = CircuitRegistry::new()?.load(kind, circuit_id.depth)?;
+    if setup.get_circuit_id() != circuit_id {
+        return Err(BlockchainError::ProofVerification {
+            reason: format!("Local build {} differs from {}", setup.get_circuit_id(), circuit_id),
+        }
+        .into());
+    }
+    let digest = PP::from_circuit_setup(&setup).params_digest()?;
+    cache
+        .lock()
+        .map_err(|_| BlockchainError::MutexPoisoned)?
+        .insert(circuit_id.clone(), digest.clone());
+    Ok(digest)
+}
+
+#[cfg(not(feature = "node"))]
+fn local_params_digest(circuit_id: &CircuitId) -> Result<String> {
+    Err(BlockchainError::ProofVerification {
+        reason: format!(
+            "The circuit manifest records no parameter digest for {}, run `cargo run circuit-digests`",
+            circuit_id
+        ),
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_committed_artifacts_match_manifest() {
+        let root = current_dir().unwrap().join("circuits/compile");
+        let manifest = CircuitManifest::load(&root.join(MANIFEST_FILE)).unwrap();
+        let circuit_id = manifest
+            .verify_artifacts(
+                CircuitKind::Inclusion,
+                2,
+                &root.join("depth_2/inclusion.r1cs"),
+                &root.join("depth_2/inclusion_js/inclusion.wasm"),
+                &root.join("depth_2/inclusion.sym"),
+                &[&root.join("depth_2/inclusion_cpp/inclusion.cpp"), &root.join("depth_2/inclusion_cpp/inclusion.dat")],
+            )
+            .unwrap();
+        assert!(manifest.check(&circuit_id).is_ok());
+    }
+
+    #[test]
+    fn test_tampered_artifact_is_rejected() {
+        let root = current_dir().unwrap().join("circuits/compile");
+        let manifest = CircuitManifest::load(&root.join(MANIFEST_FILE)).unwrap();
+        // Swapping the inclusion and liabilities builds must be detected
+        let native: [&Path; 2] = [
+            &root.join("depth_2/inclusion_cpp/inclusion.cpp"),
+            &root.join("depth_2/inclusion_cpp/inclusion.dat"),
+        ];
+        let result = manifest.verify_artifacts(
+            CircuitKind::Inclusion,
+            2,
+            &root.join("depth_2/liabilities_changes_folding.r1cs"),
+            &root.join("depth_2/inclusion_js/inclusion.wasm"),
+            &root.join("depth_2/inclusion.sym"),
+            &native,
+        );
+        assert!(result.is_err());
+        // So must a symbols file from another build
+        let result = manifest.verify_artifacts(
+            CircuitKind::Inclusion,
+            2,
+            &root.join("depth_2/inclusion.r1cs"),
+            &root.join("depth_2/inclusion_js/inclusion.wasm"),
+            &root.join("depth_2/liabilities_changes_folding.sym"),
+            &native,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_labelled_native_circuit_is_not_trusted() {
+        let manifest = CircuitManifest::load_default().unwrap();
+        // The label of a native build, without the digest of real parameters
+        let label = CircuitId::native(CircuitKind::Inclusion, 2, 1);
+        assert!(manifest.check(&label).is_err());
+    }
+}
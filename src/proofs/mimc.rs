@@ -0,0 +1,81 @@
+use ff::PrimeField;
+use num::bigint::Sign;
+use num::{BigInt, Num};
+use pasta_curves::Fq;
+use sha3::{Digest, Keccak256};
+use std::sync::OnceLock;
+
+pub const MIMC_ROUNDS: usize = 220;
+const MIMC_SEED: &[u8] = b"mimcsponge";
+const BN254_SCALAR_MODULUS: &str =
+    "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+pub(crate) fn mimc_constants() -> &'static Vec<Fq> {
+    static CONSTANTS: OnceLock<Vec<Fq>> = OnceLock::new();
+    CONSTANTS.get_or_init(|| {
+        // circomlib derives the round constants from a keccak chain reduced modulo the
+        // BN254 scalar field; those values are below the vesta modulus and are used as-is.
+        // The first and last rounds use no constant.
+        let modulus = BigInt::from_str_radix(BN254_SCALAR_MODULUS, 10).unwrap();
+        let mut constants = vec![Fq::from(0); MIMC_ROUNDS];
+        let mut c = Keccak256::digest(MIMC_SEED);
+        for constant in constants.iter_mut().take(MIMC_ROUNDS - 1).skip(1) {
+            c = Keccak256::digest(c);
+            let reduced = BigInt::from_bytes_be(Sign::Plus, &c) % &modulus;
+            *constant = Fq::from_str_vartime(&reduced.to_string()).unwrap();
+        }
+        constants
+    })
+}
+
+/// circomlib `MiMCSponge(ins.len(), 220, 1)` with `k = 0`, for verifiers that
+/// recompute a circuit's hash outside of it.
+pub fn mimc_sponge(ins: &[Fq]) -> Fq {
+    let mut xl = Fq::from(0);
+    let mut xr = Fq::from(0);
+    for input in ins {
+        xl += *input;
+        for (i, constant) in mimc_constants().iter().enumerate() {
+            let t = xl + *constant;
+            let t2 = t * t;
+            let next = xr + t2 * t2 * t;
+            if i < MIMC_ROUNDS - 1 {
+                xr = xl;
+                xl = next;
+            } else {
+                xr = next;
+            }
+        }
+    }
+    xl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mimc_constants_match_circomlib() {
+        let constants = mimc_constants();
+        assert_eq!(constants[0], Fq::from(0));
+        assert_eq!(
+            constants[1],
+            Fq::from_str_vartime(
+                "7120861356467848435263064379192047478074060781135320967663101236819528304084"
+            )
+            .unwrap()
+        );
+        assert_eq!(constants[MIMC_ROUNDS - 1], Fq::from(0));
+    }
+
+    #[cfg(feature = "node")]
+    #[test]
+    fn test_sponge_matches_witness() {
+        use crate::proofs::witness::{self, Signals};
+        let ins = [Fq::from(1), Fq::from(2), Fq::from(3)];
+        assert_eq!(
+            mimc_sponge(&ins),
+            witness::mimc_sponge(&mut Signals::new(), "hasher", &ins, Fq::from(0))
+        );
+    }
+}
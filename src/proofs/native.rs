@@ -0,0 +1,723 @@
+use crate::errors::BlockchainError;
+use crate::proofs::gadgets::{
+    add, constant, enforce_equal, merkle_sum, mimc_sponge, range_check, switcher, BALANCE_BITS,
+};
+use crate::proofs::manifest::CircuitId;
+use crate::proofs::mimc;
+use crate::proofs::registry::{CircuitKind, MAX_CIRCUIT_DEPTH, MIN_CIRCUIT_DEPTH};
+use crate::proofs::setup::PP;
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use ff::Field;
+use nova_snark::traits::circuit::{StepCircuit, TrivialTestCircuit};
+use nova_snark::PublicParams;
+use pasta_curves::Fq;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Private inputs of one inclusion step, already as field elements.
+#[derive(Debug, Clone)]
+pub struct InclusionStep {
+    pub(crate) user_hash: Fq,
+    pub(crate) user_balance: u64,
+    pub(crate) root_hash: Fq,
+    pub(crate) root_sum: Fq,
+    pub(crate) neighbors_sum: Vec<Fq>,
+    pub(crate) neighbors_hash: Vec<Fq>,
+    pub(crate) neighbors_binary: Vec<Fq>,
+}
+
+/// Private inputs of one liabilities step, already as field elements.
+#[derive(Debug, Clone)]
+pub struct LiabilitiesStep {
+    pub(crate) old_user_hash: Vec<Fq>,
+    pub(crate) old_values: Vec<u64>,
+    pub(crate) new_user_hash: Vec<Fq>,
+    pub(crate) new_values: Vec<u64>,
+    pub(crate) temp_hash: Vec<Fq>,
+    pub(crate) temp_sum: Vec<Fq>,
+    pub(crate) neighbors_sum: Vec<Vec<Fq>>,
+    pub(crate) neighbors_hash: Vec<Vec<Fq>>,
+    pub(crate) neighbors_binary: Vec<Vec<Fq>>,
+}
+
+/// Native counterpart of `inclusion(levels)`. `step` is `None` when the
+/// circuit is only used to derive public parameters.
+#[derive(Debug, Clone)]
+pub struct InclusionCircuit {
+    levels: usize,
+    step: Option<InclusionStep>,
+}
+
+/// Native counterpart of `liabilities(levels, changes)`.
+#[derive(Debug, Clone)]
+pub struct LiabilitiesCircuit {
+    levels: usize,
+    changes: usize,
+    step: Option<LiabilitiesStep>,
+}
+
+/// Inclusion with the balance kept private: proves that the leaf `userHash`
+/// holds at least `threshold` under `(rootHash, sum)`.
+#[derive(Debug, Clone)]
+pub struct ThresholdCircuit {
+    levels: usize,
+    threshold: Option<u64>,
+    step: Option<InclusionStep>,
+}
+
+/// Inclusion of several leaves under one root: each step opens one leaf and
+/// chains `(userHash, userBalance)` into the accumulator `acc`.
+#[derive(Debug, Clone)]
+pub struct BatchInclusionCircuit {
+    levels: usize,
+    step: Option<InclusionStep>,
+}
+
+/// Opens the leaf at `index` under `(rootHash, sum)` and proves its hash is not
+/// `absentHash`. Folded once per leaf of the tree, in index order, it shows that
+/// no leaf has that hash without relying on how the leaves are laid out.
+#[derive(Debug, Clone)]
+pub struct NonMembershipCircuit {
+    levels: usize,
+    step: Option<InclusionStep>,
+}
+
+fn alloc<CS: ConstraintSystem<Fq>>(
+    cs: CS,
+    value: Option<Fq>,
+) -> std::result::Result<AllocatedNum<Fq>, SynthesisError> {
+    AllocatedNum::alloc(cs, || value.ok_or(SynthesisError::AssignmentMissing))
+}
+
+fn alloc_vec<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    name: &str,
+    len: usize,
+    values: Option<&Vec<Fq>>,
+) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+    (0..len)
+        .map(|i| alloc(cs.namespace(|| format!("{}[{}]", name, i)), values.map(|v| v[i])))
+        .collect()
+}
+
+/// Walks a Merkle sum path from a leaf and returns the `(root, sum)` it opens to.
+fn open_path<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    leaf_hash: &AllocatedNum<Fq>,
+    leaf_sum: &AllocatedNum<Fq>,
+    neighbors_hash: &[AllocatedNum<Fq>],
+    neighbors_sum: &[AllocatedNum<Fq>],
+    neighbors_binary: &[AllocatedNum<Fq>],
+) -> std::result::Result<(AllocatedNum<Fq>, AllocatedNum<Fq>), SynthesisError> {
+    let mut hash_node = leaf_hash.clone();
+    let mut sum_node = leaf_sum.clone();
+    for i in 0..neighbors_hash.len() {
+        let mut cs = cs.namespace(|| format!("level {}", i));
+        let (hash_l, hash_r) = switcher(
+            cs.namespace(|| "switcherHash"),
+            &neighbors_binary[i],
+            &hash_node,
+            &neighbors_hash[i],
+        )?;
+        let (sum_l, sum_r) = switcher(
+            cs.namespace(|| "switcherSum"),
+            &neighbors_binary[i],
+            &sum_node,
+            &neighbors_sum[i],
+        )?;
+        (hash_node, sum_node) = merkle_sum(cs.namespace(|| "merklesum"), &hash_l, &hash_r, &sum_l, &sum_r)?;
+    }
+    Ok((hash_node, sum_node))
+}
+
+/// Recomposes a leaf index from the path bits, leaf level first.
+fn path_index<CS: ConstraintSystem<Fq>>(
+    mut cs: CS,
+    neighbors_binary: &[AllocatedNum<Fq>],
+) -> std::result::Result<AllocatedNum<Fq>, SynthesisError> {
+    let mut index = constant(cs.namespace(|| "zero"), Fq::from(0))?;
+    let mut weight = Fq::from(1);
+    for (i, bit) in neighbors_binary.iter().enumerate() {
+        let term = alloc(cs.namespace(|| format!("term {}", i)), bit.get_value().map(|b| b * weight))?;
+        cs.enforce(
+            || format!("term {} = bit * 2^{}", i, i),
+            |lc| lc + (weight, bit.get_variable()),
+            |lc| lc + CS::one(),
+            |lc| lc + term.get_variable(),
+        );
+        index = add(cs.namespace(|| format!("index {}", i)), &index, &term)?;
+        weight = weight + weight;
+    }
+    Ok(index)
+}
+
+impl InclusionCircuit {
+    pub fn new(levels: usize, step: InclusionStep) -> InclusionCircuit {
+        InclusionCircuit {
+            levels,
+            step: Some(step),
+        }
+    }
+
+    pub fn blank(levels: usize) -> InclusionCircuit {
+        InclusionCircuit { levels, step: None }
+    }
+}
+
+impl StepCircuit<Fq> for InclusionCircuit {
+    fn arity(&self) -> usize {
+        4
+    }
+
+    /// `z` is `[sum, rootHash, userHash, acc]` of the previous step, only `acc` is read.
+    fn synthesize<CS: ConstraintSystem<Fq>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<Fq>],
+    ) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+        let step = self.step.as_ref();
+        let levels = self.levels;
+        let neighbors_sum = alloc_vec(cs.namespace(|| "neighborsSum"), "neighborsSum", levels, step.map(|s| &s.neighbors_sum))?;
+        let neighbors_hash = alloc_vec(cs.namespace(|| "neighborsHash"), "neighborsHash", levels, step.map(|s| &s.neighbors_hash))?;
+        let neighbors_binary = alloc_vec(cs.namespace(|| "neighborsBinary"), "neighborsBinary", levels, step.map(|s| &s.neighbors_binary))?;
+        let sum = alloc(cs.namespace(|| "sum"), step.map(|s| s.root_sum))?;
+        let root_hash = alloc(cs.namespace(|| "rootHash"), step.map(|s| s.root_hash))?;
+        let user_balance = alloc(cs.namespace(|| "userBalance"), step.map(|s| Fq::from(s.user_balance)))?;
+        let user_hash = alloc(cs.namespace(|| "userHash"), step.map(|s| s.user_hash))?;
+
+        let (hash_node, sum_node) = open_path(
+            cs.namespace(|| "path"),
+            &user_hash,
+            &user_balance,
+            &neighbors_hash,
+            &neighbors_sum,
+            &neighbors_binary,
+        )?;
+        enforce_equal(cs.namespace(|| "root hash"), &hash_node, &root_hash);
+        enforce_equal(cs.namespace(|| "root sum"), &sum_node, &sum);
+        let acc = mimc_sponge(
+            cs.namespace(|| "acc"),
+            &[z[3].clone(), sum.clone(), root_hash.clone(), user_balance, user_hash.clone()],
+        )?;
+
+        Ok(vec![sum, root_hash, user_hash, acc])
+    }
+
+    fn output(&self, z: &[Fq]) -> Vec<Fq> {
+        match &self.step {
+            Some(step) => {
+                let acc = mimc::mimc_sponge(&[
+                    z[3],
+                    step.root_sum,
+                    step.root_hash,
+                    Fq::from(step.user_balance),
+                    step.user_hash,
+                ]);
+                vec![step.root_sum, step.root_hash, step.user_hash, acc]
+            }
+            None => z.to_vec(),
+        }
+    }
+}
+
+impl ThresholdCircuit {
+    pub fn new(levels: usize, threshold: u64, step: InclusionStep) -> ThresholdCircuit {
+        ThresholdCircuit {
+            levels,
+            threshold: Some(threshold),
+            step: Some(step),
+        }
+    }
+
+    pub fn blank(levels: usize) -> ThresholdCircuit {
+        ThresholdCircuit {
+            levels,
+            threshold: None,
+            step: None,
+        }
+    }
+}
+
+impl StepCircuit<Fq> for ThresholdCircuit {
+    fn arity(&self) -> usize {
+        4
+    }
+
+    /// `z` is the public `[threshold, rootHash, sum, userHash]` and is passed through.
+    fn synthesize<CS: ConstraintSystem<Fq>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<Fq>],
+    ) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+        let step = self.step.as_ref();
+        let levels = self.levels;
+        let neighbors_sum = alloc_vec(cs.namespace(|| "neighborsSum"), "neighborsSum", levels, step.map(|s| &s.neighbors_sum))?;
+        let neighbors_hash = alloc_vec(cs.namespace(|| "neighborsHash"), "neighborsHash", levels, step.map(|s| &s.neighbors_hash))?;
+        let neighbors_binary = alloc_vec(cs.namespace(|| "neighborsBinary"), "neighborsBinary", levels, step.map(|s| &s.neighbors_binary))?;
+        let user_balance = alloc(cs.namespace(|| "userBalance"), step.map(|s| Fq::from(s.user_balance)))?;
+        range_check(
+            cs.namespace(|| "balanceCheck"),
+            &user_balance,
+            step.map(|s| s.user_balance),
+            BALANCE_BITS,
+        )?;
+
+        let (hash_node, sum_node) = open_path(
+            cs.namespace(|| "path"),
+            &z[3],
+            &user_balance,
+            &neighbors_hash,
+            &neighbors_sum,
+            &neighbors_binary,
+        )?;
+        enforce_equal(cs.namespace(|| "root hash"), &hash_node, &z[1]);
+        enforce_equal(cs.namespace(|| "root sum"), &sum_node, &z[2]);
+
+        // userBalance - threshold fits in BALANCE_BITS only if it is not negative
+        let excess = alloc(
+            cs.namespace(|| "excess"),
+            user_balance.get_value().zip(z[0].get_value()).map(|(b, t)| b - t),
+        )?;
+        cs.enforce(
+            || "excess = userBalance - threshold",
+            |lc| lc + user_balance.get_variable() - z[0].get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + excess.get_variable(),
+        );
+        let raw_excess = step
+            .zip(self.threshold)
+            .and_then(|(s, threshold)| s.user_balance.checked_sub(threshold));
+        range_check(cs.namespace(|| "excessCheck"), &excess, raw_excess, BALANCE_BITS)?;
+
+        Ok(z.to_vec())
+    }
+
+    fn output(&self, z: &[Fq]) -> Vec<Fq> {
+        z.to_vec()
+    }
+}
+
+impl BatchInclusionCircuit {
+    pub fn new(levels: usize, step: InclusionStep) -> BatchInclusionCircuit {
+        BatchInclusionCircuit {
+            levels,
+            step: Some(step),
+        }
+    }
+
+    pub fn blank(levels: usize) -> BatchInclusionCircuit {
+        BatchInclusionCircuit { levels, step: None }
+    }
+
+    /// `acc` after a step that opened `user_hash` with `user_balance`.
+    pub fn chain(acc: Fq, user_hash: Fq, user_balance: u64) -> Fq {
+        mimc::mimc_sponge(&[acc, user_hash, Fq::from(user_balance)])
+    }
+}
+
+impl StepCircuit<Fq> for BatchInclusionCircuit {
+    fn arity(&self) -> usize {
+        3
+    }
+
+    /// `z` is `[rootHash, sum, acc]`, the roots are passed through.
+    fn synthesize<CS: ConstraintSystem<Fq>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<Fq>],
+    ) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+        let step = self.step.as_ref();
+        let levels = self.levels;
+        let neighbors_sum = alloc_vec(cs.namespace(|| "neighborsSum"), "neighborsSum", levels, step.map(|s| &s.neighbors_sum))?;
+        let neighbors_hash = alloc_vec(cs.namespace(|| "neighborsHash"), "neighborsHash", levels, step.map(|s| &s.neighbors_hash))?;
+        let neighbors_binary = alloc_vec(cs.namespace(|| "neighborsBinary"), "neighborsBinary", levels, step.map(|s| &s.neighbors_binary))?;
+        let user_balance = alloc(cs.namespace(|| "userBalance"), step.map(|s| Fq::from(s.user_balance)))?;
+        let user_hash = alloc(cs.namespace(|| "userHash"), step.map(|s| s.user_hash))?;
+
+        let (hash_node, sum_node) = open_path(
+            cs.namespace(|| "path"),
+            &user_hash,
+            &user_balance,
+            &neighbors_hash,
+            &neighbors_sum,
+            &neighbors_binary,
+        )?;
+        enforce_equal(cs.namespace(|| "root hash"), &hash_node, &z[0]);
+        enforce_equal(cs.namespace(|| "root sum"), &sum_node, &z[1]);
+        let acc = mimc_sponge(cs.namespace(|| "acc"), &[z[2].clone(), user_hash, user_balance])?;
+
+        Ok(vec![z[0].clone(), z[1].clone(), acc])
+    }
+
+    fn output(&self, z: &[Fq]) -> Vec<Fq> {
+        match &self.step {
+            Some(step) => vec![z[0], z[1], BatchInclusionCircuit::chain(z[2], step.user_hash, step.user_balance)],
+            None => z.to_vec(),
+        }
+    }
+}
+
+impl NonMembershipCircuit {
+    pub fn new(levels: usize, step: InclusionStep) -> NonMembershipCircuit {
+        NonMembershipCircuit {
+            levels,
+            step: Some(step),
+        }
+    }
+
+    pub fn blank(levels: usize) -> NonMembershipCircuit {
+        NonMembershipCircuit { levels, step: None }
+    }
+}
+
+impl StepCircuit<Fq> for NonMembershipCircuit {
+    fn arity(&self) -> usize {
+        4
+    }
+
+    /// `z` is `[rootHash, sum, absentHash, index]`, where `index` is the leaf
+    /// opened by this step. Only `index` changes, it moves to the next leaf.
+    fn synthesize<CS: ConstraintSystem<Fq>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<Fq>],
+    ) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+        let step = self.step.as_ref();
+        let levels = self.levels;
+        let neighbors_sum = alloc_vec(cs.namespace(|| "neighborsSum"), "neighborsSum", levels, step.map(|s| &s.neighbors_sum))?;
+        let neighbors_hash = alloc_vec(cs.namespace(|| "neighborsHash"), "neighborsHash", levels, step.map(|s| &s.neighbors_hash))?;
+        let neighbors_binary = alloc_vec(cs.namespace(|| "neighborsBinary"), "neighborsBinary", levels, step.map(|s| &s.neighbors_binary))?;
+        let balance = alloc(cs.namespace(|| "balance"), step.map(|s| Fq::from(s.user_balance)))?;
+        let leaf_hash = alloc(cs.namespace(|| "leafHash"), step.map(|s| s.user_hash))?;
+
+        let (hash_node, sum_node) = open_path(
+            cs.namespace(|| "path"),
+            &leaf_hash,
+            &balance,
+            &neighbors_hash,
+            &neighbors_sum,
+            &neighbors_binary,
+        )?;
+        enforce_equal(cs.namespace(|| "root hash"), &hash_node, &z[0]);
+        enforce_equal(cs.namespace(|| "root sum"), &sum_node, &z[1]);
+        let index = path_index(cs.namespace(|| "index"), &neighbors_binary)?;
+        enforce_equal(cs.namespace(|| "leaf index"), &index, &z[3]);
+
+        // leafHash - absentHash has an inverse only if it is not zero
+        let inverse = alloc(
+            cs.namespace(|| "inverse"),
+            leaf_hash
+                .get_value()
+                .zip(z[2].get_value())
+                .map(|(leaf, absent)| (leaf - absent).invert().unwrap_or(Fq::zero())),
+        )?;
+        cs.enforce(
+            || "(leafHash - absentHash) * inverse = 1",
+            |lc| lc + leaf_hash.get_variable() - z[2].get_variable(),
+            |lc| lc + inverse.get_variable(),
+            |lc| lc + CS::one(),
+        );
+
+        let next_index = alloc(cs.namespace(|| "nextIndex"), z[3].get_value().map(|i| i + Fq::one()))?;
+        cs.enforce(
+            || "nextIndex = index + 1",
+            |lc| lc + z[3].get_variable() + CS::one(),
+            |lc| lc + CS::one(),
+            |lc| lc + next_index.get_variable(),
+        );
+
+        Ok(vec![z[0].clone(), z[1].clone(), z[2].clone(), next_index])
+    }
+
+    fn output(&self, z: &[Fq]) -> Vec<Fq> {
+        match &self.step {
+            Some(_) => vec![z[0], z[1], z[2], z[3] + Fq::one()],
+            None => z.to_vec(),
+        }
+    }
+}
+
+impl LiabilitiesCircuit {
+    pub fn new(levels: usize, changes: usize, step: LiabilitiesStep) -> LiabilitiesCircuit {
+        LiabilitiesCircuit {
+            levels,
+            changes,
+            step: Some(step),
+        }
+    }
+
+    pub fn blank(levels: usize, changes: usize) -> LiabilitiesCircuit {
+        LiabilitiesCircuit {
+            levels,
+            changes,
+            step: None,
+        }
+    }
+}
+
+impl StepCircuit<Fq> for LiabilitiesCircuit {
+    fn arity(&self) -> usize {
+        4
+    }
+
+    fn synthesize<CS: ConstraintSystem<Fq>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<Fq>],
+    ) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+        let step = self.step.as_ref();
+        let changes = self.changes;
+        let temp_hash = alloc_vec(cs.namespace(|| "tempHash"), "tempHash", changes + 1, step.map(|s| &s.temp_hash))?;
+        let temp_sum = alloc_vec(cs.namespace(|| "tempSum"), "tempSum", changes + 1, step.map(|s| &s.temp_sum))?;
+        enforce_equal(cs.namespace(|| "old root hash"), &z[2], &temp_hash[0]);
+        enforce_equal(cs.namespace(|| "old root sum"), &z[3], &temp_sum[0]);
+
+        let mut current_sum = z[3].clone();
+        for j in 0..changes {
+            let mut cs = cs.namespace(|| format!("change {}", j));
+            let old_hash = alloc(cs.namespace(|| "oldUserHash"), step.map(|s| s.old_user_hash[j]))?;
+            let old_value = alloc(cs.namespace(|| "oldValues"), step.map(|s| Fq::from(s.old_values[j])))?;
+            let new_hash = alloc(cs.namespace(|| "newUserHash"), step.map(|s| s.new_user_hash[j]))?;
+            let new_value = alloc(cs.namespace(|| "newValues"), step.map(|s| Fq::from(s.new_values[j])))?;
+            let neighbors_sum = alloc_vec(cs.namespace(|| "neighborsSum"), "neighborsSum", self.levels, step.map(|s| &s.neighbors_sum[j]))?;
+            let neighbors_hash = alloc_vec(cs.namespace(|| "neighborsHash"), "neighborsHash", self.levels, step.map(|s| &s.neighbors_hash[j]))?;
+            let neighbors_binary = alloc_vec(cs.namespace(|| "neighborsBinary"), "neighborsBinary", self.levels, step.map(|s| &s.neighbors_binary[j]))?;
+
+            range_check(
+                cs.namespace(|| "balanceCheck"),
+                &new_value,
+                step.map(|s| s.new_values[j]),
+                BALANCE_BITS,
+            )?;
+
+            // currentSum + newValues[j] - oldValues[j]
+            let with_new = add(cs.namespace(|| "add new value"), &current_sum, &new_value)?;
+            let next_sum = alloc(
+                cs.namespace(|| "currentSum"),
+                with_new.get_value().zip(old_value.get_value()).map(|(a, b)| a - b),
+            )?;
+            cs.enforce(
+                || "currentSum = previous + new - old",
+                |lc| lc + with_new.get_variable() - old_value.get_variable(),
+                |lc| lc + CS::one(),
+                |lc| lc + next_sum.get_variable(),
+            );
+            current_sum = next_sum;
+
+            let (old_root, old_sum) = open_path(
+                cs.namespace(|| "old path"),
+                &old_hash,
+                &old_value,
+                &neighbors_hash,
+                &neighbors_sum,
+                &neighbors_binary,
+            )?;
+            enforce_equal(cs.namespace(|| "old root hash"), &old_root, &temp_hash[j]);
+            enforce_equal(cs.namespace(|| "old root sum"), &old_sum, &temp_sum[j]);
+
+            let (new_root, new_sum) = open_path(
+                cs.namespace(|| "new path"),
+                &new_hash,
+                &new_value,
+                &neighbors_hash,
+                &neighbors_sum,
+                &neighbors_binary,
+            )?;
+            enforce_equal(cs.namespace(|| "new root hash"), &new_root, &temp_hash[j + 1]);
+            enforce_equal(cs.namespace(|| "new root sum"), &new_sum, &temp_sum[j + 1]);
+        }
+        enforce_equal(cs.namespace(|| "new sum"), &current_sum, &temp_sum[changes]);
+
+        Ok(vec![
+            z[0].clone(),
+            z[1].clone(),
+            temp_hash[changes].clone(),
+            temp_sum[changes].clone(),
+        ])
+    }
+
+    fn output(&self, z: &[Fq]) -> Vec<Fq> {
+        match &self.step {
+            Some(step) => vec![
+                z[0],
+                z[1],
+                step.temp_hash[self.changes],
+                step.temp_sum[self.changes],
+            ],
+            None => z.to_vec(),
+        }
+    }
+}
+
+/// Drop-in alternative to `CircuitSetup` that synthesizes the circuits in Rust
+/// instead of loading circom artifacts, so any depth and batch size can be used.
+#[derive(Debug, Clone)]
+pub struct NativeCircuitSetup {
+    kind: CircuitKind,
+    depth: usize,
+    changes_per_step: usize,
+    circuit_id: CircuitId,
+}
+
+impl NativeCircuitSetup {
+    pub fn new(kind: CircuitKind, depth: usize, changes_per_step: usize) -> Result<NativeCircuitSetup> {
+        if !(MIN_CIRCUIT_DEPTH..=MAX_CIRCUIT_DEPTH).contains(&depth) {
+            return Err(BlockchainError::CircuitSetup(format!(
+                "Unsupported tree depth {}, native circuits support {} to {}",
+                depth, MIN_CIRCUIT_DEPTH, MAX_CIRCUIT_DEPTH
+            ))
+            .into());
+        }
+        if changes_per_step == 0 {
+            return Err(BlockchainError::CircuitSetup(
+                "A liabilities step needs at least one change".to_string(),
+            )
+            .into());
+        }
+        let changes_per_step = match kind {
+            CircuitKind::Inclusion
+            | CircuitKind::InclusionThreshold
+            | CircuitKind::BatchInclusion
+            | CircuitKind::NonMembership => 1,
+            CircuitKind::LiabilitiesChangesFolding => changes_per_step,
+            CircuitKind::Solvency => {
+                return Err(BlockchainError::CircuitSetup(
+                    "The solvency circuit does not depend on the tree, use SolvencyCircuit::params".to_string(),
+                )
+                .into())
+            }
+        };
+        Ok(NativeCircuitSetup {
+            kind,
+            depth,
+            changes_per_step,
+            circuit_id: CircuitId::native(kind, depth, changes_per_step),
+        })
+    }
+
+    pub fn get_kind(&self) -> CircuitKind {
+        self.kind
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get_changes_per_step(&self) -> usize {
+        self.changes_per_step
+    }
+
+    /// The label of this setup. Proofs carry the identity of the parameters
+    /// instead, see `params_circuit_id`.
+    pub fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+
+    pub fn inclusion_params(&self) -> Result<PP<InclusionCircuit>> {
+        let pp = PublicParams::setup(
+            InclusionCircuit::blank(self.depth),
+            TrivialTestCircuit::default(),
+        );
+        PP::from_public_params(pp, self.circuit_id.clone()).identified_by_digest()
+    }
+
+    pub fn threshold_params(&self) -> Result<PP<ThresholdCircuit>> {
+        let pp = PublicParams::setup(ThresholdCircuit::blank(self.depth), TrivialTestCircuit::default());
+        PP::from_public_params(pp, self.circuit_id.clone()).identified_by_digest()
+    }
+
+    pub fn batch_inclusion_params(&self) -> Result<PP<BatchInclusionCircuit>> {
+        let pp = PublicParams::setup(BatchInclusionCircuit::blank(self.depth), TrivialTestCircuit::default());
+        PP::from_public_params(pp, self.circuit_id.clone()).identified_by_digest()
+    }
+
+    pub fn non_membership_params(&self) -> Result<PP<NonMembershipCircuit>> {
+        let pp = PublicParams::setup(NonMembershipCircuit::blank(self.depth), TrivialTestCircuit::default());
+        PP::from_public_params(pp, self.circuit_id.clone()).identified_by_digest()
+    }
+
+    pub fn liabilities_params(&self) -> Result<PP<LiabilitiesCircuit>> {
+        let pp = PublicParams::setup(
+            LiabilitiesCircuit::blank(self.depth, self.changes_per_step),
+            TrivialTestCircuit::default(),
+        );
+        PP::from_public_params(pp, self.circuit_id.clone()).identified_by_digest()
+    }
+
+    /// The identity of this setup's parameters, which carries their digest.
+    /// Generates the parameters, so it is as slow as the `*_params` methods.
+    pub fn params_circuit_id(&self) -> Result<CircuitId> {
+        let circuit_id = match self.kind {
+            CircuitKind::Inclusion => self.inclusion_params()?.get_circuit_id().clone(),
+            CircuitKind::InclusionThreshold => self.threshold_params()?.get_circuit_id().clone(),
+            CircuitKind::BatchInclusion => self.batch_inclusion_params()?.get_circuit_id().clone(),
+            CircuitKind::NonMembership => self.non_membership_params()?.get_circuit_id().clone(),
+            CircuitKind::LiabilitiesChangesFolding => self.liabilities_params()?.get_circuit_id().clone(),
+            CircuitKind::Solvency => {
+                return Err(BlockchainError::CircuitSetup("The solvency circuit has no native setup".to_string()).into())
+            }
+        };
+        Ok(circuit_id)
+    }
+}
+
+#[cfg(all(test, feature = "node"))]
+mod tests {
+    use super::*;
+    use crate::proofs::witness::{self, Signals};
+    use bellpepper_core::test_cs::TestConstraintSystem;
+
+    #[test]
+    fn test_native_setup_rejects_bad_parameters() {
+        assert!(NativeCircuitSetup::new(CircuitKind::Inclusion, 1, 1).is_err());
+        assert!(NativeCircuitSetup::new(CircuitKind::LiabilitiesChangesFolding, 3, 0).is_err());
+        let setup = NativeCircuitSetup::new(CircuitKind::LiabilitiesChangesFolding, 3, 4).unwrap();
+        assert_eq!(setup.get_circuit_id().get_changes_per_step(), 4);
+        assert!(setup.get_circuit_id().is_native());
+    }
+
+    #[test]
+    fn test_inclusion_circuit_rejects_wrong_root() {
+        let mut cs = TestConstraintSystem::<Fq>::new();
+        let step = InclusionStep {
+            user_hash: Fq::from(1),
+            user_balance: 5,
+            root_hash: Fq::from(2),
+            root_sum: Fq::from(12),
+            neighbors_sum: vec![Fq::from(7), Fq::from(0)],
+            neighbors_hash: vec![Fq::from(3), Fq::from(4)],
+            neighbors_binary: vec![Fq::from(0), Fq::from(1)],
+        };
+        let z: Vec<AllocatedNum<Fq>> = (0..4)
+            .map(|i| AllocatedNum::alloc(cs.namespace(|| format!("z{}", i)), || Ok(Fq::from(0))).unwrap())
+            .collect();
+        InclusionCircuit::new(2, step).synthesize(&mut cs, &z).unwrap();
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_non_membership_circuit_rejects_absent_hash() {
+        // A one-level tree of two leaves with balances 5 and 7, opening leaf 0
+        let (root_hash, root_sum) = witness::merkle_sum(&mut Signals::new(), "root", Fq::from(1), Fq::from(2), Fq::from(5), Fq::from(7));
+        let step = InclusionStep {
+            user_hash: Fq::from(1),
+            user_balance: 5,
+            root_hash,
+            root_sum,
+            neighbors_sum: vec![Fq::from(7)],
+            neighbors_hash: vec![Fq::from(2)],
+            neighbors_binary: vec![Fq::from(0)],
+        };
+        for (absent, satisfied) in [(Fq::from(9), true), (Fq::from(1), false)] {
+            let mut cs = TestConstraintSystem::<Fq>::new();
+            let z: Vec<AllocatedNum<Fq>> = [root_hash, root_sum, absent, Fq::from(0)]
+                .iter()
+                .enumerate()
+                .map(|(i, v)| AllocatedNum::alloc(cs.namespace(|| format!("z{}", i)), || Ok(*v)).unwrap())
+                .collect();
+            NonMembershipCircuit::new(1, step.clone()).synthesize(&mut cs, &z).unwrap();
+            assert_eq!(cs.is_satisfied(), satisfied);
+        }
+    }
+}
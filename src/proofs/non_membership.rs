@@ -0,0 +1,229 @@
+use crate::errors::BlockchainError;
+use crate::proofs::bundle::BlockWrapper;
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::manifest::CircuitId;
+use crate::proofs::native::{NativeCircuitSetup, NonMembershipCircuit};
+use crate::proofs::registry::CircuitKind;
+use crate::proofs::setup::{verify_compressed, CompressedProof};
+use crate::proofs::util::field_from_hex;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[cfg(feature = "node")]
+use crate::proofs::inclusion::InclusionInput;
+#[cfg(feature = "node")]
+use crate::proofs::registry::tree_depth;
+#[cfg(feature = "node")]
+use crate::proofs::setup::{compress, fold_steps};
+#[cfg(feature = "node")]
+use merkle_sum_tree::{Leaf, MerkleSumTree};
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Proof that no leaf of the tree of `header` has the ID `leaf_id`.
+///
+/// Every leaf is opened under the block root, one folding step per leaf in
+/// index order, and shown to have another hash, so the proof does not depend
+/// on how the node lays out its leaves. It is compressed, so none of the
+/// opened leaves or balances are disclosed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProofOfNonMembership {
+    compressed_snark: CompressedProof<NonMembershipCircuit>,
+    circuit_id: CircuitId,
+    leaf_id: String,
+    /// Hash of the leaf `leaf_id` would have, the public input the proof is for.
+    leaf_hash: String,
+    header: BlockWrapper,
+}
+
+/// What a non-membership proof was verified to show.
+#[derive(Debug)]
+pub struct NonMembershipOutput {
+    leaf_id: String,
+    block_number: i32,
+    root_hash: String,
+}
+
+/// Hash of the leaf with `id`. Leaf hashes depend on the ID only, the balance
+/// is summed separately.
+#[cfg(feature = "node")]
+fn leaf_hash(id: &str) -> Fq {
+    field_from_hex(&Leaf::new(id.to_string(), 0).get_node().get_hash().to_string())
+}
+
+impl ProofOfNonMembership {
+    #[cfg(feature = "node")]
+    /// `tree` must be the tree of `header`.
+    pub fn new(
+        tree: &MerkleSumTree,
+        leaf_id: &str,
+        header: BlockWrapper,
+        circuit_setup: &NativeCircuitSetup,
+    ) -> Result<ProofOfNonMembership> {
+        if circuit_setup.get_kind() != CircuitKind::NonMembership {
+            return Err(failure::format_err!(
+                "Expected a non-membership setup, got {}",
+                circuit_setup.get_kind().name()
+            ));
+        }
+        let leafs = tree.get_leafs();
+        if leafs.iter().any(|leaf| leaf.get_id() == leaf_id) {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Leaf {} is in the tree of block {}",
+                leaf_id,
+                header.get_block_number()
+            ))
+            .into());
+        }
+        let inputs = (0..leafs.len())
+            .map(|index| InclusionInput::new(tree, index))
+            .collect::<Result<Vec<InclusionInput>>>()?;
+        if inputs[0].get_root_hash() != header.get_root_hash() {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Tree has root {} but block {} has root {}",
+                inputs[0].get_root_hash(),
+                header.get_block_number(),
+                header.get_root_hash()
+            ))
+            .into());
+        }
+
+        let pp = circuit_setup.non_membership_params()?;
+        let start_proof = Instant::now();
+        let depth = tree_depth(tree);
+        let circuits: Vec<NonMembershipCircuit> = inputs
+            .iter()
+            .map(|input| NonMembershipCircuit::new(depth, input.to_native_step()))
+            .collect();
+        let hash = leaf_hash(leaf_id);
+        let start_public_input = public_input(&header, hash);
+        let recursive_snark = fold_steps(pp.get_pp(), &circuits, start_public_input.to_vec())?;
+        let compressed_snark = compress(&pp, &recursive_snark)?;
+        println!("CompressedSNARK::proof took {:?}", start_proof.elapsed());
+
+        Ok(ProofOfNonMembership {
+            compressed_snark,
+            circuit_id: pp.get_circuit_id().clone(),
+            leaf_id: leaf_id.to_string(),
+            leaf_hash: format!("{:?}", hash),
+            header,
+        })
+    }
+
+    /// Checks that all `2^depth` leaves were opened under the block root, each
+    /// with a hash other than the one of `leaf_id`. Leaf hashes come from the
+    /// merkle sum tree, so verifier builds check the proof against the carried
+    /// `leaf_hash` and leave binding it to `leaf_id` to the node build.
+    /// `header` is the trusted block the proof must be for.
+    pub fn verify(&self, header: &BlockWrapper) -> Result<NonMembershipOutput> {
+        if self.header.get_block_number() != header.get_block_number()
+            || self.header.get_root_hash() != header.get_root_hash()
+            || self.header.get_root_sum() != header.get_root_sum()
+        {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof is for root {} of block {}, the trusted block {} has root {}",
+                    self.header.get_root_hash(),
+                    self.header.get_block_number(),
+                    header.get_block_number(),
+                    header.get_root_hash()
+                ),
+            }
+            .into());
+        }
+        let start = Instant::now();
+        #[cfg(feature = "node")]
+        self.check_leaf_hash()?;
+        let depth = self.circuit_id.get_depth();
+        let circuit_setup = NativeCircuitSetup::new(CircuitKind::NonMembership, depth, 1)?;
+        // The identity of locally generated parameters carries their digest
+        let pp = circuit_setup.non_membership_params()?;
+        if &self.circuit_id != pp.get_circuit_id() {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof was made with non-membership circuit {}, this build has {}",
+                    self.circuit_id,
+                    pp.get_circuit_id()
+                ),
+            }
+            .into());
+        }
+        let leaves = 1usize << depth;
+        let z0 = public_input(header, field_from_hex(&self.leaf_hash));
+        let output = verify_compressed(&pp, &self.compressed_snark, leaves, &z0).map_err(|e| {
+            BlockchainError::ProofVerification {
+                reason: format!("Non-membership proof rejected: {}", e),
+            }
+        })?;
+        // Each step opens the leaf at the running index and moves it by one
+        let expected = [z0[0], z0[1], z0[2], Fq::from(leaves as u64)];
+        if output != expected {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!("Proof does not cover the {} leaves of the tree", leaves),
+            }
+            .into());
+        }
+        println!("Non-membership proof verified successfully in {:?}", start.elapsed());
+
+        Ok(NonMembershipOutput {
+            leaf_id: self.leaf_id.clone(),
+            block_number: header.get_block_number(),
+            root_hash: header.get_root_hash().to_string(),
+        })
+    }
+
+    #[cfg(feature = "node")]
+    fn check_leaf_hash(&self) -> Result<()> {
+        if format!("{:?}", leaf_hash(&self.leaf_id)) != self.leaf_hash {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!("Proof is for leaf hash {}, not the hash of {}", self.leaf_hash, self.leaf_id),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn get_leaf_id(&self) -> &str {
+        &self.leaf_id
+    }
+
+    pub fn get_leaf_hash(&self) -> &str {
+        &self.leaf_hash
+    }
+
+    pub fn get_header(&self) -> &BlockWrapper {
+        &self.header
+    }
+}
+
+impl NonMembershipOutput {
+    pub fn get_leaf_id(&self) -> &str {
+        &self.leaf_id
+    }
+
+    pub fn get_block_number(&self) -> i32 {
+        self.block_number
+    }
+
+    pub fn get_root_hash(&self) -> &str {
+        &self.root_hash
+    }
+}
+
+fn public_input(header: &BlockWrapper, leaf_hash: Fq) -> [Fq; 4] {
+    [
+        field_from_hex(header.get_root_hash()),
+        Fq::from(header.get_root_sum() as u64),
+        leaf_hash,
+        Fq::from(0),
+    ]
+}
+
+impl ProofEncoding for ProofOfNonMembership {
+    const KIND: PayloadKind = PayloadKind::NonMembershipProof;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+}
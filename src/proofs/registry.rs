@@ -0,0 +1,261 @@
+#[cfg(feature = "node")]
+use crate::errors::BlockchainError;
+#[cfg(feature = "node")]
+use crate::proofs::manifest::{CircuitManifest, DEFAULT_CIRCUITS_DIR, MANIFEST_FILE};
+#[cfg(feature = "node")]
+use crate::proofs::manifest::CircuitId;
+#[cfg(feature = "node")]
+use crate::proofs::setup::{CircuitSetup, PP};
+#[cfg(feature = "node")]
+use merkle_sum_tree::MerkleSumTree;
+#[cfg(feature = "node")]
+use std::collections::HashMap;
+#[cfg(feature = "node")]
+use std::env::current_dir;
+#[cfg(feature = "node")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "node")]
+use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+pub const MIN_CIRCUIT_DEPTH: usize = 2;
+pub const MAX_CIRCUIT_DEPTH: usize = 10;
+pub const DEFAULT_CIRCUIT_DEPTH: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CircuitKind {
+    Inclusion,
+    LiabilitiesChangesFolding,
+    /// Native only, there are no circom artifacts for these.
+    InclusionThreshold,
+    Solvency,
+    NonMembership,
+    BatchInclusion,
+}
+
+impl CircuitKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CircuitKind::Inclusion => "inclusion",
+            CircuitKind::LiabilitiesChangesFolding => "liabilities_changes_folding",
+            CircuitKind::InclusionThreshold => "inclusion_threshold",
+            CircuitKind::Solvency => "solvency",
+            CircuitKind::NonMembership => "non_membership",
+            CircuitKind::BatchInclusion => "batch_inclusion",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CircuitKind> {
+        match name {
+            "inclusion" => Some(CircuitKind::Inclusion),
+            "liabilities_changes_folding" => Some(CircuitKind::LiabilitiesChangesFolding),
+            "inclusion_threshold" => Some(CircuitKind::InclusionThreshold),
+            "solvency" => Some(CircuitKind::Solvency),
+            "non_membership" => Some(CircuitKind::NonMembership),
+            "batch_inclusion" => Some(CircuitKind::BatchInclusion),
+            _ => None,
+        }
+    }
+}
+
+/// Which circuits the node proves inclusion and liabilities with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CircuitSource {
+    /// The circom builds listed in the manifest.
+    #[default]
+    Compiled,
+    /// The bellpepper circuits in `native`, available at every depth.
+    Native,
+}
+
+impl CircuitSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CircuitSource::Compiled => "compiled",
+            CircuitSource::Native => "native",
+        }
+    }
+}
+
+impl FromStr for CircuitSource {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<CircuitSource> {
+        match s {
+            "compiled" => Ok(CircuitSource::Compiled),
+            "native" => Ok(CircuitSource::Native),
+            _ => Err(failure::format_err!(
+                "Unknown circuit source '{}', expected compiled or native",
+                s
+            )),
+        }
+    }
+}
+
+/// Compiled circuits, one build per tree depth, laid out as
+/// `<root>/depth_<N>/<name>.r1cs` and `<root>/depth_<N>/<name>_js/<name>.wasm`
+/// and listed in `<root>/manifest.json`.
+/// Loaded setups are cached since reading an R1CS is slow.
+#[cfg(feature = "node")]
+pub struct CircuitRegistry {
+    root: PathBuf,
+    loaded: Mutex<HashMap<(CircuitKind, usize), Arc<CircuitSetup>>>,
+}
+
+#[cfg(feature = "node")]
+impl CircuitRegistry {
+    pub fn new() -> Result<CircuitRegistry> {
+        let root = current_dir()?.join(DEFAULT_CIRCUITS_DIR);
+        Ok(CircuitRegistry::with_root(root))
+    }
+
+    pub fn with_root(root: PathBuf) -> CircuitRegistry {
+        CircuitRegistry {
+            root,
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest_file(&self) -> PathBuf {
+        self.root.join(MANIFEST_FILE)
+    }
+
+    pub fn manifest(&self) -> Result<CircuitManifest> {
+        CircuitManifest::load(&self.manifest_file())
+    }
+
+    pub fn depth_dir(&self, depth: usize) -> PathBuf {
+        self.root.join(format!("depth_{}", depth))
+    }
+
+    pub fn circuit_file(&self, kind: CircuitKind, depth: usize) -> PathBuf {
+        self.depth_dir(depth).join(format!("{}.r1cs", kind.name()))
+    }
+
+    pub fn witness_generator_file(&self, kind: CircuitKind, depth: usize) -> PathBuf {
+        self.depth_dir(depth)
+            .join(format!("{}_js", kind.name()))
+            .join(format!("{}.wasm", kind.name()))
+    }
+
+    /// Depths in the supported range for which both artifacts are present.
+    pub fn available_depths(&self, kind: CircuitKind) -> Vec<usize> {
+        (MIN_CIRCUIT_DEPTH..=MAX_CIRCUIT_DEPTH)
+            .filter(|&depth| {
+                self.circuit_file(kind, depth).is_file()
+                    && self.witness_generator_file(kind, depth).is_file()
+            })
+            .collect()
+    }
+
+    /// Reads and verifies the artifacts for `depth` without caching them.
+    pub fn load(&self, kind: CircuitKind, depth: usize) -> Result<CircuitSetup> {
+        if !(MIN_CIRCUIT_DEPTH..=MAX_CIRCUIT_DEPTH).contains(&depth) {
+            return Err(BlockchainError::CircuitSetup(format!(
+                "Tree depth {} is outside the supported range {}..={}",
+                depth, MIN_CIRCUIT_DEPTH, MAX_CIRCUIT_DEPTH
+            ))
+            .into());
+        }
+        let setup = CircuitSetup::from_files(
+            kind,
+            depth,
+            &self.circuit_file(kind, depth),
+            &self.witness_generator_file(kind, depth),
+            &self.manifest_file(),
+        );
+        setup.map_err(|e| {
+            failure::format_err!(
+                "{} (available depths: {:?}, run circuits/compile.sh to build more)",
+                e,
+                self.available_depths(kind)
+            )
+        })
+    }
+
+    pub fn get(&self, kind: CircuitKind, depth: usize) -> Result<Arc<CircuitSetup>> {
+        if let Some(setup) = self.cached(kind, depth)? {
+            return Ok(setup);
+        }
+        let setup = Arc::new(self.load(kind, depth)?);
+        let mut loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| failure::format_err!("Mutex poisoned"))?;
+        Ok(Arc::clone(loaded.entry((kind, depth)).or_insert(setup)))
+    }
+
+    /// Generates the public parameters of every build present and records
+    /// their digests in the manifest, so verifiers need not generate them.
+    pub fn record_params_digests(&self) -> Result<Vec<CircuitId>> {
+        let mut manifest = self.manifest()?;
+        let mut recorded = vec![];
+        for kind in [CircuitKind::Inclusion, CircuitKind::LiabilitiesChangesFolding] {
+            for depth in self.available_depths(kind) {
+                let setup = self.load(kind, depth)?;
+                let digest = PP::from_circuit_setup(&setup).params_digest()?;
+                manifest.set_params_digest(setup.get_circuit_id(), digest)?;
+                recorded.push(setup.get_circuit_id().clone());
+            }
+        }
+        manifest.write(&self.manifest_file())?;
+        Ok(recorded)
+    }
+
+    /// Picks the build matching the depth of `merkle_sum_tree`.
+    pub fn for_tree(
+        &self,
+        kind: CircuitKind,
+        merkle_sum_tree: &MerkleSumTree,
+    ) -> Result<Arc<CircuitSetup>> {
+        self.get(kind, tree_depth(merkle_sum_tree))
+    }
+
+    fn cached(&self, kind: CircuitKind, depth: usize) -> Result<Option<Arc<CircuitSetup>>> {
+        let loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| failure::format_err!("Mutex poisoned"))?;
+        Ok(loaded.get(&(kind, depth)).cloned())
+    }
+}
+
+/// Number of levels between the leaves and the root of a merkle sum tree.
+#[cfg(feature = "node")]
+pub fn tree_depth(merkle_sum_tree: &MerkleSumTree) -> usize {
+    merkle_sum_tree
+        .get_leafs()
+        .len()
+        .next_power_of_two()
+        .trailing_zeros() as usize
+}
+
+#[cfg(all(test, feature = "node"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_depth_is_available() {
+        let registry = CircuitRegistry::new().unwrap();
+        assert!(registry
+            .available_depths(CircuitKind::Inclusion)
+            .contains(&DEFAULT_CIRCUIT_DEPTH));
+        assert!(registry
+            .available_depths(CircuitKind::LiabilitiesChangesFolding)
+            .contains(&DEFAULT_CIRCUIT_DEPTH));
+    }
+
+    #[test]
+    fn test_missing_depth_fails_clearly() {
+        let registry = CircuitRegistry::with_root(PathBuf::from("does/not/exist"));
+        let error = registry.load(CircuitKind::Inclusion, 3).err().unwrap();
+        assert!(error.to_string().contains("No compiled inclusion artifact for depth 3"));
+        assert!(registry.load(CircuitKind::Inclusion, 11).is_err());
+    }
+}
@@ -1,80 +1,217 @@
-use nova_scotia::{
-    circom::{
-        circuit::{CircomCircuit, R1CS},
-        reader::load_r1cs,
-    },
-    create_public_params, FileLocation,
-};
 use nova_snark::{
-    traits::{circuit::TrivialTestCircuit, Group},
-    PublicParams,
+    provider::ipa_pc::EvaluationEngine,
+    spartan::snark::RelaxedR1CSSNARK,
+    traits::circuit::{StepCircuit, TrivialTestCircuit},
+    CompressedSNARK, PublicParams,
 };
-use pasta_curves::Fq;
+use pasta_curves::{Fp, Fq};
 use serde::{Deserialize, Serialize};
-use std::{env::current_dir, path::PathBuf};
+use sha2::{Digest, Sha256};
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::manifest::CircuitId;
+
+// Loading circuit artifacts and proving are only needed by the node
+#[cfg(feature = "node")]
+use crate::errors::BlockchainError;
+#[cfg(feature = "node")]
+use crate::proofs::manifest::CircuitManifest;
+#[cfg(feature = "node")]
+use crate::proofs::registry::{CircuitKind, CircuitRegistry, DEFAULT_CIRCUIT_DEPTH};
+#[cfg(feature = "node")]
+use crate::proofs::witness::WitnessBackend;
+#[cfg(feature = "node")]
+use nova_scotia::{
+    circom::circuit::{CircomCircuit, R1CS},
+    circom::reader::load_r1cs,
+    create_public_params, FileLocation,
+};
+#[cfg(feature = "node")]
+use nova_snark::{traits::Group, RecursiveSNARK};
+#[cfg(feature = "node")]
+use pasta_curves::{Ep, Eq};
+#[cfg(feature = "node")]
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
 
 type G1 = pasta_curves::pallas::Point;
 type G2 = pasta_curves::vesta::Point;
 
+#[cfg(feature = "node")]
+pub type NovaPublicParams = PublicParams<
+    G1,
+    G2,
+    CircomCircuit<<G1 as Group>::Scalar>,
+    TrivialTestCircuit<<G2 as Group>::Scalar>,
+>;
+
+type S1 = RelaxedR1CSSNARK<G1, EvaluationEngine<G1>>;
+type S2 = RelaxedR1CSSNARK<G2, EvaluationEngine<G2>>;
+
+/// A folded proof compressed with Spartan. Unlike a `RecursiveSNARK` it holds
+/// neither the folded instances' witnesses nor the last step's, so the private
+/// inputs of the steps stay on the prover.
+pub type CompressedProof<C> = CompressedSNARK<G1, G2, C, TrivialTestCircuit<Fp>, S1, S2>;
+
+/// The step circuit proofs and parameters are decoded as when none is named.
+/// Verifying never runs the circuit, and its type leaves no trace in the
+/// encoding, so verifier builds use a stand-in and need no circom support.
+#[cfg(feature = "node")]
+pub type DefaultCircuit = CircomCircuit<Fq>;
+#[cfg(not(feature = "node"))]
+pub type DefaultCircuit = TrivialTestCircuit<Fq>;
+
+/// Public parameters for a step circuit, circom by default.
 #[derive(Serialize, Deserialize)]
-pub struct PP {
-    pp: PublicParams<
-        G1,
-        G2,
-        CircomCircuit<<G1 as Group>::Scalar>,
-        TrivialTestCircuit<<G2 as Group>::Scalar>,
-    >,
+#[serde(bound = "")]
+pub struct PP<C: StepCircuit<Fq> = DefaultCircuit> {
+    pp: PublicParams<G1, G2, C, TrivialTestCircuit<Fp>>,
+    circuit_id: CircuitId,
 }
+#[cfg(feature = "node")]
 pub struct CircuitSetup {
+    kind: CircuitKind,
+    depth: usize,
+    circuit_id: CircuitId,
     witness_generator_file: PathBuf,
+    native_witness_generator_file: PathBuf,
+    symbols_file: PathBuf,
     r1cs: R1CS<Fq>,
 }
+#[cfg(feature = "node")]
 impl PP {
-    pub fn new(r1cs: R1CS<Fq>) -> PP {
+    pub fn new(r1cs: R1CS<Fq>, circuit_id: CircuitId) -> PP {
         let pp = create_public_params(r1cs);
-        PP { pp }
+        PP { pp, circuit_id }
     }
 
+    #[cfg(feature = "node")]
     pub fn from_circuit_setup(circuit_setup: &CircuitSetup) -> PP {
         let r1cs = circuit_setup.get_r1cs();
         let pp = create_public_params(r1cs);
-        PP { pp }
+        PP {
+            pp,
+            circuit_id: circuit_setup.get_circuit_id().clone(),
+        }
     }
+}
 
-    pub fn from_public_params(pp: PublicParams<G1, G2, CircomCircuit<<G1 as Group>::Scalar>, TrivialTestCircuit<<G2 as Group>::Scalar>>) -> PP {
-        PP { pp }
+impl<C: StepCircuit<Fq>> PP<C> {
+    pub fn from_public_params(pp: PublicParams<G1, G2, C, TrivialTestCircuit<Fp>>, circuit_id: CircuitId) -> PP<C> {
+        PP { pp, circuit_id }
     }
 
-    pub fn get_pp(
-        &self,
-    ) -> &PublicParams<
-        G1,
-        G2,
-        CircomCircuit<<G1 as Group>::Scalar>,
-        TrivialTestCircuit<<G2 as Group>::Scalar>,
-    > {
+    pub fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+
+    pub fn get_pp(&self) -> &PublicParams<G1, G2, C, TrivialTestCircuit<Fp>> {
         &self.pp
     }
+
+    /// SHA-256 of the binary encoding of the parameters alone, without the
+    /// identity they are labelled with. Generating parameters is deterministic,
+    /// so verifiers compare this with the digest of a build they trust.
+    pub fn params_digest(&self) -> Result<String> {
+        Ok(hex::encode(Sha256::digest(bincode::serialize(&self.pp)?)))
+    }
+
+    /// Replaces the R1CS hash of the identity with `params_digest`, for native
+    /// circuits that have no R1CS file to hash.
+    pub fn identified_by_digest(self) -> Result<PP<C>> {
+        let digest = self.params_digest()?;
+        Ok(PP {
+            circuit_id: self.circuit_id.with_r1cs_hash(digest),
+            pp: self.pp,
+        })
+    }
 }
 
+impl<C: StepCircuit<Fq>> ProofEncoding for PP<C> {
+    const KIND: PayloadKind = PayloadKind::PublicParams;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+}
+
+#[cfg(feature = "node")]
 impl CircuitSetup {
+    /// Loads the default-depth build of a circuit, panicking if it is missing.
     pub fn new(circuit_name: &str) -> CircuitSetup {
-        let root = current_dir().unwrap();
-        let circuit_file = root.join("circuits/compile/".to_string() + circuit_name + ".r1cs");
-        let witness_generator_file =
-            root.join("circuits/compile/".to_string() + circuit_name + "_js/" + circuit_name + ".wasm");
+        let kind = CircuitKind::from_name(circuit_name)
+            .unwrap_or_else(|| panic!("Unknown circuit: {}", circuit_name));
+        CircuitRegistry::new()
+            .and_then(|registry| registry.load(kind, DEFAULT_CIRCUIT_DEPTH))
+            .unwrap_or_else(|e| panic!("Failed to load circuit {}: {}", circuit_name, e))
+    }
+
+    /// Loads a circuit build after checking its artifacts against the manifest.
+    pub fn from_files(
+        kind: CircuitKind,
+        depth: usize,
+        circuit_file: &Path,
+        witness_generator_file: &Path,
+        manifest_file: &Path,
+    ) -> Result<CircuitSetup> {
+        for file in [circuit_file, witness_generator_file] {
+            if !file.is_file() {
+                return Err(BlockchainError::CircuitSetup(format!(
+                    "No compiled {} artifact for depth {}: {} not found",
+                    kind.name(),
+                    depth,
+                    file.display()
+                ))
+                .into());
+            }
+        }
 
-        println!("  Loading R1CS for {}...", circuit_name);
+        // circom writes the symbols next to the R1CS and the C++ generator to <name>_cpp/
+        let symbols_file = circuit_file.with_extension("sym");
+        let native_dir = circuit_file.with_file_name(format!("{}_cpp", kind.name()));
+        let native_witness_generator_file = native_dir.join(kind.name());
+        let native_sources = [
+            native_dir.join(format!("{}.cpp", kind.name())),
+            native_dir.join(format!("{}.dat", kind.name())),
+        ];
+
+        let manifest = CircuitManifest::load(manifest_file)?;
+        let circuit_id = manifest.verify_artifacts(
+            kind,
+            depth,
+            circuit_file,
+            witness_generator_file,
+            &symbols_file,
+            &[&native_sources[0], &native_sources[1]],
+        )?;
+
+        println!("  Loading R1CS for {} (depth {})...", kind.name(), depth);
         let start_time = std::time::Instant::now();
-        let r1cs = load_r1cs::<G1, G2>(&FileLocation::PathBuf(circuit_file));
+        let r1cs = load_r1cs::<G1, G2>(&FileLocation::PathBuf(circuit_file.to_path_buf()));
         println!("  R1CS loading took: {:?}", start_time.elapsed());
 
-        CircuitSetup {
-            witness_generator_file,
+        Ok(CircuitSetup {
+            kind,
+            depth,
+            circuit_id,
+            witness_generator_file: witness_generator_file.to_path_buf(),
+            native_witness_generator_file,
+            symbols_file,
             r1cs,
-        }
+        })
+    }
+
+    pub fn get_kind(&self) -> CircuitKind {
+        self.kind
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
     }
-    
 
     pub fn get_r1cs(&self) -> R1CS<Fq> {
         self.r1cs.clone()
@@ -83,4 +220,106 @@ impl CircuitSetup {
     pub fn get_witness_generator_file(&self) -> &PathBuf {
         &self.witness_generator_file
     }
+
+    pub fn get_symbols_file(&self) -> &PathBuf {
+        &self.symbols_file
+    }
+
+    /// Generator file for backends that run an external witness calculator.
+    pub fn get_witness_generator(&self, backend: WitnessBackend) -> Result<PathBuf> {
+        let file = match backend {
+            WitnessBackend::Wasm => &self.witness_generator_file,
+            WitnessBackend::Native => &self.native_witness_generator_file,
+            WitnessBackend::Rust => {
+                return Err(failure::format_err!(
+                    "The rust witness backend runs in-process and has no generator file"
+                ))
+            }
+        };
+        if !file.is_file() {
+            return Err(BlockchainError::CircuitSetup(format!(
+                "No {} witness generator for {} at depth {}: {} not found",
+                backend.name(),
+                self.kind.name(),
+                self.depth,
+                file.display()
+            ))
+            .into());
+        }
+        Ok(file.clone())
+    }
+}
+
+/// Folds one step per circuit into a `RecursiveSNARK` starting from `start_public_input`.
+#[cfg(feature = "node")]
+pub fn fold_steps<C: StepCircuit<Fq>>(
+    pp: &PublicParams<G1, G2, C, TrivialTestCircuit<Fp>>,
+    circuits: &[C],
+    start_public_input: Vec<Fq>,
+) -> Result<RecursiveSNARK<Ep, Eq, C, TrivialTestCircuit<Fp>>> {
+    if circuits.is_empty() {
+        return Err(failure::format_err!("Cannot fold zero steps"));
+    }
+    let mut recursive_snark = RecursiveSNARK::new(
+        pp,
+        &circuits[0],
+        &TrivialTestCircuit::default(),
+        start_public_input.clone(),
+        vec![Fp::from(0)],
+    );
+    extend_steps(pp, &mut recursive_snark, circuits, start_public_input)?;
+    Ok(recursive_snark)
+}
+
+/// Compresses a proof folded with `pp`. The Spartan keys are derived from `pp`
+/// each time, as the parameters themselves are.
+#[cfg(feature = "node")]
+pub fn compress<C: StepCircuit<Fq>>(
+    pp: &PP<C>,
+    recursive_snark: &RecursiveSNARK<Ep, Eq, C, TrivialTestCircuit<Fp>>,
+) -> Result<CompressedProof<C>> {
+    let (pk, _vk) = CompressedProof::<C>::setup(pp.get_pp())
+        .map_err(|e| failure::format_err!("Spartan setup failed: {:?}", e))?;
+    CompressedProof::<C>::prove(pp.get_pp(), &pk, recursive_snark)
+        .map_err(|e| failure::format_err!("Compressing the proof failed: {:?}", e))
+}
+
+/// Checks a compressed proof of `num_steps` steps from `z0` against `pp` and
+/// returns the final public output.
+pub fn verify_compressed<C: StepCircuit<Fq>>(
+    pp: &PP<C>,
+    proof: &CompressedProof<C>,
+    num_steps: usize,
+    z0: &[Fq],
+) -> Result<Vec<Fq>> {
+    let (_pk, vk) = CompressedProof::<C>::setup(pp.get_pp())
+        .map_err(|e| failure::format_err!("Spartan setup failed: {:?}", e))?;
+    let (output, _) = proof
+        .verify(&vk, num_steps, z0, &[Fp::from(0)])
+        .map_err(|e| failure::format_err!("{:?}", e))?;
+    Ok(output)
+}
+
+/// Folds `circuits` onto a proof that was started from `start_public_input`.
+#[cfg(feature = "node")]
+pub fn extend_steps<C: StepCircuit<Fq>>(
+    pp: &PublicParams<G1, G2, C, TrivialTestCircuit<Fp>>,
+    recursive_snark: &mut RecursiveSNARK<Ep, Eq, C, TrivialTestCircuit<Fp>>,
+    circuits: &[C],
+    start_public_input: Vec<Fq>,
+) -> Result<()> {
+    let circuit_secondary = TrivialTestCircuit::default();
+    let z0_secondary = vec![Fp::from(0)];
+    for circuit in circuits {
+        recursive_snark
+            .prove_step(
+                pp,
+                circuit,
+                &circuit_secondary,
+                start_public_input.clone(),
+                z0_secondary.clone(),
+            )
+            .map_err(|e| failure::format_err!("Folding step failed: {:?}", e))?;
+    }
+    Ok(())
 }
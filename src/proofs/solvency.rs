@@ -0,0 +1,588 @@
+use crate::errors::BlockchainError;
+use crate::proofs::bundle::{BlockWrapper, BundleReport, ProofBundle};
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::gadgets::{enforce_equal, mimc_sponge, range_check, BALANCE_BITS};
+use crate::proofs::manifest::{CircuitId, CircuitManifest};
+use crate::proofs::mimc;
+use crate::proofs::registry::CircuitKind;
+use crate::proofs::setup::{verify_compressed, CompressedProof, PP};
+use crate::proofs::util::field_from_hex;
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use nova_snark::traits::circuit::{StepCircuit, TrivialTestCircuit};
+use nova_snark::PublicParams;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "node")]
+use crate::proofs::setup::{compress, fold_steps};
+#[cfg(feature = "node")]
+use ed25519_dalek::{Signer, SigningKey};
+#[cfg(feature = "node")]
+use ff::Field;
+#[cfg(feature = "node")]
+use rand::rngs::OsRng;
+#[cfg(feature = "node")]
+use rand::RngCore;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Reserve asset compared against the ledger's liabilities when none is given.
+pub const DEFAULT_RESERVE_ASSET: &str = "native";
+
+/// Custody balance of one asset, as supplied by the operator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetReserve {
+    asset: String,
+    amount: u64,
+}
+
+/// One line of a reserves statement. The signature covers the commitment
+/// `MiMC(amount, salt)`, so `amount` and `salt` can be stripped before the
+/// statement is published without invalidating it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveEntry {
+    asset: String,
+    commitment: String,
+    amount: Option<u64>,
+    salt: Option<String>,
+}
+
+/// Custody balances per asset at a block, signed with the operator's ed25519 key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReservesStatement {
+    block_number: i32,
+    entries: Vec<ReserveEntry>,
+    public_key: String,
+    signature: String,
+}
+
+#[derive(Debug, Clone)]
+struct SolvencyStep {
+    liabilities: u64,
+    reserves: u64,
+    salt: Fq,
+}
+
+/// Proves `liabilities <= reserves` for the public input
+/// `[liabilities, commitment]` while keeping the reserves private.
+#[derive(Debug, Clone)]
+pub struct SolvencyCircuit {
+    step: Option<SolvencyStep>,
+}
+
+/// Compressed, since the folded witness holds the reserves and the salt.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SolvencyProof {
+    compressed_snark: CompressedProof<SolvencyCircuit>,
+    circuit_id: CircuitId,
+}
+
+/// Liabilities of one block, proven by a liabilities bundle ending at that
+/// block, set against the signed reserves of one asset. Without a solvency
+/// proof the reserves are opened and the margin is public.
+#[derive(Serialize, Deserialize)]
+pub struct SolvencyReport {
+    liabilities: ProofBundle,
+    asset: String,
+    statement: ReservesStatement,
+    proof: Option<SolvencyProof>,
+    circuit_id: CircuitId,
+}
+
+/// What a solvency report was verified to show.
+#[derive(Debug)]
+pub struct SolvencyOutput {
+    block_number: i32,
+    asset: String,
+    liabilities: u64,
+    reserves: Option<u64>,
+    margin: Option<u64>,
+    operator: String,
+}
+
+/// `MiMC(amount, salt)`, the same sponge the merkle sum tree uses.
+pub fn commit(amount: u64, salt: Fq) -> Fq {
+    mimc::mimc_sponge(&[Fq::from(amount), salt])
+}
+
+/// Reads the hex ed25519 seed at `path`, creating a new one if the file does not exist.
+#[cfg(feature = "node")]
+pub fn load_or_create_operator_key(path: &Path) -> Result<[u8; 32]> {
+    if path.exists() {
+        let seed = hex::decode(fs::read_to_string(path)?.trim())
+            .map_err(|e| failure::format_err!("Invalid operator key {}: {}", path.display(), e))?;
+        let length = seed.len();
+        return seed.try_into().map_err(|_| {
+            failure::format_err!("Operator key {} must hold 32 bytes, found {}", path.display(), length)
+        });
+    }
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    fs::write(path, hex::encode(seed))?;
+    Ok(seed)
+}
+
+impl AssetReserve {
+    pub fn new(asset: &str, amount: u64) -> AssetReserve {
+        AssetReserve {
+            asset: asset.to_string(),
+            amount,
+        }
+    }
+
+    /// Reads a JSON list of `{"asset": .., "amount": ..}`.
+    pub fn load_all(path: &Path) -> Result<Vec<AssetReserve>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+impl ReserveEntry {
+    pub fn get_asset(&self) -> &str {
+        &self.asset
+    }
+
+    pub fn get_amount(&self) -> Option<u64> {
+        self.amount
+    }
+
+    pub fn get_salt(&self) -> Option<&str> {
+        self.salt.as_deref()
+    }
+
+    fn opening(&self) -> Option<(u64, Fq)> {
+        Some((self.amount?, field_from_hex(self.salt.as_ref()?)))
+    }
+}
+
+impl ReservesStatement {
+    #[cfg(feature = "node")]
+    pub fn sign(block_number: i32, reserves: &[AssetReserve], seed: &[u8; 32]) -> ReservesStatement {
+        let entries = reserves
+            .iter()
+            .map(|reserve| {
+                let salt = Fq::random(OsRng);
+                ReserveEntry {
+                    asset: reserve.asset.clone(),
+                    commitment: format!("{:?}", commit(reserve.amount, salt)),
+                    amount: Some(reserve.amount),
+                    salt: Some(format!("{:?}", salt)),
+                }
+            })
+            .collect();
+        let signing_key = SigningKey::from_bytes(seed);
+        let mut statement = ReservesStatement {
+            block_number,
+            entries,
+            public_key: hex::encode(signing_key.verifying_key().to_bytes()),
+            signature: String::new(),
+        };
+        statement.signature = hex::encode(signing_key.sign(&statement.message()).to_bytes());
+        statement
+    }
+
+    pub fn load(path: &Path) -> Result<ReservesStatement> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get_block_number(&self) -> i32 {
+        self.block_number
+    }
+
+    pub fn get_public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    pub fn get_entry(&self, asset: &str) -> Option<&ReserveEntry> {
+        self.entries.iter().find(|entry| entry.asset == asset)
+    }
+
+    /// The signed bytes: the block number and each asset with its commitment.
+    fn message(&self) -> Vec<u8> {
+        let mut message = self.block_number.to_le_bytes().to_vec();
+        for entry in &self.entries {
+            message.extend_from_slice(entry.asset.as_bytes());
+            message.push(0);
+            message.extend_from_slice(entry.commitment.as_bytes());
+            message.push(0);
+        }
+        message
+    }
+
+    /// Checks that the statement is signed by one of `operator_keys`, hex
+    /// ed25519 public keys the verifier trusts, then `check_signature`.
+    pub fn verify(&self, operator_keys: &[String]) -> Result<()> {
+        if !operator_keys.iter().any(|key| key.eq_ignore_ascii_case(&self.public_key)) {
+            return Err(statement_error(format!(
+                "Statement is signed with {}, which is not a configured operator key",
+                self.public_key
+            )));
+        }
+        self.check_signature()
+    }
+
+    /// Checks the signature against the embedded key and that every opened
+    /// entry matches its commitment. Says nothing about who signed.
+    pub fn check_signature(&self) -> Result<()> {
+        let public_key: [u8; 32] = hex::decode(&self.public_key)
+            .map_err(|e| statement_error(e.to_string()))?
+            .try_into()
+            .map_err(|_| statement_error("Operator key must hold 32 bytes".to_string()))?;
+        let signature: [u8; 64] = hex::decode(&self.signature)
+            .map_err(|e| statement_error(e.to_string()))?
+            .try_into()
+            .map_err(|_| statement_error("Invalid operator signature".to_string()))?;
+        VerifyingKey::from_bytes(&public_key)
+            .and_then(|key| key.verify(&self.message(), &Signature::from_bytes(&signature)))
+            .map_err(|_| statement_error("Invalid operator signature".to_string()))?;
+        for entry in &self.entries {
+            if let Some((amount, salt)) = entry.opening() {
+                if format!("{:?}", commit(amount, salt)) != entry.commitment {
+                    return Err(statement_error(format!(
+                        "Amount of {} does not match its commitment",
+                        entry.asset
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy of the statement with the amount of `asset` removed.
+    pub fn redacted(&self, asset: &str) -> ReservesStatement {
+        let mut statement = self.clone();
+        for entry in statement.entries.iter_mut().filter(|entry| entry.asset == asset) {
+            entry.amount = None;
+            entry.salt = None;
+        }
+        statement
+    }
+}
+
+impl SolvencyOutput {
+    pub fn get_liabilities(&self) -> u64 {
+        self.liabilities
+    }
+
+    /// `None` when the report keeps the reserves hidden.
+    pub fn get_margin(&self) -> Option<u64> {
+        self.margin
+    }
+}
+
+impl SolvencyCircuit {
+    pub fn blank() -> SolvencyCircuit {
+        SolvencyCircuit { step: None }
+    }
+
+    pub fn circuit_id() -> CircuitId {
+        CircuitId::native(CircuitKind::Solvency, 0, 1)
+    }
+
+    pub fn params() -> PP<SolvencyCircuit> {
+        let pp = PublicParams::setup(SolvencyCircuit::blank(), TrivialTestCircuit::default());
+        PP::from_public_params(pp, SolvencyCircuit::circuit_id())
+    }
+}
+
+impl StepCircuit<Fq> for SolvencyCircuit {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn synthesize<CS: ConstraintSystem<Fq>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<Fq>],
+    ) -> std::result::Result<Vec<AllocatedNum<Fq>>, SynthesisError> {
+        let step = self.step.as_ref();
+        let reserves = AllocatedNum::alloc(cs.namespace(|| "reserves"), || {
+            step.map(|s| Fq::from(s.reserves)).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let salt = AllocatedNum::alloc(cs.namespace(|| "salt"), || {
+            step.map(|s| s.salt).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        range_check(cs.namespace(|| "reservesCheck"), &reserves, step.map(|s| s.reserves), BALANCE_BITS)?;
+
+        let commitment = mimc_sponge(cs.namespace(|| "commitment"), &[reserves.clone(), salt])?;
+        enforce_equal(cs.namespace(|| "committed reserves"), &commitment, &z[1]);
+
+        // margin = reserves - liabilities must fit in BALANCE_BITS, so it is not negative
+        let margin_value = step.and_then(|s| s.reserves.checked_sub(s.liabilities));
+        let margin = AllocatedNum::alloc(cs.namespace(|| "margin"), || {
+            margin_value.map(Fq::from).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        cs.enforce(
+            || "margin = reserves - liabilities",
+            |lc| lc + reserves.get_variable() - z[0].get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + margin.get_variable(),
+        );
+        range_check(cs.namespace(|| "marginCheck"), &margin, margin_value, BALANCE_BITS)?;
+
+        Ok(z.to_vec())
+    }
+
+    fn output(&self, z: &[Fq]) -> Vec<Fq> {
+        z.to_vec()
+    }
+}
+
+impl SolvencyProof {
+    #[cfg(feature = "node")]
+    pub fn new(liabilities: u64, reserves: u64, salt: Fq) -> Result<SolvencyProof> {
+        if reserves < liabilities {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Reserves {} are below liabilities {}",
+                reserves, liabilities
+            ))
+            .into());
+        }
+        let pp = SolvencyCircuit::params();
+        let circuit = SolvencyCircuit {
+            step: Some(SolvencyStep {
+                liabilities,
+                reserves,
+                salt,
+            }),
+        };
+        let start_public_input = vec![Fq::from(liabilities), commit(reserves, salt)];
+        let recursive_snark = fold_steps(pp.get_pp(), &[circuit], start_public_input)?;
+        Ok(SolvencyProof {
+            compressed_snark: compress(&pp, &recursive_snark)?,
+            circuit_id: SolvencyCircuit::circuit_id(),
+        })
+    }
+
+    pub fn verify(&self, liabilities: u64, commitment: Fq) -> Result<()> {
+        if self.circuit_id != SolvencyCircuit::circuit_id() {
+            return Err(verification_error(format!(
+                "Unknown solvency circuit {}",
+                self.circuit_id
+            )));
+        }
+        let pp = SolvencyCircuit::params();
+        verify_compressed(&pp, &self.compressed_snark, 1, &[Fq::from(liabilities), commitment])
+            .map_err(|e| verification_error(format!("Solvency proof rejected: {}", e)))?;
+        Ok(())
+    }
+}
+
+impl SolvencyReport {
+    /// Sets the liabilities proven by `bundle` against the reserves of
+    /// `asset`. With `hide_margin` the amount is stripped from the statement and
+    /// replaced by a proof that it covers the liabilities.
+    #[cfg(feature = "node")]
+    pub fn new(
+        bundle: ProofBundle,
+        asset: &str,
+        statement: &ReservesStatement,
+        hide_margin: bool,
+    ) -> Result<SolvencyReport> {
+        statement.check_signature()?;
+        let header = liabilities_header(&bundle)?;
+        if statement.block_number != header.get_block_number() {
+            return Err(statement_error(format!(
+                "Statement is for block {} but the header is block {}",
+                statement.block_number,
+                header.get_block_number()
+            )));
+        }
+        let (reserves, salt) = statement
+            .get_entry(asset)
+            .and_then(ReserveEntry::opening)
+            .ok_or_else(|| statement_error(format!("Statement has no opened reserves for {}", asset)))?;
+        let liabilities = header.get_root_sum() as u64;
+        if reserves < liabilities {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Insolvent at block {}: liabilities {} exceed {} reserves {}",
+                header.get_block_number(),
+                liabilities,
+                asset,
+                reserves
+            ))
+            .into());
+        }
+        let (statement, proof) = if hide_margin {
+            (
+                statement.redacted(asset),
+                Some(SolvencyProof::new(liabilities, reserves, salt)?),
+            )
+        } else {
+            (statement.clone(), None)
+        };
+        Ok(SolvencyReport {
+            liabilities: bundle,
+            asset: asset.to_string(),
+            statement,
+            proof,
+            circuit_id: SolvencyCircuit::circuit_id(),
+        })
+    }
+
+    pub fn get_header(&self) -> Option<&BlockWrapper> {
+        self.liabilities.get_headers().last()
+    }
+
+    pub fn get_statement(&self) -> &ReservesStatement {
+        &self.statement
+    }
+
+    pub fn is_margin_hidden(&self) -> bool {
+        self.proof.is_some()
+    }
+
+    /// Verifies against the manifest under `circuits/compile`.
+    pub fn verify(self, operator_keys: &[String]) -> Result<SolvencyOutput> {
+        let manifest = CircuitManifest::load_default()?;
+        self.verify_with_manifest(&manifest, operator_keys)
+    }
+
+    /// Checks that one of `operator_keys` signed the statement, the liabilities
+    /// bundle against `manifest`, then the reserves against the proven root sum.
+    pub fn verify_with_manifest(self, manifest: &CircuitManifest, operator_keys: &[String]) -> Result<SolvencyOutput> {
+        self.statement.verify(operator_keys)?;
+        let header = liabilities_header(&self.liabilities)?;
+        if self.statement.block_number != header.get_block_number() {
+            return Err(verification_error(format!(
+                "Statement is for block {} but the report covers block {}",
+                self.statement.block_number,
+                header.get_block_number()
+            )));
+        }
+        // Checks that the proof ends at the header's root and sum
+        match self.liabilities.verify_with_manifest(manifest)? {
+            BundleReport::Liabilities(_) => {}
+            BundleReport::Inclusion(_) => {
+                return Err(verification_error("Report does not carry a liabilities proof".to_string()))
+            }
+        }
+        let entry = self
+            .statement
+            .get_entry(&self.asset)
+            .ok_or_else(|| verification_error(format!("Statement has no reserves for {}", self.asset)))?;
+        let liabilities = header.get_root_sum() as u64;
+        let (reserves, margin) = match (&self.proof, entry.amount) {
+            (Some(proof), _) => {
+                proof.verify(liabilities, field_from_hex(&entry.commitment))?;
+                (None, None)
+            }
+            (None, Some(reserves)) => match reserves.checked_sub(liabilities) {
+                Some(margin) => (Some(reserves), Some(margin)),
+                None => {
+                    return Err(verification_error(format!(
+                        "Liabilities {} exceed reserves {}",
+                        liabilities, reserves
+                    )))
+                }
+            },
+            (None, None) => {
+                return Err(verification_error(
+                    "Reserves are hidden but the report has no solvency proof".to_string(),
+                ))
+            }
+        };
+        Ok(SolvencyOutput {
+            block_number: header.get_block_number(),
+            asset: self.asset.clone(),
+            liabilities,
+            reserves,
+            margin,
+            operator: self.statement.public_key.clone(),
+        })
+    }
+}
+
+impl ProofEncoding for SolvencyReport {
+    const KIND: PayloadKind = PayloadKind::SolvencyReport;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+}
+
+/// The block a liabilities bundle ends at.
+fn liabilities_header(liabilities: &ProofBundle) -> Result<BlockWrapper> {
+    liabilities
+        .get_headers()
+        .last()
+        .cloned()
+        .ok_or_else(|| verification_error("Liabilities bundle has no block header".to_string()))
+}
+
+fn statement_error(reason: String) -> failure::Error {
+    BlockchainError::ReservesStatement(reason).into()
+}
+
+fn verification_error(reason: String) -> failure::Error {
+    BlockchainError::ProofVerification { reason }.into()
+}
+
+#[cfg(all(test, feature = "node"))]
+mod tests {
+    use super::*;
+    use crate::proofs::bundle::ProofOfLiabilitiesWrapper;
+    use crate::proofs::liabilities::{LiabilitiesInput, MerkleSumTreeChange, ProofOfLiabilities};
+    use crate::proofs::setup::CircuitSetup;
+    use merkle_sum_tree::{Leaf, MerkleSumTree};
+    use std::sync::Arc;
+
+    /// A bundle proving a tree whose only user holds `root_sum`, at block 3.
+    fn liabilities(root_sum: i32) -> ProofBundle {
+        let empty = Leaf::new("0".to_string(), 0);
+        let old_tree = Arc::new(MerkleSumTree::new(vec![empty.clone(); 4]).unwrap());
+        let mut leafs = vec![empty; 4];
+        leafs[0] = Leaf::new("alice".to_string(), root_sum);
+        let new_tree = Arc::new(MerkleSumTree::new(leafs).unwrap());
+        let input = LiabilitiesInput::new(vec![MerkleSumTreeChange::new(0, old_tree, new_tree)]).unwrap();
+        let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+        let (proof, pp) = ProofOfLiabilities::new(vec![input], &circuit_setup).unwrap();
+        let header = BlockWrapper::new(
+            proof.get_final_root_hash().to_string(),
+            proof.get_final_root_sum(),
+            3,
+            "now".to_string(),
+        );
+        ProofBundle::from_liabilities(ProofOfLiabilitiesWrapper::new(proof, pp, Some(header))).unwrap()
+    }
+
+    #[test]
+    fn test_statement_signature_covers_commitments() {
+        let reserves = vec![AssetReserve::new("native", 500), AssetReserve::new("btc", 2)];
+        let statement = ReservesStatement::sign(3, &reserves, &[7u8; 32]);
+        let operator_keys = vec![statement.get_public_key().to_string()];
+        assert!(statement.verify(&operator_keys).is_ok());
+        assert!(statement.redacted("native").verify(&operator_keys).is_ok());
+
+        // A valid signature by a key the verifier does not know
+        let other = ReservesStatement::sign(3, &reserves, &[8u8; 32]);
+        assert!(other.check_signature().is_ok());
+        assert!(other.verify(&operator_keys).is_err());
+
+        let mut tampered = statement.clone();
+        tampered.entries[0].amount = Some(900);
+        assert!(tampered.verify(&operator_keys).is_err());
+        tampered.entries[0].commitment = format!("{:?}", commit(900, Fq::from(1)));
+        assert!(tampered.verify(&operator_keys).is_err());
+    }
+
+    #[test]
+    fn test_open_report_shows_margin() {
+        let statement = ReservesStatement::sign(3, &[AssetReserve::new("native", 500)], &[7u8; 32]);
+        let operator_keys = vec![statement.get_public_key().to_string()];
+        let report = SolvencyReport::new(liabilities(420), "native", &statement, false).unwrap();
+        let output = report.verify(&operator_keys).unwrap();
+        assert_eq!(output.get_liabilities(), 420);
+        assert_eq!(output.get_margin(), Some(80));
+        assert!(SolvencyReport::new(liabilities(600), "native", &statement, false).is_err());
+        assert!(SolvencyReport::new(liabilities(420), "btc", &statement, false).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::errors::BlockchainError;
+use crate::proofs::bundle::BlockWrapper;
+use crate::proofs::encoding::{PayloadKind, ProofEncoding};
+use crate::proofs::manifest::CircuitId;
+use crate::proofs::native::{NativeCircuitSetup, ThresholdCircuit};
+use crate::proofs::registry::CircuitKind;
+use crate::proofs::setup::{verify_compressed, CompressedProof};
+use crate::proofs::util::field_from_hex;
+use pasta_curves::Fq;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[cfg(feature = "node")]
+use crate::proofs::inclusion::InclusionInput;
+#[cfg(feature = "node")]
+use crate::proofs::setup::{compress, fold_steps};
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+/// Proof that the leaf `user_hash` held at least `threshold` in the tree of
+/// `header`, without revealing the balance. The folded proof is compressed so
+/// that the balance and the sibling path, its witness, are not sent along.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProofOfThreshold {
+    compressed_snark: CompressedProof<ThresholdCircuit>,
+    circuit_id: CircuitId,
+    threshold: u64,
+    user_hash: String,
+    header: BlockWrapper,
+}
+
+/// What a threshold proof was verified to show.
+#[derive(Debug)]
+pub struct ThresholdOutput {
+    user_hash: String,
+    threshold: u64,
+    block_number: i32,
+    root_hash: String,
+    root_sum: i32,
+}
+
+impl ProofOfThreshold {
+    #[cfg(feature = "node")]
+    /// `inclusion_input` must be taken from the tree of `header`.
+    pub fn new(
+        inclusion_input: &InclusionInput,
+        threshold: u64,
+        header: BlockWrapper,
+        circuit_setup: &NativeCircuitSetup,
+    ) -> Result<ProofOfThreshold> {
+        if circuit_setup.get_kind() != CircuitKind::InclusionThreshold {
+            return Err(failure::format_err!(
+                "Expected an inclusion threshold setup, got {}",
+                circuit_setup.get_kind().name()
+            ));
+        }
+        if inclusion_input.get_root_hash() != header.get_root_hash() {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Inclusion input opens root {} but block {} has root {}",
+                inclusion_input.get_root_hash(),
+                header.get_block_number(),
+                header.get_root_hash()
+            ))
+            .into());
+        }
+        let balance = u64::try_from(inclusion_input.get_user_balance()).map_err(|_| {
+            BlockchainError::ProofCreation(format!(
+                "Negative balance {} at block {}",
+                inclusion_input.get_user_balance(),
+                header.get_block_number()
+            ))
+        })?;
+        if balance < threshold {
+            return Err(BlockchainError::ProofCreation(format!(
+                "Balance at block {} is below {}",
+                header.get_block_number(),
+                threshold
+            ))
+            .into());
+        }
+        let pp = circuit_setup.threshold_params()?;
+        let start_proof = Instant::now();
+        let step = inclusion_input.to_native_step();
+        let start_public_input = vec![Fq::from(threshold), step.root_hash, step.root_sum, step.user_hash];
+        let circuit = ThresholdCircuit::new(circuit_setup.get_depth(), threshold, step);
+        let recursive_snark = fold_steps(pp.get_pp(), &[circuit], start_public_input)?;
+        let compressed_snark = compress(&pp, &recursive_snark)?;
+        println!("CompressedSNARK::proof took {:?}", start_proof.elapsed());
+
+        Ok(ProofOfThreshold {
+            compressed_snark,
+            circuit_id: pp.get_circuit_id().clone(),
+            threshold,
+            user_hash: inclusion_input.get_user_hash().to_string(),
+            header,
+        })
+    }
+
+    /// Rebuilds the parameters for the proof's depth and checks it against the
+    /// claimed threshold and user and the root of `header`, a header the
+    /// verifier trusts, e.g. from a verified liabilities bundle.
+    pub fn verify(&self, header: &BlockWrapper) -> Result<ThresholdOutput> {
+        if self.header.get_block_number() != header.get_block_number()
+            || self.header.get_root_hash() != header.get_root_hash()
+            || self.header.get_root_sum() != header.get_root_sum()
+        {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof is for root {} of block {}, the trusted block {} has root {}",
+                    self.header.get_root_hash(),
+                    self.header.get_block_number(),
+                    header.get_block_number(),
+                    header.get_root_hash()
+                ),
+            }
+            .into());
+        }
+        let start = Instant::now();
+        let circuit_setup = NativeCircuitSetup::new(
+            CircuitKind::InclusionThreshold,
+            self.circuit_id.get_depth(),
+            1,
+        )?;
+        // The identity of locally generated parameters carries their digest
+        let pp = circuit_setup.threshold_params()?;
+        if &self.circuit_id != pp.get_circuit_id() {
+            return Err(BlockchainError::ProofVerification {
+                reason: format!(
+                    "Proof was made with inclusion threshold circuit {}, this build has {}",
+                    self.circuit_id,
+                    pp.get_circuit_id()
+                ),
+            }
+            .into());
+        }
+        let z0 = [
+            Fq::from(self.threshold),
+            field_from_hex(header.get_root_hash()),
+            Fq::from(header.get_root_sum() as u64),
+            field_from_hex(&self.user_hash),
+        ];
+        verify_compressed(&pp, &self.compressed_snark, 1, &z0).map_err(|e| BlockchainError::ProofVerification {
+            reason: format!("Threshold proof rejected: {}", e),
+        })?;
+        println!("Threshold proof verified successfully in {:?}", start.elapsed());
+
+        Ok(ThresholdOutput {
+            user_hash: self.user_hash.clone(),
+            threshold: self.threshold,
+            block_number: self.header.get_block_number(),
+            root_hash: self.header.get_root_hash().to_string(),
+            root_sum: self.header.get_root_sum(),
+        })
+    }
+
+    pub fn get_threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    pub fn get_header(&self) -> &BlockWrapper {
+        &self.header
+    }
+}
+
+impl ThresholdOutput {
+    pub fn get_threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    pub fn get_block_number(&self) -> i32 {
+        self.block_number
+    }
+}
+
+impl ProofEncoding for ProofOfThreshold {
+    const KIND: PayloadKind = PayloadKind::ThresholdProof;
+
+    fn get_circuit_id(&self) -> &CircuitId {
+        &self.circuit_id
+    }
+}
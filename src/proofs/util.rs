@@ -1,7 +1,44 @@
+use ff::PrimeField;
 use num::{BigInt, Num};
+use pasta_curves::Fq;
+use sha2::{Digest, Sha256};
 
 pub fn convert_hex_to_dec(hex_str: String) -> String {
     BigInt::from_str_radix(hex_str.as_str().strip_prefix("0x").unwrap(), 16)
         .unwrap()
         .to_string()
 }
+
+/// Leaf ID committing to an address: `SHA-256(address || 0 || salt)`, so paths
+/// do not reveal who owns a leaf to anyone without the salt.
+pub fn leaf_commitment(address: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(address.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(salt.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn field_from_hex(hex_str: &str) -> Fq {
+    Fq::from_str_vartime(convert_hex_to_dec(hex_str.to_string()).as_str()).unwrap()
+}
+
+/// SHA-256 of a block header. Transactions enter through their own digest so
+/// that headers can be re-hashed without them.
+pub fn block_hash(
+    block_number: i32,
+    prev_hash: &str,
+    timestamp: &str,
+    transactions_hash: &str,
+    root_hash: &str,
+    root_sum: i32,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(block_number.to_be_bytes());
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(timestamp.as_bytes());
+    hasher.update(transactions_hash.as_bytes());
+    hasher.update(root_hash.as_bytes());
+    hasher.update(root_sum.to_be_bytes());
+    hex::encode(hasher.finalize())
+}
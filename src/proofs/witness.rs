@@ -0,0 +1,238 @@
+use crate::proofs::mimc::{mimc_constants, MIMC_ROUNDS};
+use crate::proofs::setup::{fold_steps, NovaPublicParams};
+use nova_scotia::circom::circuit::{CircomCircuit, R1CS};
+use nova_snark::traits::circuit::TrivialTestCircuit;
+use nova_snark::RecursiveSNARK;
+use pasta_curves::{Ep, Eq, Fp, Fq};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+pub use crate::proofs::gadgets::BALANCE_BITS;
+
+/// How the circom witness is computed for each folding step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WitnessBackend {
+    /// The `*_js/*.wasm` generator emitted by circom.
+    #[default]
+    Wasm,
+    /// The C++ generator under `*_cpp/`, built with `make`.
+    Native,
+    /// In-process evaluation of the MerkleSum/Switcher templates.
+    Rust,
+}
+
+impl WitnessBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WitnessBackend::Wasm => "wasm",
+            WitnessBackend::Native => "native",
+            WitnessBackend::Rust => "rust",
+        }
+    }
+}
+
+impl FromStr for WitnessBackend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<WitnessBackend> {
+        match s {
+            "wasm" => Ok(WitnessBackend::Wasm),
+            "native" => Ok(WitnessBackend::Native),
+            "rust" => Ok(WitnessBackend::Rust),
+            _ => Err(failure::format_err!(
+                "Unknown witness backend '{}', expected wasm, native or rust",
+                s
+            )),
+        }
+    }
+}
+
+/// Circuit signal values keyed by their full circom name, e.g. `main.step_out[0]`.
+#[derive(Default)]
+pub struct Signals {
+    values: HashMap<String, Fq>,
+}
+
+impl Signals {
+    pub fn new() -> Signals {
+        Signals::default()
+    }
+
+    pub fn set(&mut self, name: String, value: Fq) {
+        self.values.insert(name, value);
+    }
+
+    pub fn set_array(&mut self, name: &str, values: &[Fq]) {
+        for (i, value) in values.iter().enumerate() {
+            self.set(format!("{}[{}]", name, i), *value);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Fq> {
+        self.values.get(name).copied()
+    }
+}
+
+/// Wire layout of a compiled circuit, read from the `.sym` file circom emits.
+pub struct SymbolTable {
+    wires: Vec<(usize, String)>,
+}
+
+impl SymbolTable {
+    pub fn load(path: &Path) -> Result<SymbolTable> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| failure::format_err!("Cannot read {}: {}", path.display(), e))?;
+        let mut wires = vec![];
+        for line in data.lines() {
+            // signal index, witness index (-1 when optimised away), component, name
+            let fields: Vec<&str> = line.splitn(4, ',').collect();
+            if fields.len() != 4 {
+                return Err(failure::format_err!("Malformed symbol line: {}", line));
+            }
+            let wire: i64 = fields[1].parse()?;
+            if wire >= 0 {
+                wires.push((wire as usize, fields[3].to_string()));
+            }
+        }
+        Ok(SymbolTable { wires })
+    }
+
+    /// Lays the computed signals out as the full witness vector for `r1cs`.
+    pub fn witness(&self, signals: &Signals, r1cs: &R1CS<Fq>) -> Result<Vec<Fq>> {
+        let mut witness = vec![Fq::from(0); r1cs.num_variables];
+        witness[0] = Fq::from(1);
+        for (wire, name) in &self.wires {
+            let value = signals
+                .get(name)
+                .ok_or_else(|| failure::format_err!("Signal {} was not computed", name))?;
+            if *wire >= witness.len() {
+                return Err(failure::format_err!(
+                    "Signal {} maps to wire {} but the circuit has {} variables",
+                    name,
+                    wire,
+                    witness.len()
+                ));
+            }
+            witness[*wire] = value;
+        }
+        Ok(witness)
+    }
+}
+
+/// circomlib `MiMCFeistel(220)`.
+fn mimc_feistel(signals: &mut Signals, prefix: &str, xl_in: Fq, xr_in: Fq, k: Fq) -> (Fq, Fq) {
+    let constants = mimc_constants();
+    signals.set(format!("{}.xL_in", prefix), xl_in);
+    signals.set(format!("{}.xR_in", prefix), xr_in);
+    signals.set(format!("{}.k", prefix), k);
+    let mut xl = xl_in;
+    let mut xr = xr_in;
+    for (i, constant) in constants.iter().enumerate() {
+        let t = k + xl + *constant;
+        let t2 = t * t;
+        let t4 = t2 * t2;
+        signals.set(format!("{}.t2[{}]", prefix, i), t2);
+        signals.set(format!("{}.t4[{}]", prefix, i), t4);
+        if i < MIMC_ROUNDS - 1 {
+            let next_xl = xr + t4 * t;
+            xr = xl;
+            xl = next_xl;
+            signals.set(format!("{}.xL[{}]", prefix, i), xl);
+            signals.set(format!("{}.xR[{}]", prefix, i), xr);
+        } else {
+            xr += t4 * t;
+        }
+    }
+    signals.set(format!("{}.xL_out", prefix), xl);
+    signals.set(format!("{}.xR_out", prefix), xr);
+    (xl, xr)
+}
+
+/// circomlib `MiMCSponge(ins.len(), 220, 1)`.
+pub fn mimc_sponge(signals: &mut Signals, prefix: &str, ins: &[Fq], k: Fq) -> Fq {
+    let mut xl = Fq::from(0);
+    let mut xr = Fq::from(0);
+    for (i, input) in ins.iter().enumerate() {
+        signals.set(format!("{}.ins[{}]", prefix, i), *input);
+        (xl, xr) = mimc_feistel(signals, &format!("{}.S[{}]", prefix, i), xl + *input, xr, k);
+    }
+    signals.set(format!("{}.k", prefix), k);
+    signals.set(format!("{}.outs[0]", prefix), xl);
+    xl
+}
+
+/// `MerkleSum` from `circuits/merkle.circom`, returns `(root, sum)`.
+pub fn merkle_sum(signals: &mut Signals, prefix: &str, l: Fq, r: Fq, sum_l: Fq, sum_r: Fq) -> (Fq, Fq) {
+    signals.set(format!("{}.L", prefix), l);
+    signals.set(format!("{}.R", prefix), r);
+    signals.set(format!("{}.sumL", prefix), sum_l);
+    signals.set(format!("{}.sumR", prefix), sum_r);
+    let root = mimc_sponge(
+        signals,
+        &format!("{}.hasher", prefix),
+        &[l, sum_l, r, sum_r],
+        Fq::from(0),
+    );
+    let sum = sum_l + sum_r;
+    signals.set(format!("{}.root", prefix), root);
+    signals.set(format!("{}.sum", prefix), sum);
+    (root, sum)
+}
+
+/// `Switcher` from `circuits/utils.circom`, returns `(outL, outR)`.
+pub fn switcher(signals: &mut Signals, prefix: &str, sel: Fq, l: Fq, r: Fq) -> (Fq, Fq) {
+    let aux = (r - l) * sel;
+    let out_l = aux + l;
+    let out_r = r - aux;
+    signals.set(format!("{}.sel", prefix), sel);
+    signals.set(format!("{}.L", prefix), l);
+    signals.set(format!("{}.R", prefix), r);
+    signals.set(format!("{}.aux", prefix), aux);
+    signals.set(format!("{}.outL", prefix), out_l);
+    signals.set(format!("{}.outR", prefix), out_r);
+    (out_l, out_r)
+}
+
+/// `SafeRangeCheck(bits)` from `circuits/utils.circom`.
+pub fn safe_range_check(signals: &mut Signals, prefix: &str, value: u64, bits: usize) {
+    for i in 0..bits {
+        let bit = if i < 64 { Fq::from((value >> i) & 1) } else { Fq::from(0) };
+        signals.set(format!("{}.n2b.out[{}]", prefix, i), bit);
+        signals.set(format!("{}.b2n.in[{}]", prefix, i), bit);
+    }
+    signals.set(format!("{}.in", prefix), Fq::from(value));
+    signals.set(format!("{}.out", prefix), Fq::from(1));
+}
+
+/// Folds precomputed witnesses, the in-process counterpart of
+/// `nova_scotia::create_recursive_circuit`.
+pub fn prove_with_witnesses(
+    pp: &NovaPublicParams,
+    r1cs: &R1CS<Fq>,
+    witnesses: Vec<Vec<Fq>>,
+    start_public_input: Vec<Fq>,
+) -> Result<RecursiveSNARK<Ep, Eq, CircomCircuit<Fq>, TrivialTestCircuit<Fp>>> {
+    let circuits: Vec<CircomCircuit<Fq>> = witnesses
+        .into_iter()
+        .map(|witness| CircomCircuit {
+            r1cs: r1cs.clone(),
+            witness: Some(witness),
+        })
+        .collect();
+    fold_steps(pp, &circuits, start_public_input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_from_str() {
+        assert_eq!("rust".parse::<WitnessBackend>().unwrap(), WitnessBackend::Rust);
+        assert!("python".parse::<WitnessBackend>().is_err());
+    }
+}
@@ -1,4 +1,13 @@
+pub mod address;
+pub mod auth;
 pub mod cli;
 pub mod client;
+pub mod config;
+pub mod framing;
+pub mod http;
+pub mod jobs;
+pub mod legacy;
 pub mod requests;
+pub mod rpc;
 pub mod server;
+pub mod tls;
@@ -0,0 +1,100 @@
+use crate::errors::{BlockchainError, Result};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+/// Longest address accepted.
+pub const MAX_ADDRESS_LENGTH: usize = 64;
+
+/// Characters allowed in addresses besides ASCII letters and digits, none of
+/// which needs escaping in JSON, URL paths or file names.
+pub const ADDRESS_PUNCTUATION: &[char] = &['_', '-', '.'];
+
+/// An account address: 1 to `MAX_ADDRESS_LENGTH` ASCII letters, digits and
+/// `ADDRESS_PUNCTUATION`. Anything else is rejected rather than rewritten, so
+/// two different strings never name the same account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct Address(String);
+
+impl Address {
+    pub fn parse(address: &str) -> Result<Address> {
+        let valid = !address.is_empty()
+            && address.len() <= MAX_ADDRESS_LENGTH
+            && address.chars().all(|c| c.is_ascii_alphanumeric() || ADDRESS_PUNCTUATION.contains(&c))
+            && address != "."
+            && address != "..";
+        if !valid {
+            return Err(BlockchainError::InvalidRequest(format!(
+                "Invalid address {:?}, addresses are 1 to {} letters, digits and any of {:?}",
+                address, MAX_ADDRESS_LENGTH, ADDRESS_PUNCTUATION
+            ))
+            .into());
+        }
+        Ok(Address(address.to_string()))
+    }
+
+    /// The sender of a transfer: `None` for the empty address, which mints.
+    pub fn parse_sender(from: &str) -> Result<Option<Address>> {
+        if from.is_empty() {
+            Ok(None)
+        } else {
+            Address::parse(from).map(Some)
+        }
+    }
+
+    /// Deserializes a transfer sender as `parse_sender` does.
+    pub fn deserialize_sender<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Option<Address>, D::Error> {
+        let from = String::deserialize(deserializer)?;
+        Address::parse_sender(&from).map_err(de::Error::custom)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = failure::Error;
+
+    fn try_from(address: String) -> Result<Address> {
+        Address::parse(&address)
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> String {
+        address.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bad_addresses_are_rejected() {
+        assert_eq!(Address::parse("alice1").unwrap().as_str(), "alice1");
+        assert_eq!(Address::parse("alice_1").unwrap().as_str(), "alice_1");
+        assert_eq!(Address::parse("alice-1.eth").unwrap().as_str(), "alice-1.eth");
+        assert!(Address::parse("alice/1").is_err());
+        assert!(Address::parse("..").is_err());
+        assert!(Address::parse("").is_err());
+        assert!(Address::parse(&"a".repeat(MAX_ADDRESS_LENGTH + 1)).is_err());
+        assert_eq!(Address::parse_sender("").unwrap(), None);
+        assert!(Address::parse_sender("al ice").is_err());
+
+        // Nothing is stripped, so a b cannot stand for the account ab
+        assert!(Address::parse("a b").is_err());
+        assert!(serde_json::from_str::<Address>(r#""a b""#).is_err());
+        assert_ne!(Address::parse("a_b").unwrap(), Address::parse("ab").unwrap());
+        assert_eq!(serde_json::from_str::<Address>(r#""ab""#).unwrap(), Address::parse("ab").unwrap());
+    }
+}
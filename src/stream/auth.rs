@@ -0,0 +1,261 @@
+use crate::errors::{BlockchainError, Result};
+use crate::stream::address::Address;
+use crate::stream::tls::ClientCertificate;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// What a credential is allowed to do.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Runs the node: funds accounts and acts on any account.
+    Operator,
+    /// Owns the accounts listed with its credential.
+    User,
+    /// Reads every account and proof, changes nothing.
+    Auditor,
+}
+
+/// What a request needs permission for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Public chain data: headers, the liabilities proof and proof jobs.
+    ReadChain,
+    /// Balances and proofs of one account.
+    ReadAccount(Address),
+    /// Registering or spending from one account.
+    ManageAccount(Address),
+    /// Minting balance with a transfer from the empty address.
+    Fund,
+}
+
+/// Who presented a credential.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    role: Role,
+    accounts: Vec<Address>,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CredentialEntry {
+    token: String,
+    role: Role,
+    #[serde(default)]
+    accounts: Vec<Address>,
+    /// Name the operator's client certificate is issued to.
+    #[serde(default)]
+    certificate: Option<String>,
+}
+
+/// API tokens the node accepts, read from a JSON file such as
+/// `[{"token": "s3cret", "role": "user", "accounts": ["alice"]}]`. Operators
+/// may name the client certificate their token must come with, as in
+/// `{"token": "t", "role": "operator", "certificate": "ops.example.com"}`.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    identities: HashMap<String, Identity>,
+    operator_certificates: bool,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Operator => write!(f, "operator"),
+            Role::User => write!(f, "user"),
+            Role::Auditor => write!(f, "auditor"),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::ReadChain => write!(f, "read chain data"),
+            Action::ReadAccount(address) => write!(f, "read account {}", address),
+            Action::ManageAccount(address) => write!(f, "act for account {}", address),
+            Action::Fund => write!(f, "fund accounts"),
+        }
+    }
+}
+
+impl Action {
+    /// A transfer spends from `from`, or mints without a sender.
+    pub fn transfer(from: Option<&Address>) -> Action {
+        match from {
+            Some(from) => Action::ManageAccount(from.clone()),
+            None => Action::Fund,
+        }
+    }
+}
+
+impl Identity {
+    pub fn get_role(&self) -> Role {
+        self.role
+    }
+
+    pub fn get_accounts(&self) -> &[Address] {
+        &self.accounts
+    }
+
+    pub fn authorize(&self, action: &Action) -> Result<()> {
+        let allowed = match (self.role, action) {
+            (Role::Operator, _) => true,
+            (_, Action::ReadChain) => true,
+            (Role::Auditor, Action::ReadAccount(_)) => true,
+            (Role::User, Action::ReadAccount(address)) | (Role::User, Action::ManageAccount(address)) => {
+                self.accounts.contains(address)
+            }
+            _ => false,
+        };
+        if !allowed {
+            return Err(BlockchainError::Forbidden(format!("{} may not {}", self.role, action)).into());
+        }
+        Ok(())
+    }
+}
+
+impl Credentials {
+    pub fn load(path: &Path) -> Result<Credentials> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| failure::format_err!("Cannot read credentials file {}: {}", path.display(), e))?;
+        Credentials::from_json(&data)
+            .map_err(|e| failure::format_err!("Invalid credentials file {}: {}", path.display(), e))
+    }
+
+    pub fn from_json(data: &str) -> Result<Credentials> {
+        let entries: Vec<CredentialEntry> = serde_json::from_str(data)?;
+        let mut identities = HashMap::new();
+        for entry in entries {
+            if entry.token.is_empty() {
+                return Err(failure::format_err!("Empty token for a {} credential", entry.role));
+            }
+            if entry.role == Role::User && entry.accounts.is_empty() {
+                return Err(failure::format_err!("User credential without accounts"));
+            }
+            if entry.role != Role::Operator && entry.certificate.is_some() {
+                return Err(failure::format_err!("Only operator credentials name a certificate"));
+            }
+            let identity = Identity {
+                role: entry.role,
+                accounts: entry.accounts,
+                certificate: entry.certificate,
+            };
+            if identities.insert(entry.token, identity).is_some() {
+                return Err(failure::format_err!("Token listed twice"));
+            }
+        }
+        Ok(Credentials {
+            identities,
+            operator_certificates: false,
+        })
+    }
+
+    /// The identity holding `token`, failing when there is none or it is unknown.
+    pub fn authenticate(&self, token: Option<&str>) -> Result<&Identity> {
+        let token = token.ok_or_else(|| BlockchainError::Unauthorized("Missing API token".to_string()))?;
+        match self.identities.get(token) {
+            Some(identity) => Ok(identity),
+            None => Err(BlockchainError::Unauthorized("Unknown API token".to_string()).into()),
+        }
+    }
+
+    /// Refuses operator tokens on connections without a verified client
+    /// certificate issued to the name listed with the token. Fails when an
+    /// operator credential names no certificate.
+    pub fn require_operator_certificates(&mut self) -> Result<()> {
+        let unbound = self
+            .identities
+            .values()
+            .any(|identity| identity.role == Role::Operator && identity.certificate.is_none());
+        if unbound {
+            return Err(failure::format_err!("Operator credentials must name their client certificate"));
+        }
+        self.operator_certificates = true;
+        Ok(())
+    }
+
+    /// Authenticates `token` and checks it may perform every one of `actions`,
+    /// on a connection without a client certificate.
+    pub fn authorize(&self, token: Option<&str>, actions: &[Action]) -> Result<()> {
+        self.authorize_connection(token, actions, None)
+    }
+
+    /// Same as `authorize`, on a connection that may have presented a verified client certificate.
+    pub fn authorize_connection(
+        &self,
+        token: Option<&str>,
+        actions: &[Action],
+        client_certificate: Option<&ClientCertificate>,
+    ) -> Result<()> {
+        let identity = self.authenticate(token)?;
+        if identity.role == Role::Operator && self.operator_certificates {
+            let bound = match (client_certificate, &identity.certificate) {
+                (Some(certificate), Some(name)) => certificate.is_issued_to(name),
+                _ => false,
+            };
+            if !bound {
+                return Err(BlockchainError::Forbidden(
+                    "operator requests need the operator's client certificate".to_string(),
+                )
+                .into());
+            }
+        }
+        actions.iter().try_for_each(|action| identity.authorize(action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles() {
+        let credentials = Credentials::from_json(
+            r#"[{"token": "op", "role": "operator", "certificate": "operator"},
+                {"token": "alice", "role": "user", "accounts": ["alice"]},
+                {"token": "audit", "role": "auditor"}]"#,
+        )
+        .unwrap();
+        let address = |address: &str| Address::parse(address).unwrap();
+        let alice = || Action::ReadAccount(address("alice"));
+        let spend = |from: &str| Action::transfer(Address::parse_sender(from).unwrap().as_ref());
+
+        assert!(credentials.authorize(None, &[Action::ReadChain]).is_err());
+        assert!(credentials.authorize(Some("nobody"), &[Action::ReadChain]).is_err());
+
+        assert!(credentials.authorize(Some("op"), &[spend(""), spend("bob"), alice()]).is_ok());
+
+        assert!(credentials.authorize(Some("alice"), &[Action::ReadChain, alice(), spend("alice")]).is_ok());
+        assert!(credentials.authorize(Some("alice"), &[spend("")]).is_err());
+        assert!(credentials.authorize(Some("alice"), &[spend("bob")]).is_err());
+        assert!(credentials.authorize(Some("alice"), &[Action::ReadAccount(address("bob"))]).is_err());
+
+        assert!(credentials.authorize(Some("audit"), &[Action::ReadChain, alice()]).is_ok());
+        assert!(credentials.authorize(Some("audit"), &[spend("alice")]).is_err());
+
+        let mut credentials = credentials;
+        credentials.require_operator_certificates().unwrap();
+        let certificate = |name: &str| {
+            let certified = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+            ClientCertificate::new(certified.cert.der().clone())
+        };
+        assert!(credentials.authorize(Some("op"), &[Action::ReadChain]).is_err());
+        assert!(credentials.authorize_connection(Some("op"), &[spend("")], Some(&certificate("operator"))).is_ok());
+        // A certificate issued to someone else does not vouch for the operator token
+        assert!(credentials.authorize_connection(Some("op"), &[spend("")], Some(&certificate("alice"))).is_err());
+        assert!(credentials.authorize(Some("alice"), &[spend("alice")]).is_ok());
+
+        let mut unbound = Credentials::from_json(r#"[{"token": "op", "role": "operator"}]"#).unwrap();
+        assert!(unbound.require_operator_certificates().is_err());
+        assert!(Credentials::from_json(r#"[{"token": "t", "role": "auditor", "certificate": "audit"}]"#).is_err());
+
+        assert!(Credentials::from_json(r#"[{"token": "t", "role": "user"}]"#).is_err());
+        assert!(Credentials::from_json(r#"[{"token": "t", "role": "admin"}]"#).is_err());
+        assert!(Credentials::from_json(r#"[{"token": "t", "role": "user", "accounts": ["alice 1"]}]"#).is_err());
+    }
+}
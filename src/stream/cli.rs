@@ -1,8 +1,18 @@
+use crate::blockchain::blockchain::BlockRange;
 use crate::errors::Result;
+use crate::proofs::encoding::ProofFormat;
+use crate::proofs::registry::{CircuitRegistry, CircuitSource};
+use crate::proofs::solvency::DEFAULT_RESERVE_ASSET;
+use crate::proofs::witness::WitnessBackend;
+use crate::stream::auth::Credentials;
 use crate::stream::client::Client;
+use crate::stream::config::Config;
 use crate::stream::server::Server;
-use clap::{arg, Command};
+use crate::stream::tls::{ClientTls, ServerTls};
+use clap::{arg, ArgAction, Command};
+use std::path::Path;
 use std::process::exit;
+use std::time::Duration;
 
 pub struct Cli {}
 
@@ -15,18 +25,165 @@ impl Cli {
             .version("0.1")
             .author("acyrntoine@gmail.com")
             .about("simple blockchain state")
+            .arg(arg!(--config <PATH> "'JSON config file with listen, http_listen and node addresses'").global(true))
+            .arg(arg!(--node <ADDRESS> "'Address of the node to connect to (default 127.0.0.1:8888)'").global(true))
+            .arg(arg!(--token <TOKEN> "'API token sent to nodes that require credentials'").global(true))
+            .arg(arg!(--"tls-cert" <PATH> "'PEM certificate chain: the node's, or the client's for operator connections'").global(true))
+            .arg(arg!(--"tls-key" <PATH> "'PEM private key of --tls-cert'").global(true))
+            .arg(
+                arg!(--"operator-key" <KEY> "'Hex ed25519 public key trusted to sign reserves statements, repeatable'")
+                    .global(true)
+                    .action(ArgAction::Append),
+            )
+            .arg(arg!(--"tls-ca" <PATH> "'PEM CA certificate: of the node for clients, of operator client certificates for the node'").global(true))
             .subcommand(
                 Command::new("balance")
                     .about("get balance in the blockchain")
                     .arg(arg!(<ADDRESS>"'The Address it gets the balance for'")),
             )
+            .subcommand(
+                Command::new("register")
+                    .about("get the secret salt your leaf is committed with")
+                    .arg(arg!(<ADDRESS>"'The Address to register'")),
+            )
             .subcommand(
                 Command::new("balance-history")
                     .about("get the historical balance and the merkle roots associated to it")
-                    .arg(arg!(<ADDRESS>"'The Address it gets the balance for'")),
+                    .arg(arg!(<ADDRESS>"'The Address it gets the balance for'"))
+                    .arg(arg!(--from <BLOCK> "'First block of the history'"))
+                    .arg(arg!(--to <BLOCK> "'Last block of the history'"))
+                    .arg(
+                        arg!(--"changes-only" "'Prove only the blocks where the balance changed, linked to the last root'")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(
+                        arg!(--"no-wait" "'Print the proof job id instead of waiting for the proof'")
+                            .action(ArgAction::SetTrue),
+                    ),
+            )
+            .subcommand(
+                Command::new("proof-job")
+                    .about("fetch a balance history proof job and verify it once it is ready")
+                    .arg(arg!(<ID>"'The proof job id'"))
+                    .arg(arg!(<ADDRESS>"'The Address the history was proven for'")),
+            )
+            .subcommand(
+                Command::new("start-node")
+                    .about("Create new blockchain")
+                    .arg(arg!(--depth <DEPTH> "'Merkle sum tree depth, selects the compiled circuits (only depth 2 is shipped, build others with circuits/compile.sh)'"))
+                    .arg(arg!(--witness <BACKEND> "'Witness generator: wasm (default), native or rust'"))
+                    .arg(arg!(--circuits <SOURCE> "'Circuits to prove with: compiled (default) or native'"))
+                    .arg(arg!(--"proof-format" <FORMAT> "'Proof encoding sent to clients: binary (default) or json'"))
+                    .arg(
+                        arg!(--"legacy-protocol" "'Serve the underscore text protocol instead of JSON-RPC'")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(arg!(--listen <ADDRESS> "'Address of the TCP listener (default 0.0.0.0:8888)'"))
+                    .arg(arg!(--"http-listen" <ADDRESS> "'Address of the HTTP API (default 0.0.0.0:8080)'"))
+                    .arg(arg!(--"max-frame-size" <BYTES> "'Largest JSON-RPC message read or written (default 64 MiB)'"))
+                    .arg(arg!(--workers <COUNT> "'Threads serving connections (default 8)'"))
+                    .arg(arg!(--"proof-workers" <COUNT> "'Threads building threshold, batch and non-membership proofs (default 2)'"))
+                    .arg(arg!(--"max-connections" <COUNT> "'Connections served or queued at once (default 64)'"))
+                    .arg(arg!(--timeout <SECONDS> "'Time a connection may spend reading and writing in total (default 30)'"))
+                    .arg(arg!(--"max-request-size" <BYTES> "'Largest request accepted (default 64 KiB)'"))
+                    .arg(arg!(--credentials <PATH> "'JSON file of API tokens and roles required on every request'"))
+                    .arg(arg!(--"history-dir" <DIR> "'Directory to keep balance history proofs in instead of memory'"))
+                    .arg(
+                        arg!(--async "'Serve on an async runtime, answering balances from a state snapshot'")
+                            .action(ArgAction::SetTrue),
+                    ),
             )
-            .subcommand(Command::new("start-node").about("Create new blockchain"))
             .subcommand(Command::new("verify").about("Verify Liabilities Proof"))
+            .subcommand(
+                Command::new("circuit-digests")
+                    .about("record the public parameter digest of every compiled circuit in the manifest"),
+            )
+            .subcommand(
+                Command::new("export-proof")
+                    .about("write a liabilities or inclusion proof bundle to a file for offline verification")
+                    .arg(arg!(<KIND>"'liabilities or inclusion'"))
+                    .arg(arg!([ADDRESS]"'The Address to prove, for inclusion proofs'"))
+                    .arg(arg!(--output <PATH> "'File to write the bundle to'").required(true))
+                    .arg(
+                        arg!(--json "'Write JSON instead of the binary format'")
+                            .action(ArgAction::SetTrue),
+                    ),
+            )
+            .subcommand(
+                Command::new("verify-file")
+                    .about("verify an exported proof bundle without a server")
+                    .arg(arg!(<PATH>"'The bundle file'")),
+            )
+            .subcommand(
+                Command::new("prove-balance")
+                    .about("prove that an address held at least an amount at a block, without revealing the balance")
+                    .arg(arg!(<ADDRESS>"'The Address to prove'"))
+                    .arg(arg!(--block <NUMBER> "'Block of the balance'").required(true))
+                    .arg(arg!(--min <AMOUNT> "'Threshold the balance is proven to reach'").required(true))
+                    .arg(arg!(--output <PATH> "'File to write the proof to'").required(true)),
+            )
+            .subcommand(
+                Command::new("verify-balance")
+                    .about("verify a balance threshold proof without a server")
+                    .arg(arg!(<PATH>"'The proof file'"))
+                    .arg(
+                        arg!(--liabilities <PATH> "'Exported liabilities bundle ending at or after the proof's block'")
+                            .required(true),
+                    )
+                    .arg(arg!(--min <AMOUNT> "'Reject proofs for a lower threshold'")),
+            )
+            .subcommand(
+                Command::new("batch-inclusion")
+                    .about("verify the balances of several addresses with one proof")
+                    .arg(arg!(<ADDRESS>... "'The Addresses to verify'"))
+                    .arg(arg!(--block <NUMBER> "'Block whose tree is checked, the head block by default'"))
+                    .arg(
+                        arg!(--liabilities <PATH> "'Exported liabilities bundle ending at or after the checked block'")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                Command::new("prove-absent")
+                    .about("prove that an address had no leaf in the tree at a block")
+                    .arg(arg!(<ADDRESS>"'The Address to prove absent'"))
+                    .arg(arg!(--block <NUMBER> "'Block whose tree is checked'").required(true))
+                    .arg(arg!(--output <PATH> "'File to write the proof to'").required(true)),
+            )
+            .subcommand(
+                Command::new("verify-absent")
+                    .about("verify a non-membership proof without a server")
+                    .arg(arg!(<PATH>"'The proof file'"))
+                    .arg(
+                        arg!(--liabilities <PATH> "'Exported liabilities bundle ending at or after the proof's block'")
+                            .required(true),
+                    )
+                    .arg(arg!(--address <ADDRESS> "'Check the absent leaf belongs to this address'"))
+                    .arg(arg!(--salt <SALT> "'Salt of the address, printed by prove-absent'")),
+            )
+            .subcommand(
+                Command::new("sign-reserves")
+                    .about("sign the operator's custody balances per asset for a block")
+                    .arg(arg!(<RESERVES>"'JSON file listing the amount held per asset'"))
+                    .arg(arg!(--key <PATH> "'Operator key file, created if missing'").required(true))
+                    .arg(arg!(--block <NUMBER> "'Block the reserves are declared for'").required(true))
+                    .arg(arg!(--output <PATH> "'File to write the signed statement to'").required(true)),
+            )
+            .subcommand(
+                Command::new("solvency")
+                    .about("check that the liabilities at the statement's block are covered by the reserves")
+                    .arg(arg!(<STATEMENT>"'Signed reserves statement'"))
+                    .arg(arg!(--asset <NAME> "'Reserve asset to compare with, native by default'"))
+                    .arg(
+                        arg!(--"hide-margin" "'Prove solvency without revealing the reserves'")
+                            .action(ArgAction::SetTrue),
+                    )
+                    .arg(arg!(--output <PATH> "'File to write the report to'")),
+            )
+            .subcommand(
+                Command::new("verify-solvency")
+                    .about("verify a solvency report without a server")
+                    .arg(arg!(<PATH>"'The report file'")),
+            )
             .subcommand(
                 Command::new("transfer")
                     .about("trasnfer in the blockchain")
@@ -42,27 +199,266 @@ impl Cli {
             )
             .get_matches();
 
-        if let Some(ref _matches) = matches.subcommand_matches("start-node") {
-            let server = Server::new()?;
-            server.run_server();
+        let config = Config::load(matches.get_one::<String>("config").map(Path::new))?;
+        let node = matches
+            .get_one::<String>("node")
+            .map_or(config.get_node(), |node| node.as_str());
+        let token = matches
+            .get_one::<String>("token")
+            .map(String::as_str)
+            .or(config.get_token());
+        let mut operator_keys = config.get_operator_keys().to_vec();
+        if let Some(keys) = matches.get_many::<String>("operator-key") {
+            operator_keys.extend(keys.cloned());
+        }
+        let tls_cert = matches.get_one::<String>("tls-cert").map(Path::new);
+        let tls_key = matches.get_one::<String>("tls-key").map(Path::new);
+        let tls_ca = matches.get_one::<String>("tls-ca").map(Path::new);
+        let tls_identity = match (tls_cert, tls_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err(failure::format_err!("--tls-cert and --tls-key go together")),
+        };
+        let client = || -> Result<Client> {
+            let mut client = Client::with_node(node)?;
+            if let Some(token) = token {
+                client.set_token(token);
+            }
+            if let Some(ca) = tls_ca {
+                client.set_tls(ClientTls::from_files(ca, tls_identity)?);
+            }
+            client.set_operator_keys(operator_keys.clone());
+            Ok(client)
+        };
+
+        if let Some(ref matches) = matches.subcommand_matches("start-node") {
+            let mut server = match matches.get_one::<String>("depth") {
+                Some(depth) => Server::with_depth(depth.parse()?)?,
+                None => Server::new()?,
+            };
+            if let Some(backend) = matches.get_one::<String>("witness") {
+                server.set_witness_backend(backend.parse::<WitnessBackend>()?);
+            }
+            if let Some(source) = matches.get_one::<String>("circuits") {
+                server.set_circuit_source(source.parse::<CircuitSource>()?);
+            }
+            if let Some(format) = matches.get_one::<String>("proof-format") {
+                server.set_proof_format(format.parse::<ProofFormat>()?);
+            }
+            server.set_legacy_protocol(matches.get_flag("legacy-protocol"));
+            server.set_listen_address(
+                matches.get_one::<String>("listen").map_or(config.get_listen(), |listen| listen.as_str()),
+            );
+            server.set_http_address(
+                matches
+                    .get_one::<String>("http-listen")
+                    .map_or(config.get_http_listen(), |http_listen| http_listen.as_str()),
+            );
+            if let Some(max_frame_size) = matches.get_one::<String>("max-frame-size") {
+                server.set_max_frame_size(max_frame_size.parse()?);
+            }
+            if let Some(workers) = matches.get_one::<String>("workers") {
+                server.set_workers(workers.parse()?);
+            }
+            if let Some(proof_workers) = matches.get_one::<String>("proof-workers") {
+                server.set_proof_workers(proof_workers.parse()?);
+            }
+            if let Some(max_connections) = matches.get_one::<String>("max-connections") {
+                server.set_max_connections(max_connections.parse()?);
+            }
+            if let Some(timeout) = matches.get_one::<String>("timeout") {
+                server.set_io_timeout(Duration::from_secs(timeout.parse()?));
+            }
+            if let Some(max_request_size) = matches.get_one::<String>("max-request-size") {
+                server.set_max_request_size(max_request_size.parse()?);
+            }
+            if let Some(history_dir) = matches.get_one::<String>("history-dir") {
+                server.set_history_dir(Path::new(history_dir).to_path_buf());
+            }
+            let credentials = matches
+                .get_one::<String>("credentials")
+                .map(String::as_str)
+                .or(config.get_credentials());
+            if let Some(path) = credentials {
+                server.set_credentials(Credentials::load(Path::new(path))?);
+            }
+            if let Some((cert, key)) = tls_identity {
+                server.set_tls(ServerTls::from_files(cert, key, tls_ca)?);
+            }
+            if matches.get_flag("async") {
+                server.run_async()?;
+            } else {
+                server.run_server()?;
+            }
         }
 
         if let Some(ref matches) = matches.subcommand_matches("balance") {
             if let Some(address) = matches.get_one::<String>("ADDRESS") {
-                let client = Client::new()?;
+                let client = client()?;
                 client.get_balance(address);
             }
         }
 
+        if let Some(ref matches) = matches.subcommand_matches("register") {
+            if let Some(address) = matches.get_one::<String>("ADDRESS") {
+                let client = client()?;
+                client.register(address);
+            }
+        }
+
+        if let Some(ref _matches) = matches.subcommand_matches("circuit-digests") {
+            for circuit_id in CircuitRegistry::new()?.record_params_digests()? {
+                println!("Recorded parameter digest of {}", circuit_id);
+            }
+        }
+
         if let Some(ref _matches) = matches.subcommand_matches("verify") {
-            let client = Client::new()?;
+            let client = client()?;
             client.verify_liabilities();
         }
 
+        if let Some(ref matches) = matches.subcommand_matches("export-proof") {
+            if let (Some(kind), Some(output)) = (
+                matches.get_one::<String>("KIND"),
+                matches.get_one::<String>("output"),
+            ) {
+                let address = matches.get_one::<String>("ADDRESS").map(|a| a.as_str());
+                let format = if matches.get_flag("json") {
+                    ProofFormat::Json
+                } else {
+                    ProofFormat::Binary
+                };
+                let client = client()?;
+                client.export_proof(kind, address, Path::new(output), format);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("verify-file") {
+            if let Some(path) = matches.get_one::<String>("PATH") {
+                let client = client()?;
+                client.verify_file(Path::new(path));
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("prove-balance") {
+            if let (Some(address), Some(block), Some(min), Some(output)) = (
+                matches.get_one::<String>("ADDRESS"),
+                matches.get_one::<String>("block"),
+                matches.get_one::<String>("min"),
+                matches.get_one::<String>("output"),
+            ) {
+                let client = client()?;
+                client.prove_balance(address, block.parse()?, min.parse()?, Path::new(output));
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("verify-balance") {
+            if let (Some(path), Some(liabilities)) = (
+                matches.get_one::<String>("PATH"),
+                matches.get_one::<String>("liabilities"),
+            ) {
+                let minimum = match matches.get_one::<String>("min") {
+                    Some(min) => Some(min.parse()?),
+                    None => None,
+                };
+                let client = client()?;
+                client.verify_balance(Path::new(path), Path::new(liabilities), minimum);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("batch-inclusion") {
+            if let (Some(addresses), Some(liabilities)) = (
+                matches.get_many::<String>("ADDRESS"),
+                matches.get_one::<String>("liabilities"),
+            ) {
+                let addresses: Vec<String> = addresses.cloned().collect();
+                let block = match matches.get_one::<String>("block") {
+                    Some(block) => Some(block.parse()?),
+                    None => None,
+                };
+                let client = client()?;
+                client.batch_inclusion(&addresses, block, Path::new(liabilities));
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("prove-absent") {
+            if let (Some(address), Some(block), Some(output)) = (
+                matches.get_one::<String>("ADDRESS"),
+                matches.get_one::<String>("block"),
+                matches.get_one::<String>("output"),
+            ) {
+                let client = client()?;
+                client.prove_absent(address, block.parse()?, Path::new(output));
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("verify-absent") {
+            if let (Some(path), Some(liabilities)) = (
+                matches.get_one::<String>("PATH"),
+                matches.get_one::<String>("liabilities"),
+            ) {
+                let client = client()?;
+                client.verify_absent(
+                    Path::new(path),
+                    Path::new(liabilities),
+                    matches.get_one::<String>("address").map(|address| address.as_str()),
+                    matches.get_one::<String>("salt").map(|salt| salt.as_str()),
+                );
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("sign-reserves") {
+            if let (Some(reserves), Some(key), Some(block), Some(output)) = (
+                matches.get_one::<String>("RESERVES"),
+                matches.get_one::<String>("key"),
+                matches.get_one::<String>("block"),
+                matches.get_one::<String>("output"),
+            ) {
+                let client = client()?;
+                client.sign_reserves(Path::new(reserves), Path::new(key), block.parse()?, Path::new(output));
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("solvency") {
+            if let Some(statement) = matches.get_one::<String>("STATEMENT") {
+                let asset = matches
+                    .get_one::<String>("asset")
+                    .map(|a| a.as_str())
+                    .unwrap_or(DEFAULT_RESERVE_ASSET);
+                let output = matches.get_one::<String>("output").map(Path::new);
+                let client = client()?;
+                client.solvency(Path::new(statement), asset, matches.get_flag("hide-margin"), output);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("verify-solvency") {
+            if let Some(path) = matches.get_one::<String>("PATH") {
+                let client = client()?;
+                client.verify_solvency(Path::new(path));
+            }
+        }
+
         if let Some(ref matches) = matches.subcommand_matches("balance-history") {
             if let Some(address) = matches.get_one::<String>("ADDRESS") {
-                let client = Client::new()?;
-                client.get_balance_history(address);
+                let wait = !matches.get_flag("no-wait");
+                let from = match matches.get_one::<String>("from") {
+                    Some(from) => Some(from.parse()?),
+                    None => None,
+                };
+                let to = match matches.get_one::<String>("to") {
+                    Some(to) => Some(to.parse()?),
+                    None => None,
+                };
+                let range = BlockRange::new(from, to)?;
+                let client = client()?;
+                client.get_balance_history(address, range, matches.get_flag("changes-only"), wait);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("proof-job") {
+            if let (Some(id), Some(address)) = (matches.get_one::<String>("ID"), matches.get_one::<String>("ADDRESS")) {
+                let client = client()?;
+                client.get_job(id, address);
             }
         }
 
@@ -88,7 +484,7 @@ impl Cli {
                 exit(1)
             };
 
-            let client = Client::new()?;
+            let client = client()?;
             client.add_transaction(from, to, amount);
         }
 
@@ -107,7 +503,7 @@ impl Cli {
                 exit(1)
             };
 
-            let client = Client::new()?;
+            let client = client()?;
             client.add_transaction("", id, amount);
         }
 
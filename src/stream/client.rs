@@ -1,18 +1,83 @@
 #![allow(clippy::expect_used, clippy::unwrap_used)]
-use crate::errors::Result;
-use crate::stream::requests::{
-    BlockInclusion, ProofOfInclusionWrapper, ProofOfLiabilitiesWrapper,
+use crate::blockchain::blockchain::BlockRange;
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::encoding::{ProofEncoding, ProofFormat};
+use crate::proofs::batch::ProofOfBatchInclusion;
+use crate::proofs::bundle::{
+    BlockInclusion, BlockWrapper, BundleReport, ProofBundle, ProofOfInclusionWrapper, ProofOfLiabilitiesWrapper,
 };
-use std::io::{BufRead, BufReader, Write};
+use crate::proofs::non_membership::ProofOfNonMembership;
+use crate::proofs::solvency::{
+    load_or_create_operator_key, AssetReserve, ReservesStatement, SolvencyReport,
+};
+use crate::proofs::threshold::ProofOfThreshold;
+use crate::proofs::util::leaf_commitment;
+use crate::stream::config::DEFAULT_NODE_ADDRESS;
+use crate::stream::framing::{open_frame, write_frame, MessageType, DEFAULT_MAX_FRAME_SIZE};
+use crate::stream::jobs::JobId;
+use crate::stream::requests::Balance;
+use crate::stream::rpc::{RpcError, RpcRequest, RpcResponse, FORBIDDEN};
+use crate::stream::tls::{ClientTls, Transport};
+use merkle_sum_tree::Leaf;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufReader, Read};
 use std::net::TcpStream;
-use std::str;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Responses at least this large report their download progress.
+const PROGRESS_MIN_BYTES: usize = 1024 * 1024;
 
 
-pub struct Client {}
+pub struct Client {
+    node: String,
+    next_id: AtomicU64,
+    max_frame_size: usize,
+    token: Option<String>,
+    tls: Option<ClientTls>,
+    // hex ed25519 keys trusted to sign reserves statements
+    operator_keys: Vec<String>,
+}
 
 impl Client {
     pub fn new() -> Result<Client> {
-        Ok(Client {})
+        Client::with_node(DEFAULT_NODE_ADDRESS)
+    }
+
+    /// A client for the node at `node`, e.g. `10.0.0.2:8888`.
+    pub fn with_node(node: &str) -> Result<Client> {
+        Ok(Client {
+            node: node.to_string(),
+            next_id: AtomicU64::new(1),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            token: None,
+            tls: None,
+            operator_keys: vec![],
+        })
+    }
+
+    /// Largest response accepted from the node.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// API token sent with every request, for nodes that require credentials.
+    pub fn set_token(&mut self, token: &str) {
+        self.token = Some(token.to_string());
+    }
+
+    /// Talks to the node over TLS.
+    pub fn set_tls(&mut self, tls: ClientTls) {
+        self.tls = Some(tls);
+    }
+
+    /// Operator public keys, as hex, that solvency reports must be signed with.
+    pub fn set_operator_keys(&mut self, operator_keys: Vec<String>) {
+        self.operator_keys = operator_keys;
     }
 
     pub fn get_balance(&self, address: &str) {
@@ -23,95 +88,454 @@ impl Client {
     }
 
     fn get_balance_internal(&self, address: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect("127.0.0.1:8888")?;
-        let mut buffer: Vec<u8> = Vec::with_capacity(512);
-        let input = format!("balance_{address}\n");
-        stream.write(input.as_bytes())?;
-
-        let mut reader = BufReader::new(&stream);
-        reader.read_until(b'\n', &mut buffer)?;
-        print!("{}", str::from_utf8(&buffer)?);
+        let balance: Balance = serde_json::from_value(self.call("balance", json!({ "address": address }))?)?;
+        println!("balance: {}", balance.balance);
         Ok(())
     }
 
-    pub fn get_balance_history(&self, address: &str) {
-        match self.get_balance_history_internal(address) {
+    pub fn get_balance_history(&self, address: &str, range: BlockRange, changes_only: bool, wait: bool) {
+        match self.get_balance_history_internal(address, range, changes_only, wait) {
             Ok(_) => {},
             Err(e) => eprintln!("Failed to get balance history: {}", e),
         }
     }
 
-    fn get_balance_history_internal(&self, address: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect("127.0.0.1:8888")?;
-        let mut buffer: Vec<u8> = Vec::with_capacity(512);
-        let input = format!("balance_history_{address}\n");
-        stream.write(input.as_bytes())?;
+    fn get_balance_history_internal(
+        &self,
+        address: &str,
+        range: BlockRange,
+        changes_only: bool,
+        wait: bool,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        if !wait {
+            let id = self.submit_balance_history(address, range, changes_only)?;
+            println!("Balance history proof job {} submitted", id);
+            return Ok(());
+        }
+        let leaf_id = self.fetch_salt(address)?.map(|salt| leaf_commitment(address, &salt));
+        let data = self.fetch_balance_history(address, range, changes_only)?;
+        self.verify_balance_history(data, leaf_id.as_deref())
+    }
+
+    /// The salt the node committed `address`'s leaf with, handed to its owner
+    /// along with the balance. `None` when the client has no token and the node
+    /// hands out no salts, as nodes without credentials cannot tell who the owner is.
+    fn fetch_salt(&self, address: &str) -> std::result::Result<Option<String>, Box<dyn std::error::Error>> {
+        let result = match self.call("balance", json!({ "address": address, "salt": true })) {
+            Ok(result) => result,
+            Err(e) if self.token.is_none() && e.downcast_ref::<RpcError>().map(RpcError::get_code) == Some(FORBIDDEN) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        let balance: Balance = serde_json::from_value(result)?;
+        Ok(Some(balance.salt.ok_or_else(|| format!("{} has no leaf yet", address))?))
+    }
+
+    pub fn register(&self, address: &str) {
+        match self.call("register", json!({ "address": address })) {
+            Ok(result) => println!("salt: {}", result["salt"].as_str().unwrap_or_default()),
+            Err(e) => eprintln!("Failed to register: {}", e),
+        }
+    }
 
-        let mut reader = BufReader::new(&stream);
+    pub fn get_job(&self, id: &str, address: &str) {
+        match self.get_job_internal(id, address) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to get proof job: {}", e),
+        }
+    }
 
-        reader.read_until(b'\n', &mut buffer)?;
-        let data = str::from_utf8(&buffer)?.to_string();
-        let deserialized: std::result::Result<ProofOfInclusionWrapper, failure::Error> =
-            ProofOfInclusionWrapper::deserialize(data.clone());
+    fn get_job_internal(&self, id: &str, address: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let status = self.call("job", json!({ "id": id.trim(), "address": address }))?;
+        match status["status"].as_str() {
+            Some("pending") => println!("Proof job {} is still pending", id),
+            Some("done") => {
+                let leaf_id = self.fetch_salt(address)?.map(|salt| leaf_commitment(address, &salt));
+                self.verify_balance_history(job_proof(&status)?, leaf_id.as_deref())?
+            }
+            _ => println!("Proof job {} failed: {}", id, status["reason"].as_str().unwrap_or_default()),
+        }
+        Ok(())
+    }
 
-        let mut inclusion_outputs = vec![];
-        match deserialized {
+    /// Verifies an inclusion proof and that every proven leaf is the one of
+    /// `leaf_id`, when the node handed out the salt to derive it.
+    fn verify_balance_history(&self, data: String, leaf_id: Option<&str>) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        match <ProofOfInclusionWrapper>::decode(&data) {
             Ok(proof_wrapper) => {
                 println!("Received inclusion proof for {} unique trees", 
-                         proof_wrapper.get_proof().get_inclusion_inputs().len());
-                
-                // Verify the inclusion proof and extract all step_outs
+                         proof_wrapper.get_proof().get_claims().len());
                 println!("Starting client-side verification of inclusion folding...");
-                
-                // Extract components to verify the proof
-                let (proof, wrap_blocks, pp) = proof_wrapper.into_parts();
-
-                let verification_result = proof.verify(pp);
-                let blocks = &wrap_blocks;
-                
-                match verification_result {
-                    Ok(()) => {
-                        println!("Inclusion proof verification successful!");
-                        
-                        // Build human-readable history from verified proof
-                        for (inclusion, block) in proof
-                            .get_inclusion_inputs()
-                            .iter()
-                            .zip(blocks.iter())
-                        {
-                            let root_hash = inclusion.get_root_hash();
-                            let root_sum = inclusion.get_root_sum();
-                            let balance = inclusion.get_user_balance();
-                            let block_number = block.get_block_number();
-                            let timestamp = block.get_timestamp();
-                            let inclusion_output =
-                                BlockInclusion::new(balance, root_hash.to_string(), root_sum, block_number, timestamp.to_string());
-                            inclusion_outputs.push(inclusion_output);
+                match ProofBundle::from_inclusion(proof_wrapper).and_then(ProofBundle::verify) {
+                    Ok(report) => {
+                        match (&report, leaf_id) {
+                            (BundleReport::Inclusion(history), Some(leaf_id)) => {
+                                check_leaf_ownership(history, leaf_id).map_err(|e| e.compat())?
+                            }
+                            (BundleReport::Inclusion(_), None) => println!(
+                                "The node hands out no salts without credentials, so the proven leaves are not checked to be yours"
+                            ),
+                            _ => {}
                         }
-                        
-                        println!("\n=== Verified Balance History ===");
-                        for (i, entry) in inclusion_outputs.iter().enumerate() {
-                            println!("{}. Block {} | Balance: {} | Tree Sum: {} | Timestamp: {}", 
-                                     i + 1, 
-                                     entry.block_number(), 
-                                     entry.user_balance(), 
-                                     entry.root_sum(), 
-                                     entry.timestamp());
-                        }
-                        println!("=================================\n");
-                    }
-                    Err(e) => {
-                        println!("Inclusion proof verification failed: {}", e);
+                        print_report(&report);
                     }
+                    Err(e) => println!("Inclusion proof verification failed: {}", e),
+                }
+            }
+            Err(e) => println!("Cannot read inclusion proof: {}", e),
+        }
+        Ok(())
+    }
+
+    fn submit_balance_history(
+        &self,
+        address: &str,
+        range: BlockRange,
+        changes_only: bool,
+    ) -> std::result::Result<JobId, Box<dyn std::error::Error>> {
+        let params = json!({
+            "address": address,
+            "from": range.get_from(),
+            "to": range.get_to(),
+            "changes_only": changes_only,
+        });
+        let result = self.call("balance_history", params)?;
+        Ok(result["job"].as_str().ok_or("Response carries no job id")?.to_string())
+    }
+
+    /// Submits the balance history proof of `address` and waits for the job to finish.
+    fn fetch_balance_history(
+        &self,
+        address: &str,
+        range: BlockRange,
+        changes_only: bool,
+    ) -> std::result::Result<String, Box<dyn std::error::Error>> {
+        let id = self.submit_balance_history(address, range, changes_only)?;
+        println!("Balance history proof job {} submitted", id);
+        loop {
+            let status = self.call("job", json!({ "id": id, "address": address }))?;
+            match status["status"].as_str() {
+                Some("pending") => sleep(JOB_POLL_INTERVAL),
+                Some("done") => return job_proof(&status),
+                _ => {
+                    let reason = status["reason"].as_str().unwrap_or_default();
+                    return Err(format!("Proof job {} failed: {}", id, reason).into());
                 }
             }
-            Err(_) => {
-                println!("Failed to deserialize inclusion proof from server");
+        }
+    }
+
+    /// Fetches a proof from the node and writes it as a bundle for offline verification.
+    pub fn export_proof(&self, kind: &str, address: Option<&str>, path: &Path, format: ProofFormat) {
+        match self.export_proof_internal(kind, address, path, format) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to export proof: {}", e),
+        }
+    }
+
+    fn export_proof_internal(
+        &self,
+        kind: &str,
+        address: Option<&str>,
+        path: &Path,
+        format: ProofFormat,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let bundle = match (kind, address) {
+            ("liabilities", _) => {
+                let data = self.call_proof("liabilities_proof", Value::Null)?;
+                <ProofOfLiabilitiesWrapper>::decode(&data)
+                    .and_then(ProofBundle::from_liabilities)
+                    .map_err(|e| e.compat())?
+            }
+            ("inclusion", Some(address)) => {
+                let data = self.fetch_balance_history(address, BlockRange::all(), false)?;
+                <ProofOfInclusionWrapper>::decode(&data)
+                    .and_then(ProofBundle::from_inclusion)
+                    .map_err(|e| e.compat())?
+            }
+            ("inclusion", None) => return Err("Exporting an inclusion proof requires an address".into()),
+            _ => return Err(format!("Unknown proof kind '{}', expected liabilities or inclusion", kind).into()),
+        };
+        bundle.write(path, format).map_err(|e| e.compat())?;
+        println!(
+            "Wrote {} proof to {} (parameters digest {})",
+            kind,
+            path.display(),
+            bundle.get_pp_digest()
+        );
+        Ok(())
+    }
+
+    /// Verifies a bundle written by `export_proof`, without contacting a node.
+    pub fn verify_file(&self, path: &Path) {
+        match ProofBundle::read(path).and_then(|bundle| {
+            println!("Parameters digest: {}", bundle.get_pp_digest());
+            bundle.verify()
+        }) {
+            Ok(report) => print_report(&report),
+            Err(e) => eprintln!("Proof file verification failed: {}", e),
+        }
+    }
+
+    /// Asks the node for a proof that `address` held at least `threshold` at
+    /// `block_number` and writes it to `path` for a third party to check.
+    pub fn prove_balance(&self, address: &str, block_number: i32, threshold: u64, path: &Path) {
+        match self.prove_balance_internal(address, block_number, threshold, path) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to prove balance: {}", e),
+        }
+    }
+
+    fn prove_balance_internal(
+        &self,
+        address: &str,
+        block_number: i32,
+        threshold: u64,
+        path: &Path,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let params = json!({ "address": address, "block": block_number, "threshold": threshold });
+        let data = self.call_proof("threshold_proof", params)?;
+        match ProofOfThreshold::decode(&data) {
+            Ok(proof) => {
+                fs::write(path, proof.to_bytes().map_err(|e| e.compat())?)?;
+                println!(
+                    "Wrote proof that {} held at least {} at block {} to {}",
+                    address,
+                    threshold,
+                    block_number,
+                    path.display()
+                );
+            }
+            Err(e) => println!("Cannot read threshold proof: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Checks a proof written by `prove_balance` against its block as linked to
+    /// the head proven by the liabilities bundle at `liabilities`, which may have
+    /// been exported at any later block, optionally requiring a minimum threshold.
+    pub fn verify_balance(&self, path: &Path, liabilities: &Path, minimum: Option<u64>) {
+        let output = fs::read(path)
+            .map_err(failure::Error::from)
+            .and_then(|data| ProofOfThreshold::from_bytes(&data))
+            .and_then(|proof| proof.verify(&trusted_header(liabilities, proof.get_header().get_block_number())?));
+        match output {
+            Ok(output) if minimum.map_or(false, |minimum| output.get_threshold() < minimum) => println!(
+                "Proof only shows a balance of at least {}, below the required {}",
+                output.get_threshold(),
+                minimum.unwrap_or_default()
+            ),
+            Ok(output) => println!("{:#?}", output),
+            Err(e) => eprintln!("Balance proof verification failed: {}", e),
+        }
+    }
+
+    /// Requests one proof for the balances of `addresses` at `block_number`, or
+    /// at the head block without one, verifies it against the header of its block
+    /// in the `liabilities` bundle and prints a row per address.
+    pub fn batch_inclusion(&self, addresses: &[String], block_number: Option<i32>, liabilities: &Path) {
+        match self.batch_inclusion_internal(addresses, block_number, liabilities) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to verify batch inclusion: {}", e),
+        }
+    }
+
+    fn batch_inclusion_internal(
+        &self,
+        addresses: &[String],
+        block_number: Option<i32>,
+        liabilities: &Path,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let result = self.call("batch_inclusion_proof", json!({ "addresses": addresses, "block": block_number }))?;
+        let data = result["proof"].as_str().ok_or("Response carries no proof")?;
+        let proof = match ProofOfBatchInclusion::decode(data) {
+            Ok(proof) => proof,
+            Err(e) => {
+                println!("Cannot read batch inclusion proof: {}", e);
+                return Ok(());
+            }
+        };
+        let header = trusted_header(liabilities, proof.get_header().get_block_number()).map_err(|e| e.compat())?;
+        let claims = match proof.verify(&header) {
+            Ok(claims) => claims,
+            Err(e) => {
+                println!("Batch inclusion proof verification failed: {}", e);
+                return Ok(());
+            }
+        };
+        println!("\n=== Verified Balances at Block {} (Tree Sum: {}) ===", header.get_block_number(), header.get_root_sum());
+        println!("{:<24} {:>12}  {}", "Address", "Balance", "Status");
+        for address in addresses {
+            // Addresses without a leaf at the block have no leaf ID in the response.
+            // A claim is the address' own when its hash matches the salted leaf ID
+            let claim = result["leaf_ids"][address.as_str()].as_str().and_then(|leaf_id| {
+                claims.iter().find(|claim| {
+                    Leaf::new(leaf_id.to_string(), claim.get_user_balance()).get_node().get_hash().to_string()
+                        == claim.get_user_hash()
+                })
+            });
+            match claim {
+                Some(claim) => println!("{:<24} {:>12}  included", address, claim.get_user_balance()),
+                None => println!("{:<24} {:>12}  not proven", address, "-"),
+            }
+        }
+        println!("=================================\n");
+        Ok(())
+    }
+
+    pub fn prove_absent(&self, address: &str, block_number: i32, path: &Path) {
+        match self.prove_absent_internal(address, block_number, path) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to prove absence: {}", e),
+        }
+    }
+
+    fn prove_absent_internal(
+        &self,
+        address: &str,
+        block_number: i32,
+        path: &Path,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let data = self.call_proof("non_membership_proof", json!({ "address": address, "block": block_number }))?;
+        match ProofOfNonMembership::decode(&data) {
+            Ok(proof) => {
+                // Only owners are served the proof, so the node hands out the salt too
+                let salt = self.fetch_salt(address)?.ok_or("The node hands out no salts")?;
+                fs::write(path, proof.to_bytes().map_err(|e| e.compat())?)?;
+                println!(
+                    "Wrote proof that {} was not in the tree at block {} to {}",
+                    address,
+                    block_number,
+                    path.display()
+                );
+                println!("leaf id: {} salt: {}", proof.get_leaf_id(), salt);
             }
+            Err(e) => println!("Cannot read non-membership proof: {}", e),
         }
         Ok(())
     }
 
+    /// Checks a proof written by `prove_absent` against the header of its block in
+    /// the `liabilities` bundle. With `address` and `salt`, also checks that the
+    /// absent leaf is the one of that address.
+    pub fn verify_absent(&self, path: &Path, liabilities: &Path, address: Option<&str>, salt: Option<&str>) {
+        let output = fs::read(path)
+            .map_err(failure::Error::from)
+            .and_then(|data| ProofOfNonMembership::from_bytes(&data))
+            .and_then(|proof| proof.verify(&trusted_header(liabilities, proof.get_header().get_block_number())?));
+        match output {
+            Ok(output) => match address.zip(salt) {
+                Some((address, salt)) if leaf_commitment(address, salt) != output.get_leaf_id() => {
+                    println!("Proof is for leaf {}, not the leaf of {}", output.get_leaf_id(), address)
+                }
+                _ => println!("{:#?}", output),
+            },
+            Err(e) => eprintln!("Non-membership proof verification failed: {}", e),
+        }
+    }
+
+    /// Signs a JSON list of custody balances for `block_number` with the operator
+    /// key, creating the key file on first use. Runs without a node.
+    pub fn sign_reserves(&self, reserves: &Path, key: &Path, block_number: i32, output: &Path) {
+        let statement = AssetReserve::load_all(reserves).and_then(|reserves| {
+            let seed = load_or_create_operator_key(key)?;
+            let statement = ReservesStatement::sign(block_number, &reserves, &seed);
+            statement.write(output)?;
+            Ok(statement)
+        });
+        match statement {
+            Ok(statement) => println!(
+                "Signed reserves for block {} with operator key {}, wrote {}",
+                statement.get_block_number(),
+                statement.get_public_key(),
+                output.display()
+            ),
+            Err(e) => eprintln!("Failed to sign reserves: {}", e),
+        }
+    }
+
+    /// Sets the liabilities proven by the node's latest liabilities proof against
+    /// the reserves of `asset`. The statement must be for the block that proof ends at.
+    pub fn solvency(&self, statement: &Path, asset: &str, hide_margin: bool, output: Option<&Path>) {
+        match self.solvency_internal(statement, asset, hide_margin, output) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to build solvency report: {}", e),
+        }
+    }
+
+    fn solvency_internal(
+        &self,
+        statement: &Path,
+        asset: &str,
+        hide_margin: bool,
+        output: Option<&Path>,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let statement = ReservesStatement::load(statement).map_err(|e| e.compat())?;
+        let data = self.call_proof("liabilities_proof", Value::Null)?;
+        let liabilities = <ProofOfLiabilitiesWrapper>::decode(&data)
+            .and_then(ProofBundle::from_liabilities)
+            .map_err(|e| e.compat())?;
+        let report = SolvencyReport::new(liabilities, asset, &statement, hide_margin).map_err(|e| e.compat())?;
+        let bytes = report.to_bytes().map_err(|e| e.compat())?;
+        println!("{:#?}", report.verify(&self.operator_keys).map_err(|e| e.compat())?);
+        if let Some(path) = output {
+            fs::write(path, bytes)?;
+            println!("Wrote solvency report to {}", path.display());
+        }
+        Ok(())
+    }
+
+    /// Verifies a report written by `solvency`, without contacting a node.
+    pub fn verify_solvency(&self, path: &Path) {
+        let output = fs::read(path)
+            .map_err(failure::Error::from)
+            .and_then(|data| SolvencyReport::from_bytes(&data))
+            .and_then(|report| report.verify(&self.operator_keys));
+        match output {
+            Ok(output) => println!("{:#?}", output),
+            Err(e) => eprintln!("Solvency report verification failed: {}", e),
+        }
+    }
+
+    /// Sends one JSON-RPC request and returns its result. Error objects are
+    /// returned as errors.
+    fn call(&self, method: &str, params: Value) -> std::result::Result<Value, Box<dyn std::error::Error>> {
+        let id = json!(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let stream = TcpStream::connect(&self.node)
+            .map_err(|e| format!("Cannot reach node at {}: {}", self.node, e))?;
+        let mut stream: Box<dyn Transport> = match &self.tls {
+            Some(tls) => Box::new(tls.connect(&self.node, stream).map_err(|e| e.compat())?),
+            None => Box::new(stream),
+        };
+        let mut request = RpcRequest::new(method, params, id.clone());
+        request.set_auth(self.token.clone());
+        let request = serde_json::to_vec(&request)?;
+        write_frame(&mut stream, MessageType::Request, &request, self.max_frame_size).map_err(|e| e.compat())?;
+
+        let mut frame = open_frame(&mut stream, self.max_frame_size, print_progress).map_err(|e| e.compat())?;
+        match frame.get_kind() {
+            MessageType::Response => {}
+            MessageType::Error => {
+                let mut message = String::new();
+                frame.read_to_string(&mut message)?;
+                return Err(format!("Node rejected the request: {}", message).into());
+            }
+            kind => return Err(format!("Unexpected {:?} frame from the node", kind).into()),
+        }
+        let response: RpcResponse = serde_json::from_reader(BufReader::with_capacity(64 * 1024, frame))?;
+        if response.get_id() != &id && response.get_error().is_none() {
+            return Err(format!("Response to request {} answers request {}", id, response.get_id()).into());
+        }
+        Ok(response.into_result()?)
+    }
+
+    /// Calls a method that answers with an encoded proof.
+    fn call_proof(&self, method: &str, params: Value) -> std::result::Result<String, Box<dyn std::error::Error>> {
+        let result = self.call(method, params)?;
+        Ok(result["proof"].as_str().ok_or("Response carries no proof")?.to_string())
+    }
+
     pub fn add_transaction(&self, from: &str, to: &str, amount: i32) {
         match self.add_transaction_internal(from, to, amount) {
             Ok(_) => {},
@@ -120,14 +544,8 @@ impl Client {
     }
 
     fn add_transaction_internal(&self, from: &str, to: &str, amount: i32) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect("127.0.0.1:8888")?;
-        let mut buffer: Vec<u8> = Vec::with_capacity(512);
-        let input = format!("transfer_{from}_{to}_{amount}\n");
-
-        stream.write(input.as_bytes())?;
-        let mut reader = BufReader::new(&stream);
-        reader.read_until(b'\n', &mut buffer)?;
-        print!("{}", str::from_utf8(&buffer)?);
+        self.call("transfer", json!({ "from": from, "to": to, "amount": amount }))?;
+        println!("transaction added to mempool!");
         Ok(())
     }
 
@@ -139,31 +557,84 @@ impl Client {
     }
 
     fn verify_liabilities_internal(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let mut stream = TcpStream::connect("127.0.0.1:8888")?;
-        let mut buffer: Vec<u8> = Vec::with_capacity(512);
-        let input = "verify_filler\n";
-
-        stream.write(input.as_bytes())?;
-        let mut reader = BufReader::new(&stream);
-        reader.read_until(b'\n', &mut buffer)?;
-
-        let data = str::from_utf8(&buffer)?.to_string();
-        let deserialized: std::result::Result<ProofOfLiabilitiesWrapper, failure::Error> =
-            ProofOfLiabilitiesWrapper::deserialize(data.clone());
-
-        match deserialized {
+        let data = self.call_proof("liabilities_proof", Value::Null)?;
+        match <ProofOfLiabilitiesWrapper>::decode(&data) {
             Ok(proof_of_liabilities_wrapper) => {
-                let liabilities_proof = proof_of_liabilities_wrapper.get_proof();
-                let res = liabilities_proof.verify(proof_of_liabilities_wrapper.get_pp());
-                match res {
-                    Ok(liabilities_output) => {
-                        println!("{:#?}", liabilities_output)
-                    }
+                match ProofBundle::from_liabilities(proof_of_liabilities_wrapper).and_then(ProofBundle::verify) {
+                    Ok(report) => print_report(&report),
                     Err(error) => println!("{:#?}", error),
                 }
             }
-            Err(_) => println!("{}", data),
+            Err(e) => println!("Cannot read liabilities proof: {}", e),
         }
         Ok(())
     }
 }
+
+fn print_report(report: &BundleReport) {
+    match report {
+        BundleReport::Liabilities(liabilities_output) => println!("{:#?}", liabilities_output),
+        BundleReport::Inclusion(history) => {
+            println!("Inclusion proof verification successful!");
+            println!("\n=== Verified Balance History ===");
+            for (i, entry) in history.iter().enumerate() {
+                println!("{}. Block {} | Balance: {} | Tree Sum: {} | Timestamp: {}", 
+                         i + 1, 
+                         entry.block_number(), 
+                         entry.user_balance(), 
+                         entry.root_sum(), 
+                         entry.timestamp());
+            }
+            println!("=================================\n");
+        }
+    }
+}
+
+/// The header of `block_number` as linked to the head proven by the liabilities
+/// bundle at `liabilities`.
+fn trusted_header(liabilities: &Path, block_number: i32) -> Result<BlockWrapper> {
+    let bundle = ProofBundle::read(liabilities)?;
+    let header = bundle.get_header(block_number).cloned().ok_or_else(|| {
+        failure::format_err!("{} has no header for block {}", liabilities.display(), block_number)
+    })?;
+    match bundle.verify()? {
+        BundleReport::Liabilities(_) => Ok(header),
+        BundleReport::Inclusion(_) => Err(failure::format_err!("{} is not a liabilities bundle", liabilities.display())),
+    }
+}
+
+/// Recomputes the leaf hash from the salted leaf ID and each proven balance,
+/// failing when a proven leaf is not the user's.
+fn check_leaf_ownership(history: &[BlockInclusion], leaf_id: &str) -> Result<()> {
+    let foreign: Vec<i32> = history
+        .iter()
+        .filter(|entry| {
+            let leaf = Leaf::new(leaf_id.to_string(), entry.user_balance());
+            leaf.get_node().get_hash().to_string() != entry.user_hash()
+        })
+        .map(|entry| entry.block_number())
+        .collect();
+    if !foreign.is_empty() {
+        return Err(BlockchainError::ProofVerification {
+            reason: format!("Proven leaves at blocks {:?} are not your leaf", foreign),
+        }
+        .into());
+    }
+    println!("All proven leaves match your salted leaf commitment");
+    Ok(())
+}
+
+/// Shows how much of a large response has arrived.
+fn print_progress(read: usize, total: usize) {
+    if total >= PROGRESS_MIN_BYTES {
+        eprint!("\rReceived {} of {} KiB", read / 1024, total / 1024);
+        if read == total {
+            eprintln!();
+        }
+    }
+}
+
+/// The encoded proof of a finished job.
+fn job_proof(status: &Value) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    Ok(status["proof"].as_str().ok_or("Finished job carries no proof")?.to_string())
+}
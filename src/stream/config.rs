@@ -0,0 +1,119 @@
+use crate::errors::Result;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:8888";
+pub const DEFAULT_HTTP_ADDRESS: &str = "0.0.0.0:8080";
+pub const DEFAULT_NODE_ADDRESS: &str = "127.0.0.1:8888";
+
+/// Path of the config file, when `--config` is not given.
+pub const CONFIG_ENV: &str = "BSOL_CONFIG";
+pub const LISTEN_ENV: &str = "BSOL_LISTEN";
+pub const HTTP_LISTEN_ENV: &str = "BSOL_HTTP_LISTEN";
+pub const NODE_ENV: &str = "BSOL_NODE";
+pub const CREDENTIALS_ENV: &str = "BSOL_CREDENTIALS";
+pub const TOKEN_ENV: &str = "BSOL_TOKEN";
+
+/// Network addresses of the node and the client, read from a JSON file such as
+/// `{"listen": "0.0.0.0:9000", "http_listen": "0.0.0.0:9080", "node": "10.0.0.2:9000"}`,
+/// plus the node's `credentials` file, the client's API `token` and the hex
+/// ed25519 `operator_keys` whose reserves statements the client trusts.
+/// Environment variables override the file, command line options override both.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    listen: Option<String>,
+    http_listen: Option<String>,
+    node: Option<String>,
+    credentials: Option<String>,
+    token: Option<String>,
+    operator_keys: Vec<String>,
+}
+
+impl Config {
+    /// Reads `path`, or the file named by `BSOL_CONFIG`, and applies the environment.
+    /// Without either, only the environment and the defaults are used.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = path.map(Path::to_path_buf).or_else(|| env::var_os(CONFIG_ENV).map(Into::into));
+        let mut config = match path {
+            Some(path) => {
+                let data = fs::read_to_string(&path)
+                    .map_err(|e| failure::format_err!("Cannot read config file {}: {}", path.display(), e))?;
+                Config::from_json(&data)
+                    .map_err(|e| failure::format_err!("Invalid config file {}: {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(|name| env::var(name).ok());
+        Ok(config)
+    }
+
+    pub fn from_json(data: &str) -> Result<Config> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        for (name, field) in [
+            (LISTEN_ENV, &mut self.listen),
+            (HTTP_LISTEN_ENV, &mut self.http_listen),
+            (NODE_ENV, &mut self.node),
+            (CREDENTIALS_ENV, &mut self.credentials),
+            (TOKEN_ENV, &mut self.token),
+        ] {
+            if let Some(value) = var(name).filter(|value| !value.is_empty()) {
+                *field = Some(value);
+            }
+        }
+    }
+
+    /// Address the node serves its TCP protocol on.
+    pub fn get_listen(&self) -> &str {
+        self.listen.as_deref().unwrap_or(DEFAULT_LISTEN_ADDRESS)
+    }
+
+    /// Address the node serves its HTTP API on.
+    pub fn get_http_listen(&self) -> &str {
+        self.http_listen.as_deref().unwrap_or(DEFAULT_HTTP_ADDRESS)
+    }
+
+    /// Address the client connects to.
+    pub fn get_node(&self) -> &str {
+        self.node.as_deref().unwrap_or(DEFAULT_NODE_ADDRESS)
+    }
+
+    /// File of API tokens the node requires, none by default.
+    pub fn get_credentials(&self) -> Option<&str> {
+        self.credentials.as_deref()
+    }
+
+    /// API token the client sends.
+    pub fn get_token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Operator public keys reserves statements must be signed with.
+    pub fn get_operator_keys(&self) -> &[String] {
+        &self.operator_keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let mut config = Config::from_json(r#"{"listen": "0.0.0.0:9000", "node": "10.0.0.2:9000"}"#).unwrap();
+        config.apply_env(|name| match name {
+            NODE_ENV => Some("node.internal:9000".to_string()),
+            HTTP_LISTEN_ENV => Some(String::new()),
+            _ => None,
+        });
+        assert_eq!(config.get_listen(), "0.0.0.0:9000");
+        assert_eq!(config.get_http_listen(), DEFAULT_HTTP_ADDRESS);
+        assert_eq!(config.get_node(), "node.internal:9000");
+        assert!(Config::from_json(r#"{"port": 9000}"#).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::errors::{BlockchainError, Result};
+use std::io::{self, Read, Take, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame either side accepts unless configured otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// One type byte followed by the payload length as a big-endian u32.
+const HEADER_SIZE: usize = 5;
+
+/// What a frame's payload holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A JSON-RPC request.
+    Request,
+    /// A JSON-RPC response.
+    Response,
+    /// A transport error as UTF-8 text, sent when a frame could not be read.
+    Error,
+}
+
+impl MessageType {
+    fn to_byte(self) -> u8 {
+        match self {
+            MessageType::Request => 1,
+            MessageType::Response => 2,
+            MessageType::Error => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<MessageType> {
+        match byte {
+            1 => Ok(MessageType::Request),
+            2 => Ok(MessageType::Response),
+            3 => Ok(MessageType::Error),
+            _ => Err(BlockchainError::Framing(format!("Unknown message type {}", byte)).into()),
+        }
+    }
+}
+
+fn encode_header(kind: MessageType, length: usize, max_frame_size: usize) -> Result<[u8; HEADER_SIZE]> {
+    check_length(length, max_frame_size)?;
+    let mut header = [0u8; HEADER_SIZE];
+    header[0] = kind.to_byte();
+    header[1..].copy_from_slice(&(length as u32).to_be_bytes());
+    Ok(header)
+}
+
+fn decode_header(header: &[u8; HEADER_SIZE], max_frame_size: usize) -> Result<(MessageType, usize)> {
+    let kind = MessageType::from_byte(header[0])?;
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    check_length(length, max_frame_size)?;
+    Ok((kind, length))
+}
+
+fn check_length(length: usize, max_frame_size: usize) -> Result<()> {
+    if length > max_frame_size {
+        return Err(BlockchainError::Framing(format!(
+            "Frame of {} bytes exceeds the {} byte limit",
+            length, max_frame_size
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    kind: MessageType,
+    payload: &[u8],
+    max_frame_size: usize,
+) -> Result<()> {
+    let header = encode_header(kind, payload.len(), max_frame_size)?;
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    kind: MessageType,
+    payload: &[u8],
+    max_frame_size: usize,
+) -> Result<()> {
+    let header = encode_header(kind, payload.len(), max_frame_size)?;
+    writer.write_all(&header).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The payload of a frame, streamed from the underlying reader. `progress` is
+/// called with the bytes read so far and the payload length after every read.
+pub struct FrameReader<R, F> {
+    kind: MessageType,
+    length: usize,
+    read: usize,
+    payload: Take<R>,
+    progress: F,
+}
+
+/// Reads a frame header and returns a reader over its payload. Frames longer
+/// than `max_frame_size` are rejected before any of the payload is read.
+pub fn open_frame<R: Read, F: FnMut(usize, usize)>(
+    mut reader: R,
+    max_frame_size: usize,
+    progress: F,
+) -> Result<FrameReader<R, F>> {
+    let mut header = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let (kind, length) = decode_header(&header, max_frame_size)?;
+    Ok(FrameReader {
+        kind,
+        length,
+        read: 0,
+        payload: reader.take(length as u64),
+        progress,
+    })
+}
+
+/// Reads a whole frame into memory.
+pub fn read_frame<R: Read>(reader: R, max_frame_size: usize) -> Result<(MessageType, Vec<u8>)> {
+    let mut frame = open_frame(reader, max_frame_size, |_, _| {})?;
+    let mut payload = Vec::with_capacity(frame.length);
+    frame.read_to_end(&mut payload)?;
+    Ok((frame.kind, payload))
+}
+
+pub async fn read_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_frame_size: usize,
+) -> Result<(MessageType, Vec<u8>)> {
+    let mut header = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut header).await?;
+    let (kind, length) = decode_header(&header, max_frame_size)?;
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload).await?;
+    Ok((kind, payload))
+}
+
+impl<R, F> FrameReader<R, F> {
+    pub fn get_kind(&self) -> MessageType {
+        self.kind
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+}
+
+impl<R: Read, F: FnMut(usize, usize)> Read for FrameReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.payload.read(buf)?;
+        if count == 0 && !buf.is_empty() && self.read < self.length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Frame ended after {} of {} bytes", self.read, self.length),
+            ));
+        }
+        if count > 0 {
+            self.read += count;
+            (self.progress)(self.read, self.length);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        // Newlines in the payload do not end the frame
+        let payload = b"{\"proof\":\n\"00ff\"}".repeat(1000);
+        let mut data = vec![];
+        write_frame(&mut data, MessageType::Response, &payload, DEFAULT_MAX_FRAME_SIZE).unwrap();
+        write_frame(&mut data, MessageType::Error, b"next", DEFAULT_MAX_FRAME_SIZE).unwrap();
+
+        let mut reader = data.as_slice();
+        let mut seen = vec![];
+        let mut frame = open_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE, |read, total| seen.push((read, total))).unwrap();
+        assert_eq!(frame.get_kind(), MessageType::Response);
+        let mut received = vec![];
+        frame.read_to_end(&mut received).unwrap();
+        assert_eq!(received, payload);
+        assert_eq!(seen.last(), Some(&(payload.len(), payload.len())));
+        assert_eq!(read_frame(&mut reader, 4).unwrap(), (MessageType::Error, b"next".to_vec()));
+    }
+
+    #[test]
+    fn test_async_frame_round_trip() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let mut data = vec![];
+            write_frame_async(&mut data, MessageType::Request, b"{}", 16).await.unwrap();
+            assert!(write_frame_async(&mut data, MessageType::Request, &[0; 32], 16).await.is_err());
+            // Both framings agree on the wire format
+            assert_eq!(read_frame(data.as_slice(), 16).unwrap(), (MessageType::Request, b"{}".to_vec()));
+            assert_eq!(
+                read_frame_async(&mut data.as_slice(), 16).await.unwrap(),
+                (MessageType::Request, b"{}".to_vec())
+            );
+            assert!(read_frame_async(&mut data.as_slice(), 1).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_frame_limits() {
+        let mut data = vec![];
+        assert!(write_frame(&mut data, MessageType::Request, &[0; 16], 8).is_err());
+        write_frame(&mut data, MessageType::Request, &[0; 16], 16).unwrap();
+        assert!(read_frame(data.as_slice(), 8).is_err());
+        // A truncated payload is an error, not a short frame
+        assert!(read_frame(&data[..10], 16).is_err());
+        assert!(read_frame([9, 0, 0, 0, 0].as_slice(), 16).is_err());
+    }
+}
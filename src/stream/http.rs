@@ -0,0 +1,377 @@
+use crate::blockchain::blockchain::{BlockRange, Blockchain, StateSnapshot};
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::encoding::ProofFormat;
+use crate::stream::address::Address;
+use crate::stream::auth::{Action, Credentials};
+use crate::stream::jobs::{JobStatus, ProofJobs};
+use crate::stream::requests::{
+    get_balance, get_balance_history, get_header, get_job, get_liabilities_proof, get_snapshot_balance, transfer,
+};
+use futures::sync::oneshot;
+use futures::{future, Future, Stream};
+use hyper::header::{Allow, Authorization, Bearer, ContentLength, ContentType};
+use hyper::server::{Http, Request, Response, Service};
+use hyper::{Method, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex, MutexGuard};
+use threadpool::ThreadPool;
+use tokio::sync::watch;
+
+pub const DEFAULT_HTTP_WORKERS: usize = 4;
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+type ResponseFuture = Box<dyn Future<Item = Response, Error = hyper::Error>>;
+
+#[derive(Deserialize)]
+struct TransferBody {
+    // empty when minting
+    #[serde(deserialize_with = "Address::deserialize_sender")]
+    from: Option<Address>,
+    to: Address,
+    amount: i32,
+}
+
+/// REST API over the same requests as the TCP protocols, for web frontends.
+/// Requests are served on a thread pool, so taking the blockchain lock or
+/// building a proof never blocks the event loop.
+#[derive(Clone)]
+pub struct RestService {
+    bc: Arc<Mutex<Blockchain>>,
+    jobs: Arc<ProofJobs>,
+    format: ProofFormat,
+    credentials: Option<Arc<Credentials>>,
+    pool: ThreadPool,
+    max_body_size: usize,
+    snapshots: Option<watch::Receiver<Arc<StateSnapshot>>>,
+    refuse_tokens: bool,
+}
+
+impl RestService {
+    pub fn new(bc: Arc<Mutex<Blockchain>>, jobs: Arc<ProofJobs>, format: ProofFormat) -> RestService {
+        RestService {
+            bc,
+            jobs,
+            format,
+            credentials: None,
+            pool: ThreadPool::with_name("http".to_string(), DEFAULT_HTTP_WORKERS),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            snapshots: None,
+            refuse_tokens: false,
+        }
+    }
+
+    /// Refuses every request carrying an API token, for nodes that only let
+    /// tokens travel encrypted.
+    pub fn set_refuse_tokens(&mut self, refuse_tokens: bool) {
+        self.refuse_tokens = refuse_tokens;
+    }
+
+    /// Answers balances from the latest state snapshot instead of locking the blockchain.
+    pub fn set_snapshots(&mut self, snapshots: watch::Receiver<Arc<StateSnapshot>>) {
+        self.snapshots = Some(snapshots);
+    }
+
+    /// Number of threads serving requests.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.pool = ThreadPool::with_name("http".to_string(), workers.max(1));
+    }
+
+    /// Largest request body read. Larger ones are answered with 413.
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Requires an `Authorization: Bearer <token>` header allowing each request.
+    pub fn set_credentials(&mut self, credentials: Arc<Credentials>) {
+        self.credentials = Some(credentials);
+    }
+
+    fn route(
+        &self,
+        method: &Method,
+        path: &str,
+        query: Option<&str>,
+        body: &[u8],
+        token: Option<&str>,
+    ) -> Result<(StatusCode, Value)> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            (&Method::Get, ["accounts", address, "balance"]) => {
+                let address = Address::parse(address)?;
+                self.authorize(token, Action::ReadAccount(address.clone()))?;
+                let balance = match &self.snapshots {
+                    Some(snapshots) => get_snapshot_balance(&snapshots.borrow(), &address),
+                    None => get_balance(self.lock()?, &address)?,
+                };
+                Ok((StatusCode::Ok, json!(balance)))
+            }
+            (&Method::Get, ["accounts", address, "history"]) => {
+                let address = Address::parse(address)?;
+                self.authorize(token, Action::ReadAccount(address.clone()))?;
+                self.history(&address, query)
+            }
+            (&Method::Post, ["transfers"]) => {
+                let body: TransferBody = serde_json::from_slice(body)
+                    .map_err(|e| BlockchainError::InvalidRequest(format!("Invalid transfer: {}", e)))?;
+                self.authorize(token, Action::transfer(body.from.as_ref()))?;
+                transfer(self.lock()?, body.from.as_ref(), &body.to, body.amount)?;
+                Ok((StatusCode::Accepted, json!({ "queued": true })))
+            }
+            (&Method::Get, ["blocks", block_number]) => {
+                self.authorize(token, Action::ReadChain)?;
+                let block_number = parse_block_number(block_number)?;
+                Ok((StatusCode::Ok, json!(get_header(self.lock()?, block_number)?)))
+            }
+            (&Method::Get, ["proofs", "liabilities", "latest"]) => {
+                self.authorize(token, Action::ReadChain)?;
+                let proof = get_liabilities_proof(self.lock()?, self.format)?;
+                Ok((StatusCode::Ok, json!({ "proof": proof })))
+            }
+            _ if !allowed_methods(path).is_empty() => {
+                Err(BlockchainError::MethodNotAllowed(format!("{} {}", method, path)).into())
+            }
+            _ => Err(BlockchainError::NotFound(format!("No route for {} {}", method, path)).into()),
+        }
+    }
+
+    /// Submits the history proof job, or finds the one already running for the
+    /// same history and head block, so clients poll the same URL until it is done.
+    fn history(&self, address: &Address, query: Option<&str>) -> Result<(StatusCode, Value)> {
+        let (mut from, mut to, mut changes_only) = (None, None, false);
+        for (key, value) in query.unwrap_or("").split('&').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "from" => from = Some(parse_block_number(value)?),
+                "to" => to = Some(parse_block_number(value)?),
+                "changes_only" => changes_only = value == "true",
+                _ => {}
+            }
+        }
+        let range = BlockRange::new(from, to)?;
+        let id = get_balance_history(self.lock()?, &self.jobs, address, range, changes_only)?;
+        match get_job(&self.jobs, &id, address)? {
+            JobStatus::Pending => Ok((StatusCode::Accepted, json!({ "job": id, "status": "pending" }))),
+            JobStatus::Done(proof) => Ok((StatusCode::Ok, json!({ "job": id, "status": "done", "proof": proof.as_str() }))),
+            JobStatus::Failed(reason) => Err(BlockchainError::ProofCreation(reason).into()),
+        }
+    }
+
+    fn authorize(&self, token: Option<&str>, action: Action) -> Result<()> {
+        if self.refuse_tokens && token.is_some() {
+            return Err(BlockchainError::Forbidden("API tokens are only accepted over TLS".to_string()).into());
+        }
+        match &self.credentials {
+            Some(credentials) => credentials.authorize(token, &[action]),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Blockchain>> {
+        Ok(self.bc.lock().map_err(|_| BlockchainError::MutexPoisoned)?)
+    }
+}
+
+impl Service for RestService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<dyn Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let service = self.clone();
+        let method = request.method().clone();
+        let path = request.path().to_string();
+        let query = request.query().map(str::to_string);
+        let token = request
+            .headers()
+            .get::<Authorization<Bearer>>()
+            .map(|authorization| authorization.token.clone());
+        let max_body_size = self.max_body_size;
+        let body = request.body().fold(Vec::new(), move |mut body, chunk| {
+            if body.len() + chunk.len() > max_body_size {
+                return Err(hyper::Error::TooLarge);
+            }
+            body.extend_from_slice(&chunk);
+            Ok(body)
+        });
+        Box::new(body.then(move |body| -> ResponseFuture {
+            let body = match body {
+                Ok(body) => body,
+                Err(hyper::Error::TooLarge) => {
+                    let error = format!("Request body exceeds {} bytes", max_body_size);
+                    return Box::new(future::ok(reply(StatusCode::PayloadTooLarge, json!({ "error": error }))));
+                }
+                Err(e) => return Box::new(future::err(e)),
+            };
+            let (sender, receiver) = oneshot::channel();
+            let pool = service.pool.clone();
+            pool.execute(move || {
+                // The client may already be gone
+                let _ = sender.send(service.route(&method, &path, query.as_deref(), &body, token.as_deref()));
+            });
+            Box::new(
+                receiver
+                    .map(move |routed| respond(&path, routed))
+                    .map_err(|_| hyper::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, "HTTP worker stopped"))),
+            )
+        }))
+    }
+}
+
+/// Serves the REST API on `address` until the server fails.
+pub fn serve(address: &str, service: RestService) -> Result<()> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| failure::format_err!("No address for {}", address))?;
+    let server = Http::new()
+        .bind(&address, move || Ok::<_, std::io::Error>(service.clone()))
+        .map_err(|e| failure::format_err!("Could not bind {}: {}", address, e))?;
+    println!("Serving HTTP API on {}", address);
+    server
+        .run()
+        .map_err(|e| failure::format_err!("HTTP server failed: {}", e))
+}
+
+/// Turns a routed request into a response, listing the allowed methods on a 405.
+fn respond(path: &str, routed: Result<(StatusCode, Value)>) -> Response {
+    match routed {
+        Ok((status, value)) => reply(status, value),
+        Err(e) => {
+            let status = status_code(&e);
+            let response = reply(status, json!({ "error": e.to_string() }));
+            if status == StatusCode::MethodNotAllowed {
+                response.with_header(Allow(allowed_methods(path)))
+            } else {
+                response
+            }
+        }
+    }
+}
+
+fn reply(status: StatusCode, value: Value) -> Response {
+    let body = value.to_string();
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::json())
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+/// Methods served on `path`, none when there is no such route.
+fn allowed_methods(path: &str) -> Vec<Method> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["accounts", _, "balance"] | ["accounts", _, "history"] | ["blocks", _] | ["proofs", "liabilities", "latest"] => {
+            vec![Method::Get]
+        }
+        ["transfers"] => vec![Method::Post],
+        _ => Vec::new(),
+    }
+}
+
+fn status_code(error: &failure::Error) -> StatusCode {
+    match error.downcast_ref::<BlockchainError>() {
+        Some(BlockchainError::NotFound(_)) => StatusCode::NotFound,
+        Some(BlockchainError::Unauthorized(_)) => StatusCode::Unauthorized,
+        Some(BlockchainError::Forbidden(_)) => StatusCode::Forbidden,
+        Some(BlockchainError::MethodNotAllowed(_)) => StatusCode::MethodNotAllowed,
+        Some(BlockchainError::InvalidRequest(_)) | Some(BlockchainError::InvalidTransaction { .. }) => {
+            StatusCode::BadRequest
+        }
+        _ => StatusCode::InternalServerError,
+    }
+}
+
+fn parse_block_number(block_number: &str) -> Result<i32> {
+    block_number
+        .parse()
+        .map_err(|_| BlockchainError::InvalidRequest(format!("Not a block number: {}", block_number)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> RestService {
+        let bc = Blockchain::create_blockchain().unwrap();
+        let jobs = Arc::new(ProofJobs::new(1, ProofFormat::default()));
+        RestService::new(Arc::new(Mutex::new(bc)), jobs, ProofFormat::default())
+    }
+
+    fn status(service: &RestService, method: Method, path: &str, body: &str) -> StatusCode {
+        match service.route(&method, path, None, body.as_bytes(), None) {
+            Ok((status, _)) => status,
+            Err(e) => status_code(&e),
+        }
+    }
+
+    #[test]
+    fn test_routes_and_status_codes() {
+        let service = service();
+        let funding = r#"{"from": "", "to": "alice", "amount": 10}"#;
+        assert_eq!(status(&service, Method::Post, "/transfers", funding), StatusCode::Accepted);
+        assert_eq!(status(&service, Method::Post, "/transfers", r#"{"to": "alice"}"#), StatusCode::BadRequest);
+        service.bc.lock().unwrap().add_block().unwrap();
+
+        let (_, balance) = service.route(&Method::Get, "/accounts/alice/balance", None, &[], None).unwrap();
+        assert_eq!(balance["balance"], json!(10));
+        assert_eq!(status(&service, Method::Get, "/blocks/1", ""), StatusCode::Ok);
+        assert_eq!(status(&service, Method::Get, "/blocks/99", ""), StatusCode::NotFound);
+        assert_eq!(status(&service, Method::Get, "/blocks/latest", ""), StatusCode::BadRequest);
+        assert_eq!(status(&service, Method::Get, "/accounts/alice!1/balance", ""), StatusCode::BadRequest);
+        assert_eq!(status(&service, Method::Delete, "/transfers", ""), StatusCode::MethodNotAllowed);
+        assert_eq!(status(&service, Method::Post, "/blocks/1", ""), StatusCode::MethodNotAllowed);
+        assert_eq!(status(&service, Method::Get, "/nowhere", ""), StatusCode::NotFound);
+        let refused = respond("/transfers", service.route(&Method::Delete, "/transfers", None, &[], None));
+        assert_eq!(refused.headers().get::<Allow>(), Some(&Allow(vec![Method::Post])));
+
+        let mut guarded = service.clone();
+        guarded.set_credentials(Arc::new(
+            Credentials::from_json(r#"[{"token": "alice", "role": "user", "accounts": ["alice"]}]"#).unwrap(),
+        ));
+        let code = |path: &str, token: Option<&str>| match guarded.route(&Method::Get, path, None, &[], token) {
+            Ok((status, _)) => status,
+            Err(e) => status_code(&e),
+        };
+        assert_eq!(code("/accounts/alice/balance", None), StatusCode::Unauthorized);
+        assert_eq!(code("/accounts/alice/balance", Some("alice")), StatusCode::Ok);
+        assert_eq!(code("/accounts/bob/balance", Some("alice")), StatusCode::Forbidden);
+        // al_ice is an account of its own, not alice's
+        let spend = br#"{"from": "al_ice", "to": "bob", "amount": 1}"#;
+        let refused = guarded.route(&Method::Post, "/transfers", None, spend, Some("alice")).unwrap_err();
+        assert_eq!(status_code(&refused), StatusCode::Forbidden);
+        let spend = br#"{"from": "al ice", "to": "bob", "amount": 1}"#;
+        let refused = guarded.route(&Method::Post, "/transfers", None, spend, Some("alice")).unwrap_err();
+        assert_eq!(status_code(&refused), StatusCode::BadRequest);
+
+        guarded.set_refuse_tokens(true);
+        let refused = guarded.route(&Method::Get, "/accounts/alice/balance", None, &[], Some("alice")).unwrap_err();
+        assert_eq!(status_code(&refused), StatusCode::Forbidden);
+    }
+
+    #[test]
+    fn test_balance_from_snapshot() {
+        let mut funded = Blockchain::create_blockchain().unwrap();
+        funded.add_transaction("", "alice", 10).unwrap();
+        funded.add_block().unwrap();
+        let (_sender, snapshots) = watch::channel(Arc::new(funded.get_state_snapshot()));
+
+        // The service's own blockchain is empty and locked, so only the snapshot can answer
+        let mut service = service();
+        service.set_snapshots(snapshots);
+        let _locked = service.bc.lock().unwrap();
+        let (_, balance) = service.route(&Method::Get, "/accounts/alice/balance", None, &[], None).unwrap();
+        assert_eq!(balance["balance"], json!(10));
+    }
+
+    #[test]
+    fn test_oversized_body_is_refused() {
+        let mut service = service();
+        service.set_max_body_size(16);
+        let request = Request::new(Method::Post, "/transfers".parse().unwrap())
+            .with_body(r#"{"from": "", "to": "alice", "amount": 10}"#);
+        assert_eq!(service.call(request).wait().unwrap().status(), StatusCode::PayloadTooLarge);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::blockchain::blockchain::{BlockRange, InclusionSetup, InclusionSnapshot};
+use crate::proofs::inclusion::InclusionFolding;
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::bundle::{BlockWrapper, ProofOfInclusionWrapper};
+use crate::proofs::encoding::{ProofEncoding, ProofFormat};
+use crate::proofs::setup::CircuitSetup;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+
+/// Random, so that a job's proof cannot be fetched by guessing its ID.
+pub type JobId = String;
+
+pub const DEFAULT_PROOF_WORKERS: usize = 2;
+/// How long a finished job's result can still be fetched.
+pub const DEFAULT_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+/// Jobs kept at once, pending or finished. The oldest finished ones are dropped first.
+pub const DEFAULT_MAX_JOBS: usize = 1024;
+/// Full histories kept at once. The least recently used are dropped first.
+pub const DEFAULT_MAX_HISTORIES: usize = 256;
+
+const HISTORY_EXTENSION: &str = "history";
+
+/// A full history folding and the headers of the blocks it covers.
+type History = (InclusionFolding, Vec<BlockWrapper>);
+
+#[derive(Debug, Clone)]
+pub enum JobStatus {
+    Pending,
+    Done(Arc<String>),
+    Failed(String),
+}
+
+struct Job {
+    // the address whose history the job proves, the only one that may fetch it
+    owner: String,
+    // submission order, IDs are random
+    seq: u64,
+    status: JobStatus,
+    // when the job stopped being pending
+    finished: Option<Instant>,
+}
+
+struct JobsState {
+    next_seq: u64,
+    jobs: HashMap<JobId, Job>,
+    // (address, head block hash, range, changes only) -> job that proves that history
+    cache: HashMap<(String, String, BlockRange, bool), JobId>,
+}
+
+/// The latest full history per address, extended by later requests instead of
+/// proving every tree again. At most `capacity` are kept, the least recently
+/// used are dropped first. With a directory they are written there instead of
+/// being held in memory.
+struct Histories<T> {
+    dir: Option<PathBuf>,
+    capacity: usize,
+    clock: u64,
+    // address -> (last use, the history when it is held in memory)
+    entries: HashMap<String, (u64, Option<T>)>,
+}
+
+/// Builds balance-history proofs on a worker pool so that requests return a
+/// job ID immediately instead of holding the connection open while proving.
+/// Finished jobs are kept for `ttl`, and at most `max_jobs` are kept at once.
+pub struct ProofJobs {
+    format: ProofFormat,
+    pool: Mutex<ThreadPool>,
+    state: Mutex<JobsState>,
+    histories: Mutex<Histories<History>>,
+    ttl: Duration,
+    max_jobs: usize,
+}
+
+impl JobsState {
+    /// Drops finished jobs older than `ttl`, then the oldest finished jobs
+    /// while more than `max_jobs` are kept, and the cache entries for them.
+    fn evict(&mut self, now: Instant, ttl: Duration, max_jobs: usize) {
+        self.jobs.retain(|_, job| match job.finished {
+            Some(finished) => now.duration_since(finished) < ttl,
+            None => true,
+        });
+        let excess = self.jobs.len().saturating_sub(max_jobs);
+        let mut finished: Vec<(u64, &JobId)> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.finished.is_some())
+            .map(|(id, job)| (job.seq, id))
+            .collect();
+        finished.sort_unstable();
+        let oldest: Vec<JobId> = finished.into_iter().take(excess).map(|(_, id)| id.clone()).collect();
+        for id in oldest {
+            self.jobs.remove(&id);
+        }
+        let jobs = &self.jobs;
+        self.cache.retain(|_, id| jobs.contains_key(id));
+    }
+
+    fn finish(&mut self, id: &str, status: JobStatus) {
+        // The job may have been evicted meanwhile, then nobody can ask for it
+        if let Some(job) = self.jobs.get_mut(id) {
+            job.status = status;
+            job.finished = Some(Instant::now());
+        }
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> Histories<T> {
+    fn new(capacity: usize) -> Histories<T> {
+        Histories {
+            dir: None,
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Keeps histories in `dir` from now on. Files left by an earlier run are
+    /// removed, the chain they were proven on is gone.
+    fn set_dir(&mut self, dir: PathBuf) -> Result<()> {
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |extension| extension == HISTORY_EXTENSION) {
+                fs::remove_file(path)?;
+            }
+        }
+        self.entries.clear();
+        self.dir = Some(dir);
+        Ok(())
+    }
+
+    fn path(dir: &Path, address: &str) -> PathBuf {
+        dir.join(format!("{}.{}", hex::encode(Sha256::digest(address.as_bytes())), HISTORY_EXTENSION))
+    }
+
+    fn get(&mut self, address: &str) -> Option<T> {
+        self.clock += 1;
+        let (last_use, history) = self.entries.get_mut(address)?;
+        *last_use = self.clock;
+        match &self.dir {
+            // An unreadable file only costs proving the history again
+            Some(dir) => fs::read(Self::path(dir, address))
+                .ok()
+                .and_then(|data| bincode::deserialize(&data).ok()),
+            None => history.clone(),
+        }
+    }
+
+    fn insert(&mut self, address: &str, history: T) -> Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if !self.entries.contains_key(address) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (last_use, _))| *last_use)
+                .map(|(address, _)| address.clone());
+            if let Some(oldest) = oldest {
+                self.remove(&oldest);
+            }
+        }
+        let history = match &self.dir {
+            Some(dir) => {
+                fs::write(Self::path(dir, address), bincode::serialize(&history)?)?;
+                None
+            }
+            None => Some(history),
+        };
+        self.clock += 1;
+        self.entries.insert(address.to_string(), (self.clock, history));
+        Ok(())
+    }
+
+    fn remove(&mut self, address: &str) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_file(Self::path(dir, address));
+        }
+        self.entries.remove(address);
+    }
+}
+
+impl ProofJobs {
+    pub fn new(workers: usize, format: ProofFormat) -> ProofJobs {
+        ProofJobs::with_limits(workers, format, DEFAULT_JOB_TTL, DEFAULT_MAX_JOBS)
+    }
+
+    pub fn with_limits(workers: usize, format: ProofFormat, ttl: Duration, max_jobs: usize) -> ProofJobs {
+        ProofJobs {
+            format,
+            pool: Mutex::new(ThreadPool::new(workers)),
+            state: Mutex::new(JobsState {
+                next_seq: 0,
+                jobs: HashMap::new(),
+                cache: HashMap::new(),
+            }),
+            histories: Mutex::new(Histories::new(DEFAULT_MAX_HISTORIES)),
+            ttl,
+            max_jobs,
+        }
+    }
+
+    /// Writes full histories to `dir` instead of holding them in memory.
+    pub fn set_history_dir(&self, dir: PathBuf) -> Result<()> {
+        self.histories
+            .lock()
+            .map_err(|_| failure::format_err!("Mutex poisoned"))?
+            .set_dir(dir)
+    }
+
+    pub fn submit(
+        self: &Arc<Self>,
+        snapshot: InclusionSnapshot,
+        setup: InclusionSetup,
+    ) -> Result<JobId> {
+        let key = (
+            snapshot.get_address().to_string(),
+            snapshot.get_head_hash().to_string(),
+            snapshot.get_range(),
+            snapshot.is_changes_only(),
+        );
+        let id = {
+            let mut state = self
+                .state
+                .lock()
+                .map_err(|_| failure::format_err!("Mutex poisoned"))?;
+            state.evict(Instant::now(), self.ttl, self.max_jobs);
+            if let Some(id) = state.cache.get(&key) {
+                match state.jobs.get(id).map(|job| &job.status) {
+                    Some(JobStatus::Failed(_)) | None => {}
+                    Some(_) => return Ok(id.clone()),
+                }
+            }
+            if state.jobs.len() >= self.max_jobs {
+                return Err(BlockchainError::InvalidRequest(format!(
+                    "Too many pending proof jobs ({}), try again later",
+                    self.max_jobs
+                ))
+                .into());
+            }
+            let mut bytes = [0u8; 16];
+            OsRng.fill_bytes(&mut bytes);
+            let id = hex::encode(bytes);
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.jobs.insert(
+                id.clone(),
+                Job {
+                    owner: snapshot.get_address().to_string(),
+                    seq,
+                    status: JobStatus::Pending,
+                    finished: None,
+                },
+            );
+            state.cache.insert(key, id.clone());
+            id
+        };
+
+        let jobs = Arc::clone(self);
+        let job_id = id.clone();
+        let format = self.format;
+        let pool = self
+            .pool
+            .lock()
+            .map_err(|_| failure::format_err!("Mutex poisoned"))?;
+        pool.execute(move || {
+            let output = match setup {
+                InclusionSetup::Compiled(circuit_setup) => jobs.prove_history(&snapshot, &circuit_setup, format),
+                InclusionSetup::Native(circuit_setup) => snapshot
+                    .prove_native(&circuit_setup)
+                    .and_then(|(proof, headers, pp)| ProofOfInclusionWrapper::new(proof, headers, pp).encode(format)),
+            };
+            let status = match output {
+                Ok(output) => JobStatus::Done(Arc::new(output)),
+                Err(e) => JobStatus::Failed(e.to_string()),
+            };
+            if let Ok(mut state) = jobs.state.lock() {
+                state.finish(&job_id, status);
+            }
+        });
+        Ok(id)
+    }
+
+    /// Proves `snapshot`, extending the address' latest full history proof
+    /// when there is one. Native histories are always proven in full.
+    fn prove_history(&self, snapshot: &InclusionSnapshot, circuit_setup: &CircuitSetup, format: ProofFormat) -> Result<String> {
+        // Only full histories are kept, bounded ones would not line up
+        let full_history = snapshot.get_range() == BlockRange::all() && !snapshot.is_changes_only();
+        let previous = match self.histories.lock() {
+            Ok(mut histories) if full_history => histories.get(snapshot.get_address()),
+            _ => None,
+        };
+        let (folding, headers, pp) = snapshot.prove_from(circuit_setup, previous)?;
+        let proof = folding.compress(&pp)?;
+        if full_history {
+            if let Ok(mut histories) = self.histories.lock() {
+                // Not keeping it only costs proving it again next time
+                if let Err(e) = histories.insert(snapshot.get_address(), (folding, headers.clone())) {
+                    eprintln!("Cannot keep the history of {}: {}", snapshot.get_address(), e);
+                }
+            }
+        }
+        ProofOfInclusionWrapper::new(proof, headers, pp).encode(format)
+    }
+
+    /// The job's status, or `None` when it is unknown, has expired or proves
+    /// the history of an address other than `owner`.
+    pub fn status(&self, id: &str, owner: &str) -> Option<JobStatus> {
+        let mut state = self.state.lock().ok()?;
+        state.evict(Instant::now(), self.ttl, self.max_jobs);
+        state
+            .jobs
+            .get(id)
+            .filter(|job| job.owner == owner)
+            .map(|job| job.status.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finished_jobs_are_evicted() {
+        let mut state = JobsState {
+            next_seq: 3,
+            jobs: HashMap::new(),
+            cache: HashMap::new(),
+        };
+        let start = Instant::now();
+        // Submitted in the order c, a, b, so the eviction order is not the ID order
+        for (seq, id) in ["c", "a", "b"].into_iter().enumerate() {
+            state.jobs.insert(
+                id.to_string(),
+                Job {
+                    owner: "alice".to_string(),
+                    seq: seq as u64,
+                    status: JobStatus::Pending,
+                    finished: None,
+                },
+            );
+            state.cache.insert((id.to_string(), "head".to_string(), BlockRange::all(), false), id.to_string());
+        }
+        state.finish("c", JobStatus::Failed("failed".to_string()));
+        state.finish("a", JobStatus::Done(Arc::new("proof".to_string())));
+        let kept = |state: &JobsState| {
+            let mut ids: Vec<&str> = state.jobs.keys().map(String::as_str).collect();
+            ids.sort_unstable();
+            ids.join(",")
+        };
+
+        // Over the limit, the oldest finished job goes first, pending ones stay
+        state.evict(start, Duration::from_secs(60), 2);
+        assert_eq!(kept(&state), "a,b");
+        assert_eq!(state.cache.len(), 2);
+
+        state.evict(start + Duration::from_secs(120), Duration::from_secs(60), 2);
+        assert_eq!(kept(&state), "b");
+        assert_eq!(state.cache.len(), 1);
+    }
+
+    #[test]
+    fn test_jobs_are_only_shown_to_their_owner() {
+        let jobs = ProofJobs::new(1, ProofFormat::default());
+        jobs.state.lock().unwrap().jobs.insert(
+            "id".to_string(),
+            Job {
+                owner: "alice".to_string(),
+                seq: 0,
+                status: JobStatus::Pending,
+                finished: None,
+            },
+        );
+        assert!(matches!(jobs.status("id", "alice"), Some(JobStatus::Pending)));
+        assert!(jobs.status("id", "bob").is_none());
+        assert!(jobs.status("other", "alice").is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_history_is_dropped() {
+        let dir = std::env::temp_dir().join(format!("bsol-histories-{}", std::process::id()));
+        for on_disk in [false, true] {
+            let mut histories = Histories::new(2);
+            if on_disk {
+                histories.set_dir(dir.clone()).unwrap();
+            }
+            histories.insert("alice", "a".to_string()).unwrap();
+            histories.insert("bob", "b".to_string()).unwrap();
+            assert_eq!(histories.get("alice"), Some("a".to_string()));
+            // bob's history is the least recently used, so it makes room for carol's
+            histories.insert("carol", "c".to_string()).unwrap();
+            assert_eq!(histories.get("bob"), None);
+            assert_eq!(histories.get("alice"), Some("a".to_string()));
+            assert_eq!(histories.get("carol"), Some("c".to_string()));
+            if on_disk {
+                assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use crate::blockchain::blockchain::{BlockRange, Blockchain, StateSnapshot};
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::encoding::ProofFormat;
+use crate::stream::address::Address;
+use crate::stream::jobs::{JobStatus, ProofJobs};
+use crate::stream::requests::{
+    get_balance, get_balance_history, get_batch_inclusion_proof, get_header, get_job,
+    get_liabilities_proof, get_non_membership_proof, get_snapshot_balance, get_threshold_proof, transfer,
+};
+use std::sync::{Arc, Mutex};
+
+/// Whether answering `request` builds a threshold, batch or non-membership proof.
+pub fn builds_proof(request: &str) -> bool {
+    matches!(request.trim().split('_').next(), Some("threshold" | "batch" | "absent"))
+}
+
+/// Answers `balance_<address>` from a snapshot, `None` for requests it does not cover.
+pub fn respond_from_snapshot(request: &str, snapshot: &StateSnapshot) -> Option<Result<String>> {
+    match request.trim().split('_').collect::<Vec<&str>>().as_slice() {
+        ["balance", first, ..] if *first != "history" => Some(
+            balance_address(request)
+                .map(|address| format!("balance: {}", get_snapshot_balance(snapshot, &address).balance))
+                .or_else(plain_message),
+        ),
+        _ => None,
+    }
+}
+
+/// Answers one request of the underscore-separated text protocol, e.g.
+/// `balance_alice` or `transfer_alice_bob_10`, kept for older clients.
+/// Addresses may contain `_` themselves, so only `balance_<address>` and
+/// `job_<id>_<address>` take them; the other commands need addresses without one.
+pub fn handle_request(
+    request: &str,
+    bc: &Arc<Mutex<Blockchain>>,
+    jobs: &Arc<ProofJobs>,
+    format: ProofFormat,
+) -> Result<String> {
+    let bc = bc.lock().map_err(|_| BlockchainError::MutexPoisoned)?;
+    let parts = request.trim().split('_').collect::<Vec<&str>>();
+
+    let output: Result<String> = match parts[0] {
+        "transfer" => {
+            if parts.len() < 4 {
+                Err(failure::format_err!("Transfer requires 4 parameters"))
+            } else {
+                let amount: String = parts[3].chars().filter(|c| c.is_digit(10)).collect();
+                amount
+                    .parse()
+                    .map_err(failure::Error::from)
+                    .and_then(|amount| {
+                        let from = Address::parse_sender(parts[1])?;
+                        transfer(bc, from.as_ref(), &Address::parse(parts[2])?, amount)
+                    })
+                    .map(|_| "transaction added to mempool!".to_string())
+            }
+        }
+        "verify" => get_liabilities_proof(bc, format),
+        "threshold" => {
+            if parts.len() < 4 {
+                Err(failure::format_err!("Threshold proof requires address, block and threshold"))
+            } else {
+                parse(parts[2]).and_then(|block_number| {
+                    get_threshold_proof(bc, &Address::parse(parts[1])?, block_number, parse(parts[3])?, format)
+                })
+            }
+        }
+        "batch" => {
+            if parts.len() < 2 {
+                Err(failure::format_err!("Batch request requires at least one address"))
+            } else {
+                parts[1..]
+                    .iter()
+                    .map(|address| Address::parse(address))
+                    .collect::<Result<Vec<Address>>>()
+                    .and_then(|addresses| get_batch_inclusion_proof(bc, &addresses, None, format))
+                    .map(|batch| batch.proof)
+            }
+        }
+        "absent" => {
+            if parts.len() < 3 {
+                Err(failure::format_err!("Absence proof requires address and block"))
+            } else {
+                parse(parts[2]).and_then(|block_number| {
+                    get_non_membership_proof(bc, &Address::parse(parts[1])?, block_number, format)
+                })
+            }
+        }
+        // Salts are only handed to their owner, and the text protocol carries no token
+        "register" => Ok("Salts are only handed out over JSON-RPC with credentials".to_string()),
+        "header" => {
+            if parts.len() < 2 {
+                Err(failure::format_err!("Header request requires a block number"))
+            } else {
+                parse(parts[1])
+                    .and_then(|block_number| get_header(bc, block_number))
+                    .and_then(|header| Ok(serde_json::to_string(&header)?))
+            }
+        }
+        "job" => {
+            drop(bc);
+            if parts.len() < 3 {
+                Err(failure::format_err!("Job request requires job id and address"))
+            } else {
+                // `job_<id>_<address>`, IDs are hex so the address is the rest
+                let address = parts[2..].join("_");
+                Address::parse(&address)
+                    .and_then(|address| get_job(jobs, parts[1].trim(), &address))
+                    .map(|status| match status {
+                        JobStatus::Pending => "pending".to_string(),
+                        JobStatus::Done(output) => output.to_string(),
+                        JobStatus::Failed(reason) => format!("failed: {}", reason),
+                    })
+            }
+        }
+        "balance" => {
+            if parts.len() < 2 {
+                Err(failure::format_err!("Balance request requires address"))
+            } else if parts[1] == "history" {
+                if parts.len() < 3 {
+                    Err(failure::format_err!("Balance history requires address"))
+                } else {
+                    // `balance_history_<address>[_<from>_<to>[_changes]]`, `-` leaves a bound open
+                    let changes_only = parts.get(5).map_or(false, |mode| mode.trim() == "changes");
+                    parse_bound(parts.get(3).copied())
+                        .and_then(|from| BlockRange::new(from, parse_bound(parts.get(4).copied())?))
+                        .and_then(|range| get_balance_history(bc, jobs, &Address::parse(parts[2])?, range, changes_only))
+                        .map(|id| format!("job: {}", id))
+                }
+            } else {
+                balance_address(request)
+                    .and_then(|address| get_balance(bc, &address))
+                    .map(|balance| format!("balance: {}", balance.balance))
+            }
+        }
+        _ => Ok("Wrong command".to_string()),
+    };
+    output.or_else(plain_message)
+}
+
+/// The address of `balance_<address>`: everything after the command, so
+/// `balance_alice_1` asks for `alice_1`, not `alice`.
+fn balance_address(request: &str) -> Result<Address> {
+    let address = request.trim().split_once('_').map_or("", |(_, address)| address);
+    Address::parse(address)
+}
+
+/// The text protocol answers missing data and invalid requests with a plain message.
+fn plain_message(e: failure::Error) -> Result<String> {
+    match e.downcast_ref::<BlockchainError>() {
+        Some(BlockchainError::NotFound(message)) => Ok(message.clone()),
+        Some(BlockchainError::InvalidRequest(message)) => Ok(message.clone()),
+        _ => Err(e),
+    }
+}
+
+fn parse<T>(chars: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    Ok(chars.trim().parse()?)
+}
+
+fn parse_bound(bound_chars: Option<&str>) -> Result<Option<i32>> {
+    match bound_chars.map(str::trim) {
+        None | Some("") | Some("-") => Ok(None),
+        Some(bound) => Ok(Some(parse(bound)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_may_contain_underscores() {
+        let bc = Arc::new(Mutex::new(Blockchain::create_blockchain().unwrap()));
+        let jobs = Arc::new(ProofJobs::new(1, ProofFormat::default()));
+        let request = |request: &str| handle_request(request, &bc, &jobs, ProofFormat::default()).unwrap();
+        assert_eq!(request("transfer__alice_10"), "transaction added to mempool!");
+        bc.lock().unwrap().add_block().unwrap();
+
+        // balance_alice_1 is alice_1's balance, never alice's
+        assert_eq!(request("balance_alice_1\n"), "balance: 0");
+        assert_eq!(request("balance_alice"), "balance: 10");
+        let snapshot = bc.lock().unwrap().get_state_snapshot();
+        assert_eq!(respond_from_snapshot("balance_alice_1", &snapshot).unwrap().unwrap(), "balance: 0");
+        assert!(respond_from_snapshot("balance_history_alice", &snapshot).is_none());
+
+        // Jobs are only looked up for the address they prove
+        assert_eq!(request("job_a3f1_alice_1"), "unknown job: a3f1");
+        assert!(handle_request("job_a3f1", &bc, &jobs, ProofFormat::default()).is_err());
+    }
+}
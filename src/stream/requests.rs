@@ -1,187 +1,224 @@
-use crate::blockchain::blockchain::Blockchain;
-use crate::errors::Result;
-use crate::proofs::inclusion::ProofOfInclusion;
-use crate::proofs::liabilities::ProofOfLiabilities;
-use crate::proofs::setup::PP;
+use crate::blockchain::blockchain::{BlockRange, Blockchain, StateSnapshot};
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::batch::ProofOfBatchInclusion;
+use crate::proofs::bundle::BlockWrapper;
+use crate::proofs::encoding::{ProofEncoding, ProofFormat};
+use crate::proofs::inclusion::InclusionInput;
+use crate::proofs::native::NativeCircuitSetup;
+use crate::proofs::non_membership::ProofOfNonMembership;
+use crate::proofs::registry::{tree_depth, CircuitKind};
+use crate::proofs::threshold::ProofOfThreshold;
+use crate::stream::address::Address;
+use crate::stream::jobs::{JobId, JobStatus, ProofJobs};
 use serde::{Deserialize, Serialize};
-use std::sync::MutexGuard;
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct BlockInclusion {
-    user_balance: i32,
-    root_hash: String,
-    root_sum: i32,
-    block_number: i32,
-    timestamp: String,
-}
+use std::collections::BTreeMap;
+use std::sync::{Arc, MutexGuard};
 
+/// Balance of an address. The salt its leaf is committed with is only set
+/// when the owner asked for it, see `get_owner_balance`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlockWrapper {
-    root_hash: String,
-    root_sum: i32,
-    block_number: i32,
-    timestamp: String,
+pub struct Balance {
+    pub address: Address,
+    pub balance: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ProofOfInclusionWrapper {
-    proof: ProofOfInclusion,
-    wrap_blocks: Vec<BlockWrapper>,
-    pp: PP,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct ProofOfLiabilitiesWrapper {
-    proof: ProofOfLiabilities,
-    pp: PP,
+/// A batch inclusion proof and the leaf ID of every address it covers, so
+/// claims can be matched without asking for each salt.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchInclusion {
+    pub proof: String,
+    pub leaf_ids: BTreeMap<Address, String>,
 }
 
-impl BlockWrapper {
-    pub fn get_block_number(&self) -> i32 {
-        self.block_number
-    }
-
-    pub fn get_timestamp(&self) -> &str {
-        &self.timestamp
+/// Queues a transfer of `amount` to `to`, from `from` or minted without a sender.
+pub fn transfer(mut bc: MutexGuard<Blockchain>, from: Option<&Address>, to: &Address, amount: i32) -> Result<()> {
+    if amount <= 0 {
+        return Err(BlockchainError::InvalidTransaction {
+            reason: format!("Amount must be positive, got {}", amount),
+        }
+        .into());
     }
+    bc.add_transaction(from.map_or("", Address::as_str), to.as_str(), amount)
 }
 
-impl ProofOfInclusionWrapper {
-    pub fn get_proof(&self) -> &ProofOfInclusion {
-        &self.proof
-    }
-
-    pub fn into_parts(self) -> (ProofOfInclusion, Vec<BlockWrapper>, PP) {
-        (self.proof, self.wrap_blocks, self.pp)
-    }
-
-    pub fn serialize(self) -> String {
-        serde_json::to_string(&self).unwrap()
+/// Submits a balance history proof job for `address` over `range`. With
+/// `changes_only`, proves only the blocks where the address' leaf changed.
+pub fn get_balance_history(
+    bc: MutexGuard<Blockchain>,
+    jobs: &Arc<ProofJobs>,
+    address: &Address,
+    range: BlockRange,
+    changes_only: bool,
+) -> Result<JobId> {
+    let snapshot = bc.get_inclusion_snapshot(address.as_str(), range, changes_only);
+    let setup = bc.get_inclusion_setup()?;
+    // Release the blockchain before proving so block production is not blocked
+    drop(bc);
+    match snapshot {
+        Some(snapshot) => jobs.submit(snapshot, setup),
+        None => Err(BlockchainError::NotFound("No liabilities proof".to_string()).into()),
     }
+}
 
-    pub fn deserialize(proof_of_inclusion_wrapper: String) -> Result<ProofOfInclusionWrapper> {
-        match serde_json::from_str(&proof_of_inclusion_wrapper) {
-            Ok(data) => Ok(data),
-            Err(error) => Result::Err(error.into()),
-        }
-    }
+/// The status of job `id`, which must prove the history of `owner`. Jobs of
+/// other addresses are reported unknown, so their IDs cannot be probed.
+pub fn get_job(jobs: &ProofJobs, id: &str, owner: &Address) -> Result<JobStatus> {
+    jobs.status(id, owner.as_str())
+        .ok_or_else(|| BlockchainError::NotFound(format!("unknown job: {}", id)).into())
 }
 
-impl ProofOfLiabilitiesWrapper {
-    pub fn get_proof(&self) -> ProofOfLiabilities {
-        self.proof.clone()
-    }
+pub fn get_balance(bc: MutexGuard<Blockchain>, address: &Address) -> Result<Balance> {
+    Ok(Balance {
+        balance: bc.get_balance(address.as_str()),
+        address: address.clone(),
+        salt: None,
+    })
+}
 
-    pub fn get_pp(self) -> PP {
-        self.pp
-    }
+/// Same as `get_balance` with the salt of the account's leaf, which is set
+/// before its first leaf too. Callers must check the requester owns `address`.
+pub fn get_owner_balance(bc: MutexGuard<Blockchain>, address: &Address) -> Result<Balance> {
+    Ok(Balance {
+        balance: bc.get_balance(address.as_str()),
+        address: address.clone(),
+        salt: Some(bc.derive_salt(address.as_str())),
+    })
+}
 
-    pub fn serialize(self) -> String {
-        serde_json::to_string(&self).unwrap()
+/// Same as `get_balance`, read from a snapshot instead of the locked blockchain.
+pub fn get_snapshot_balance(snapshot: &StateSnapshot, address: &Address) -> Balance {
+    Balance {
+        balance: snapshot.get_balance(address.as_str()),
+        address: address.clone(),
+        salt: None,
     }
+}
 
-    pub fn deserialize(proof_of_liabilitie_wrapper: String) -> Result<ProofOfLiabilitiesWrapper> {
-        match serde_json::from_str(&proof_of_liabilitie_wrapper) {
-            Ok(data) => Ok(data),
-            Err(error) => Result::Err(error.into()),
-        }
+/// Hands out the secret salt the leaf of `address` is committed with. Salts
+/// are drawn when an account gets its first leaf, so this changes nothing and
+/// there are never more salts than accounts. Callers must check the requester
+/// owns `address`.
+pub fn register(bc: MutexGuard<Blockchain>, address: &Address) -> Result<String> {
+    match bc.get_salt(address.as_str()) {
+        Some(salt) => Ok(salt.to_string()),
+        None => Err(BlockchainError::NotFound(format!("No account for {}", address)).into()),
     }
 }
 
-impl BlockInclusion {
-    pub fn new(
-        user_balance: i32,
-        root_hash: String,
-        root_sum: i32,
-        block_number: i32,
-        timestamp: String,
-    ) -> BlockInclusion {
-        BlockInclusion {
-            user_balance,
-            root_hash,
-            root_sum,
-            block_number,
-            timestamp,
-        }
-    }
-    
-    pub fn user_balance(&self) -> i32 {
-        self.user_balance
-    }
-    
-    pub fn root_sum(&self) -> i32 {
-        self.root_sum
-    }
-    
-    pub fn block_number(&self) -> i32 {
-        self.block_number
-    }
-    
-    pub fn timestamp(&self) -> &str {
-        &self.timestamp
+pub fn get_header(bc: MutexGuard<Blockchain>, block_number: i32) -> Result<BlockWrapper> {
+    match bc.get_block(block_number) {
+        Some(block) => Ok(BlockWrapper::from_block(block)),
+        None => Err(BlockchainError::NotFound(format!("unknown block: {}", block_number)).into()),
     }
 }
 
-
-pub fn transfer(
-    mut bc: MutexGuard<Blockchain>,
-    from: &str,
-    to: &str,
-    amoun_chars: &str,
+/// Proves that `address` held at least `threshold` at `block_number`, keeping the balance private.
+pub fn get_threshold_proof(
+    bc: MutexGuard<Blockchain>,
+    address: &Address,
+    block_number: i32,
+    threshold: u64,
+    format: ProofFormat,
 ) -> Result<String> {
-    let amount: String = amoun_chars.chars().filter(|c| c.is_digit(10)).collect();
-    let _ = bc.add_transaction(
-        from,
-        to,
-        amount.parse().unwrap(),
-    );
-    Ok("transaction added to mempool!".to_string())
+    let block = match bc.get_block(block_number) {
+        Some(block) => block,
+        None => return Err(BlockchainError::NotFound(format!("unknown block: {}", block_number)).into()),
+    };
+    let tree = block.get_merkle_sum_tree();
+    let index = match (block.get_leaf_index(address.as_str()), bc.get_leaf_id(address.as_str())) {
+        (Some(index), Some(leaf_id)) if tree.get_leaf(index).map_or(false, |leaf| leaf.get_id() == leaf_id.as_str()) => index,
+        _ => {
+            return Err(
+                BlockchainError::NotFound(format!("No balance for {} at block {}", address, block_number)).into(),
+            )
+        }
+    };
+    let inclusion_input = InclusionInput::new(tree, index)?;
+    let header = BlockWrapper::from_block(block);
+    let circuit_setup = NativeCircuitSetup::new(CircuitKind::InclusionThreshold, tree_depth(tree), 1)?;
+    // Release the blockchain before proving so block production is not blocked
+    drop(bc);
+    ProofOfThreshold::new(&inclusion_input, threshold, header, &circuit_setup)?.encode(format)
 }
 
-pub fn get_balance_history(bc: MutexGuard<Blockchain>, address_chars: &str) -> Result<String> {
-    let address: String = address_chars.chars().filter(|c| c.is_alphanumeric()).collect();
-    let (inclusion_proof, blocks, pp) = bc.get_inclusion_proof(&address);
-    match inclusion_proof {
-        Some(proof) => {
-            let mut wrap_blocks = vec![];
-            for block in blocks.unwrap() {
-                let block_wrapper = BlockWrapper {
-                    root_hash: block
-                        .get_merkle_sum_tree()
-                        .get_root_hash()
-                        .unwrap()
-                        .to_string(),
-                    root_sum: block.get_merkle_sum_tree().get_root_sum().unwrap(),
-                    block_number: block.get_block_number(),
-                    timestamp: block.get_timestamp().to_string(),
-                };
-                wrap_blocks.push(block_wrapper)
-            }
-            let proof_wrapper = ProofOfInclusionWrapper {
-                proof,
-                wrap_blocks,
-                pp: pp.unwrap(),
-            };
-            Ok(proof_wrapper.serialize())
+/// Proves the balances of several addresses at `block_number`, or at the head
+/// block without one, in one proof. Addresses without a leaf there are left
+/// out, the client reports them as missing. Callers must check the requester
+/// owns every address, the leaf IDs are handed out with the proof.
+pub fn get_batch_inclusion_proof(
+    bc: MutexGuard<Blockchain>,
+    addresses: &[Address],
+    block_number: Option<i32>,
+    format: ProofFormat,
+) -> Result<BatchInclusion> {
+    let block = match block_number {
+        Some(block_number) => bc
+            .get_block(block_number)
+            .ok_or_else(|| BlockchainError::NotFound(format!("unknown block: {}", block_number)))?,
+        None => bc
+            .get_head_block()
+            .ok_or_else(|| BlockchainError::NotFound("No block".to_string()))?,
+    }
+    .clone();
+    let mut leaf_ids = BTreeMap::new();
+    let mut indexes = vec![];
+    for address in addresses {
+        if let (Some(index), Some(leaf_id)) = (block.get_leaf_index(address.as_str()), bc.get_leaf_id(address.as_str())) {
+            indexes.push(index);
+            leaf_ids.insert(address.clone(), leaf_id);
         }
-        None => Ok("No liabilities proof".to_string()),
     }
+    // Release the blockchain before proving so block production is not blocked
+    drop(bc);
+    let tree = block.get_merkle_sum_tree();
+    let inclusion_inputs = indexes
+        .into_iter()
+        .map(|index| InclusionInput::new(tree, index))
+        .collect::<Result<Vec<InclusionInput>>>()?;
+    if inclusion_inputs.is_empty() {
+        return Err(BlockchainError::NotFound(format!(
+            "No balance for any address at block {}",
+            block.get_block_number()
+        ))
+        .into());
+    }
+    let header = BlockWrapper::from_block(&block);
+    let circuit_setup = NativeCircuitSetup::new(CircuitKind::BatchInclusion, tree_depth(tree), 1)?;
+    let proof = ProofOfBatchInclusion::new(&inclusion_inputs, header, &circuit_setup)?.encode(format)?;
+    Ok(BatchInclusion { proof, leaf_ids })
 }
 
-pub fn get_balance(bc: MutexGuard<Blockchain>, address_chars: &str) -> Result<String> {
-    let address: String = address_chars.chars().filter(|c| c.is_alphanumeric()).collect();
-    let balance = bc.get_balance(&address);
-    let output = format!("balance: {}", balance);
-    Ok(output)
+/// Proves that the leaf of `address` was not in the tree at `block_number`.
+/// Addresses without a leaf yet are proven absent under the leaf ID they will get.
+pub fn get_non_membership_proof(
+    bc: MutexGuard<Blockchain>,
+    address: &Address,
+    block_number: i32,
+    format: ProofFormat,
+) -> Result<String> {
+    let leaf_id = bc.derive_leaf_id(address.as_str());
+    let block = match bc.get_block(block_number) {
+        Some(block) => block.clone(),
+        None => return Err(BlockchainError::NotFound(format!("unknown block: {}", block_number)).into()),
+    };
+    // Release the blockchain before proving so block production is not blocked
+    drop(bc);
+    if block.get_leaf_index(address.as_str()).is_some() {
+        return Err(BlockchainError::InvalidRequest(format!(
+            "{} is in the tree at block {}",
+            address, block_number
+        ))
+        .into());
+    }
+    let tree = block.get_merkle_sum_tree();
+    let header = BlockWrapper::from_block(&block);
+    let circuit_setup = NativeCircuitSetup::new(CircuitKind::NonMembership, tree_depth(tree), 1)?;
+    ProofOfNonMembership::new(tree, &leaf_id, header, &circuit_setup)?.encode(format)
 }
 
-pub fn get_liabilities_proof(bc: MutexGuard<Blockchain>) -> Result<String> {
-    let (proof, pp) = bc.get_liabilities_proof();
-    match proof {
-        Some(proof) => {
-            let proof_wrapper = ProofOfLiabilitiesWrapper { proof, pp };
-            Ok(proof_wrapper.serialize())
-        }
-        None => Ok("No liabilities proof".to_string()),
-    }
+pub fn get_liabilities_proof(bc: MutexGuard<Blockchain>, format: ProofFormat) -> Result<String> {
+    bc.get_liabilities_proof(format)?
+        .ok_or_else(|| BlockchainError::NotFound("No liabilities proof".to_string()).into())
 }
@@ -0,0 +1,574 @@
+use crate::blockchain::blockchain::{BlockRange, Blockchain, StateSnapshot};
+use crate::errors::BlockchainError;
+use crate::proofs::encoding::ProofFormat;
+use crate::stream::address::Address;
+use crate::stream::auth::{Action, Credentials};
+use crate::stream::jobs::{JobId, JobStatus, ProofJobs};
+use crate::stream::tls::ClientCertificate;
+use crate::stream::requests::{
+    get_balance, get_balance_history, get_batch_inclusion_proof, get_header, get_job,
+    get_liabilities_proof, get_non_membership_proof, get_owner_balance, get_snapshot_balance, get_threshold_proof,
+    register, transfer,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The block, account, job or proof asked for does not exist.
+pub const NOT_FOUND: i64 = -32001;
+/// The node could not build or check the requested proof.
+pub const PROOF_FAILED: i64 = -32002;
+/// The request carries no API token, or one the node does not know.
+pub const UNAUTHORIZED: i64 = -32003;
+/// The request's API token does not allow the call.
+pub const FORBIDDEN: i64 = -32004;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+    /// API token, when the node requires credentials.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcResponse {
+    jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct TransferParams {
+    // empty when minting
+    #[serde(deserialize_with = "Address::deserialize_sender")]
+    from: Option<Address>,
+    to: Address,
+    amount: i32,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    address: Address,
+}
+
+#[derive(Deserialize)]
+struct BalanceParams {
+    address: Address,
+    // only the owner may ask for the salt of the account's leaf
+    #[serde(default)]
+    salt: bool,
+}
+
+#[derive(Deserialize)]
+struct BalanceHistoryParams {
+    address: Address,
+    #[serde(default)]
+    from: Option<i32>,
+    #[serde(default)]
+    to: Option<i32>,
+    #[serde(default)]
+    changes_only: bool,
+}
+
+#[derive(Deserialize)]
+struct JobParams {
+    id: JobId,
+    // whose history the job proves
+    address: Address,
+}
+
+#[derive(Deserialize)]
+struct BlockParams {
+    block: i32,
+}
+
+#[derive(Deserialize)]
+struct ThresholdParams {
+    address: Address,
+    block: i32,
+    threshold: u64,
+}
+
+#[derive(Deserialize)]
+struct NonMembershipParams {
+    address: Address,
+    block: i32,
+}
+
+#[derive(Deserialize)]
+struct BatchParams {
+    addresses: Vec<Address>,
+    #[serde(default)]
+    block: Option<i32>,
+}
+
+/// A method and its parsed params.
+enum Params {
+    Transfer(TransferParams),
+    Balance(BalanceParams),
+    Register(AddressParams),
+    Header(BlockParams),
+    BalanceHistory(BalanceHistoryParams),
+    Job(JobParams),
+    LiabilitiesProof,
+    ThresholdProof(ThresholdParams),
+    BatchInclusionProof(BatchParams),
+    NonMembershipProof(NonMembershipParams),
+}
+
+/// A request with its params parsed once, so authorization and the answer
+/// act on the same values.
+pub struct RpcCall {
+    request: RpcRequest,
+    params: Params,
+}
+
+impl RpcRequest {
+    pub fn new(method: &str, params: Value, id: Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params,
+            id,
+            auth: None,
+        }
+    }
+
+    pub fn set_auth(&mut self, auth: Option<String>) {
+        self.auth = auth;
+    }
+
+    pub fn get_method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn get_params(&self) -> &Value {
+        &self.params
+    }
+
+    pub fn get_id(&self) -> &Value {
+        &self.id
+    }
+
+    pub fn get_auth(&self) -> Option<&str> {
+        self.auth.as_deref()
+    }
+}
+
+impl RpcResponse {
+    pub fn success(id: Value, result: Value) -> RpcResponse {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    pub fn failure(id: Value, error: RpcError) -> RpcResponse {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+
+    pub fn get_id(&self) -> &Value {
+        &self.id
+    }
+
+    pub fn get_error(&self) -> Option<&RpcError> {
+        self.error.as_ref()
+    }
+
+    /// The result, or the error object the server answered with.
+    pub fn into_result(self) -> std::result::Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn get_code(&self) -> i64 {
+        self.code
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<BlockchainError> for RpcError {
+    fn from(error: BlockchainError) -> RpcError {
+        let code = match error {
+            BlockchainError::NotFound(_) => NOT_FOUND,
+            BlockchainError::InvalidRequest(_) | BlockchainError::InvalidTransaction { .. } => INVALID_PARAMS,
+            BlockchainError::ProofCreation(_) | BlockchainError::ProofVerification { .. } => PROOF_FAILED,
+            BlockchainError::Unauthorized(_) => UNAUTHORIZED,
+            BlockchainError::Forbidden(_) => FORBIDDEN,
+            _ => INTERNAL_ERROR,
+        };
+        RpcError::new(code, error.to_string())
+    }
+}
+
+impl From<failure::Error> for RpcError {
+    fn from(error: failure::Error) -> RpcError {
+        match error.downcast::<BlockchainError>() {
+            Ok(error) => RpcError::from(error),
+            Err(error) => RpcError::new(INTERNAL_ERROR, error.to_string()),
+        }
+    }
+}
+
+/// Answers one JSON-RPC 2.0 request. Requests that are not valid JSON are
+/// answered with a null ID.
+pub fn handle_request(
+    request: &str,
+    bc: &Arc<Mutex<Blockchain>>,
+    jobs: &Arc<ProofJobs>,
+    format: ProofFormat,
+) -> RpcResponse {
+    match parse_request(request).and_then(parse_call) {
+        Ok(call) => respond(&call, bc, jobs, format),
+        Err(response) => response,
+    }
+}
+
+/// Parses a request, or returns the error response for it.
+pub fn parse_request(request: &str) -> std::result::Result<RpcRequest, RpcResponse> {
+    let value: Value = serde_json::from_str(request)
+        .map_err(|e| RpcResponse::failure(Value::Null, RpcError::new(PARSE_ERROR, format!("Parse error: {}", e))))?;
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request: RpcRequest = serde_json::from_value(value).map_err(|e| {
+        RpcResponse::failure(id.clone(), RpcError::new(INVALID_REQUEST, format!("Invalid request: {}", e)))
+    })?;
+    if request.jsonrpc != JSONRPC_VERSION {
+        return Err(RpcResponse::failure(
+            id,
+            RpcError::new(INVALID_REQUEST, format!("Unsupported jsonrpc version {}", request.jsonrpc)),
+        ));
+    }
+    Ok(request)
+}
+
+/// Parses the request's params, or returns the error response for them.
+pub fn parse_call(request: RpcRequest) -> std::result::Result<RpcCall, RpcResponse> {
+    let params = match request.method.as_str() {
+        "transfer" => parse_params(&request.params).map(Params::Transfer),
+        "balance" => parse_params(&request.params).map(Params::Balance),
+        "register" => parse_params(&request.params).map(Params::Register),
+        "header" => parse_params(&request.params).map(Params::Header),
+        "balance_history" => parse_params(&request.params).map(Params::BalanceHistory),
+        "job" => parse_params(&request.params).map(Params::Job),
+        "liabilities_proof" => Ok(Params::LiabilitiesProof),
+        "threshold_proof" => parse_params(&request.params).map(Params::ThresholdProof),
+        "batch_inclusion_proof" => parse_params(&request.params).map(Params::BatchInclusionProof),
+        "non_membership_proof" => parse_params(&request.params).map(Params::NonMembershipProof),
+        method => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    };
+    match params {
+        Ok(params) => Ok(RpcCall { request, params }),
+        Err(error) => Err(RpcResponse::failure(request.id.clone(), error)),
+    }
+}
+
+impl RpcCall {
+    pub fn get_request(&self) -> &RpcRequest {
+        &self.request
+    }
+
+    /// Whether answering builds a proof, which may take a while.
+    pub fn builds_proof(&self) -> bool {
+        matches!(
+            self.params,
+            Params::ThresholdProof(_) | Params::BatchInclusionProof(_) | Params::NonMembershipProof(_)
+        )
+    }
+}
+
+/// Whether the response discloses an account's salt or leaf ID.
+fn discloses_salt(params: &Params) -> bool {
+    match params {
+        Params::Balance(params) => params.salt,
+        Params::Register(_) | Params::NonMembershipProof(_) | Params::BatchInclusionProof(_) => true,
+        _ => false,
+    }
+}
+
+/// Checks the request's API token allows its method and params, or returns the error response.
+/// `client_certificate` is the verified client certificate the connection presented, if any.
+/// Without credentials anything is allowed but the calls that disclose salts or leaf IDs,
+/// which are only handed to their owner, and there is no owner to check.
+pub fn authorize(
+    call: &RpcCall,
+    credentials: Option<&Credentials>,
+    client_certificate: Option<&ClientCertificate>,
+) -> std::result::Result<(), RpcResponse> {
+    let request = &call.request;
+    let credentials = match credentials {
+        Some(credentials) => credentials,
+        None if discloses_salt(&call.params) => {
+            let error =
+                BlockchainError::Forbidden("salts and leaf IDs are only handed out on nodes with credentials".to_string());
+            return Err(RpcResponse::failure(request.id.clone(), RpcError::from(error)));
+        }
+        None => return Ok(()),
+    };
+    credentials
+        .authorize_connection(request.get_auth(), &actions(&call.params), client_certificate)
+        .map_err(|error| RpcResponse::failure(request.id.clone(), RpcError::from(error)))
+}
+
+/// What a call needs permission for.
+fn actions(params: &Params) -> Vec<Action> {
+    match params {
+        Params::Transfer(params) => vec![Action::transfer(params.from.as_ref())],
+        Params::Balance(BalanceParams { address, salt: false })
+        | Params::BalanceHistory(BalanceHistoryParams { address, .. })
+        | Params::Job(JobParams { address, .. })
+        | Params::ThresholdProof(ThresholdParams { address, .. }) => vec![Action::ReadAccount(address.clone())],
+        // Only the owner gets the account's salt or leaf ID
+        Params::Balance(BalanceParams { address, salt: true })
+        | Params::Register(AddressParams { address })
+        | Params::NonMembershipProof(NonMembershipParams { address, .. }) => {
+            vec![Action::ManageAccount(address.clone())]
+        }
+        Params::BatchInclusionProof(params) => params.addresses.iter().cloned().map(Action::ManageAccount).collect(),
+        Params::Header(_) | Params::LiabilitiesProof => vec![Action::ReadChain],
+    }
+}
+
+pub fn respond(call: &RpcCall, bc: &Arc<Mutex<Blockchain>>, jobs: &Arc<ProofJobs>, format: ProofFormat) -> RpcResponse {
+    match dispatch(&call.params, bc, jobs, format) {
+        Ok(result) => RpcResponse::success(call.request.id.clone(), result),
+        Err(error) => RpcResponse::failure(call.request.id.clone(), error),
+    }
+}
+
+/// Answers the read-only methods a state snapshot covers, `None` for the others.
+pub fn respond_from_snapshot(call: &RpcCall, snapshot: &StateSnapshot) -> Option<RpcResponse> {
+    match &call.params {
+        Params::Balance(params) if !params.salt => Some(RpcResponse::success(
+            call.request.id.clone(),
+            json!(get_snapshot_balance(snapshot, &params.address)),
+        )),
+        _ => None,
+    }
+}
+
+fn dispatch(
+    params: &Params,
+    bc: &Arc<Mutex<Blockchain>>,
+    jobs: &Arc<ProofJobs>,
+    format: ProofFormat,
+) -> std::result::Result<Value, RpcError> {
+    match params {
+        Params::Transfer(params) => {
+            transfer(lock(bc)?, params.from.as_ref(), &params.to, params.amount)?;
+            Ok(json!({ "queued": true }))
+        }
+        Params::Balance(params) if params.salt => Ok(json!(get_owner_balance(lock(bc)?, &params.address)?)),
+        Params::Balance(params) => Ok(json!(get_balance(lock(bc)?, &params.address)?)),
+        Params::Register(params) => Ok(json!({ "salt": register(lock(bc)?, &params.address)? })),
+        Params::Header(params) => Ok(json!(get_header(lock(bc)?, params.block)?)),
+        Params::BalanceHistory(params) => {
+            let range = BlockRange::new(params.from, params.to)?;
+            let id = get_balance_history(lock(bc)?, jobs, &params.address, range, params.changes_only)?;
+            Ok(json!({ "job": id }))
+        }
+        Params::Job(params) => Ok(match get_job(jobs, &params.id, &params.address)? {
+            JobStatus::Pending => json!({ "status": "pending" }),
+            JobStatus::Done(proof) => json!({ "status": "done", "proof": proof.as_str() }),
+            JobStatus::Failed(reason) => json!({ "status": "failed", "reason": reason }),
+        }),
+        Params::LiabilitiesProof => Ok(json!({ "proof": get_liabilities_proof(lock(bc)?, format)? })),
+        Params::ThresholdProof(params) => {
+            let proof = get_threshold_proof(lock(bc)?, &params.address, params.block, params.threshold, format)?;
+            Ok(json!({ "proof": proof }))
+        }
+        Params::BatchInclusionProof(params) => {
+            let batch = get_batch_inclusion_proof(lock(bc)?, &params.addresses, params.block, format)?;
+            Ok(json!({ "proof": batch.proof, "leaf_ids": batch.leaf_ids }))
+        }
+        Params::NonMembershipProof(params) => {
+            let proof = get_non_membership_proof(lock(bc)?, &params.address, params.block, format)?;
+            Ok(json!({ "proof": proof }))
+        }
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: &Value) -> std::result::Result<T, RpcError> {
+    serde_json::from_value(params.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+fn lock(bc: &Arc<Mutex<Blockchain>>) -> std::result::Result<MutexGuard<Blockchain>, RpcError> {
+    bc.lock().map_err(|_| RpcError::from(BlockchainError::MutexPoisoned))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node() -> (Arc<Mutex<Blockchain>>, Arc<ProofJobs>) {
+        let bc = Blockchain::create_blockchain().unwrap();
+        (Arc::new(Mutex::new(bc)), Arc::new(ProofJobs::new(1, ProofFormat::default())))
+    }
+
+    fn call(request: &str) -> RpcResponse {
+        let (bc, jobs) = node();
+        handle_request(request, &bc, &jobs, ProofFormat::default())
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let code = |response: RpcResponse| response.get_error().map(RpcError::get_code);
+        assert_eq!(code(call("balance_alice")), Some(PARSE_ERROR));
+        assert_eq!(code(call(r#"{"jsonrpc":"1.0","method":"balance","id":1}"#)), Some(INVALID_REQUEST));
+        assert_eq!(code(call(r#"{"jsonrpc":"2.0","method":"mint","id":1}"#)), Some(METHOD_NOT_FOUND));
+        assert_eq!(code(call(r#"{"jsonrpc":"2.0","method":"header","params":{"block":"x"},"id":1}"#)), Some(INVALID_PARAMS));
+        assert_eq!(code(call(r#"{"jsonrpc":"2.0","method":"header","params":{"block":99},"id":1}"#)), Some(NOT_FOUND));
+        assert_eq!(code(call(r#"{"jsonrpc":"2.0","method":"balance","params":{"address":"alice!"},"id":1}"#)), Some(INVALID_PARAMS));
+        assert_eq!(
+            code(call(r#"{"jsonrpc":"2.0","method":"transfer","params":{"from":"","to":"a b","amount":5},"id":1}"#)),
+            Some(INVALID_PARAMS)
+        );
+        let response = call(r#"{"jsonrpc":"2.0","method":"transfer","params":{"from":"","to":"bob","amount":-5},"id":7}"#);
+        assert_eq!(response.get_id(), &json!(7));
+        assert_eq!(code(response), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn test_balance_and_transfer() {
+        let (bc, jobs) = node();
+        let request = |method: &str, params: Value| {
+            let request = serde_json::to_string(&RpcRequest::new(method, params, json!("req"))).unwrap();
+            handle_request(&request, &bc, &jobs, ProofFormat::default()).into_result()
+        };
+        let queued = request("transfer", json!({ "from": "", "to": "alice", "amount": 10 })).unwrap();
+        assert_eq!(queued, json!({ "queued": true }));
+        bc.lock().unwrap().add_block().unwrap();
+        let balance = request("balance", json!({ "address": "alice" })).unwrap();
+        assert_eq!(balance["balance"], json!(10));
+        assert!(balance.get("salt").is_none());
+        let salt = request("register", json!({ "address": "alice" })).unwrap()["salt"].clone();
+        assert!(salt.is_string());
+        let owned = request("balance", json!({ "address": "alice", "salt": true })).unwrap();
+        assert_eq!(owned["balance"], json!(10));
+        assert_eq!(owned["salt"], salt);
+        assert!(request("register", json!({ "address": "bob" })).is_err());
+        // bob has no leaf yet, but already has the salt it will be committed with
+        let bob = request("balance", json!({ "address": "bob", "salt": true })).unwrap();
+        assert_eq!(bob["salt"], json!(bc.lock().unwrap().derive_salt("bob")));
+
+        let snapshot = bc.lock().unwrap().get_state_snapshot();
+        let read = |method: &str| {
+            let call = parse_call(RpcRequest::new(method, json!({ "address": "alice" }), json!(1))).unwrap();
+            respond_from_snapshot(&call, &snapshot)
+        };
+        assert_eq!(read("balance").unwrap().into_result().unwrap(), balance);
+        assert!(read("register").is_none());
+        let call = parse_call(RpcRequest::new("balance", json!({ "address": "alice", "salt": true }), json!(1))).unwrap();
+        assert!(respond_from_snapshot(&call, &snapshot).is_none());
+
+        // Underscores are part of the address, alice_1 is an account of its own
+        request("transfer", json!({ "from": "", "to": "alice_1", "amount": 5 })).unwrap();
+        bc.lock().unwrap().add_block().unwrap();
+        request("transfer", json!({ "from": "alice_1", "to": "alice", "amount": 2 })).unwrap();
+        bc.lock().unwrap().add_block().unwrap();
+        let balance = request("balance", json!({ "address": "alice_1" })).unwrap();
+        assert_eq!(balance, json!({ "address": "alice_1", "balance": 3 }));
+        assert_eq!(request("balance", json!({ "address": "alice" })).unwrap()["balance"], json!(12));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let credentials = Credentials::from_json(
+            r#"[{"token": "op", "role": "operator"}, {"token": "alice", "role": "user", "accounts": ["alice"]}]"#,
+        )
+        .unwrap();
+        let code = |method: &str, params: Value, auth: Option<&str>| {
+            let mut request = RpcRequest::new(method, params, json!(1));
+            request.set_auth(auth.map(str::to_string));
+            parse_call(request)
+                .and_then(|call| authorize(&call, Some(&credentials), None))
+                .err()
+                .and_then(|response| response.get_error().map(RpcError::get_code))
+        };
+        let funding = json!({ "from": "", "to": "alice", "amount": 10 });
+        assert_eq!(code("transfer", funding.clone(), None), Some(UNAUTHORIZED));
+        assert_eq!(code("transfer", funding.clone(), Some("alice")), Some(FORBIDDEN));
+        assert_eq!(code("transfer", funding, Some("op")), None);
+        assert_eq!(code("transfer", json!({ "from": "alice", "to": "bob", "amount": 1 }), Some("alice")), None);
+        // al_ice is an account of its own, not alice's
+        assert_eq!(code("transfer", json!({ "from": "al_ice", "to": "bob", "amount": 1 }), Some("alice")), Some(FORBIDDEN));
+        assert_eq!(code("transfer", json!({ "from": "al ice", "to": "bob", "amount": 1 }), Some("alice")), Some(INVALID_PARAMS));
+        assert_eq!(code("balance", json!({ "address": "bob" }), Some("alice")), Some(FORBIDDEN));
+        assert_eq!(code("batch_inclusion_proof", json!({ "addresses": ["alice", "bob"] }), Some("alice")), Some(FORBIDDEN));
+        assert_eq!(code("header", json!({ "block": 1 }), Some("alice")), None);
+        assert_eq!(code("job", json!({ "id": "a3f1", "address": "alice" }), Some("alice")), None);
+        assert_eq!(code("job", json!({ "id": "a3f1", "address": "bob" }), Some("alice")), Some(FORBIDDEN));
+        assert_eq!(code("job", json!({ "id": "a3f1" }), Some("alice")), Some(INVALID_PARAMS));
+        assert_eq!(code("register", json!({ "address": "alice" }), Some("alice")), None);
+        assert_eq!(code("register", json!({ "address": "bob" }), Some("alice")), Some(FORBIDDEN));
+        assert_eq!(code("balance", json!({ "address": "alice", "salt": true }), Some("alice")), None);
+        assert_eq!(code("balance", json!({ "address": "bob", "salt": true }), Some("op")), None);
+
+        let open = |method: &str, params: Value| {
+            let call = parse_call(RpcRequest::new(method, params, json!(1))).unwrap();
+            authorize(&call, None, None)
+        };
+        assert!(open("balance", json!({ "address": "alice" })).is_ok());
+        assert!(open("balance", json!({ "address": "alice", "salt": true })).is_err());
+        assert!(open("register", json!({ "address": "alice" })).is_err());
+        assert!(open("batch_inclusion_proof", json!({ "addresses": ["alice"] })).is_err());
+        // Histories are proven and polled for without a salt, the client then skips the ownership check
+        assert!(open("balance_history", json!({ "address": "alice" })).is_ok());
+        assert!(open("job", json!({ "id": "a3f1", "address": "alice" })).is_ok());
+    }
+}
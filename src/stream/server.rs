@@ -1,80 +1,431 @@
-use crate::blockchain::blockchain::Blockchain;
-use crate::errors::Result;
-use crate::stream::requests::{get_balance, get_balance_history, get_liabilities_proof, transfer};
-use std::io::{BufRead, BufReader, Write};
+use crate::blockchain::blockchain::{Blockchain, MAX_LEVELS};
+use crate::errors::{BlockchainError, Result};
+use crate::proofs::encoding::ProofFormat;
+use crate::proofs::registry::CircuitSource;
+use crate::proofs::witness::WitnessBackend;
+use crate::stream::auth::Credentials;
+use crate::stream::jobs::{ProofJobs, DEFAULT_PROOF_WORKERS};
+use crate::stream::config::{DEFAULT_HTTP_ADDRESS, DEFAULT_LISTEN_ADDRESS};
+use crate::stream::framing::{read_frame, write_frame, MessageType, DEFAULT_MAX_FRAME_SIZE};
+use crate::stream::http::{self, RestService};
+use crate::stream::rpc::{RpcCall, RpcError, RpcResponse, INTERNAL_ERROR, PROOF_FAILED};
+use crate::stream::tls::{ClientCertificate, ServerTls};
+use crate::stream::{legacy, rpc};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::{TcpListener, TcpStream};
-use std::str;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
 
-pub struct Server {}
+mod runtime;
+
+/// Time between two blocks.
+pub const BLOCK_INTERVAL: Duration = Duration::from_secs(10);
+pub const DEFAULT_CONNECTION_WORKERS: usize = 8;
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+pub const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024;
+pub const DEFAULT_PROOF_REQUEST_WORKERS: usize = 2;
+/// Proof requests running or waiting at once, per proof request worker.
+const PROOF_REQUESTS_PER_WORKER: usize = 4;
+
+pub struct Server {
+    depth: usize,
+    witness_backend: WitnessBackend,
+    circuit_source: CircuitSource,
+    proof_format: ProofFormat,
+    legacy_protocol: bool,
+    listen_address: String,
+    http_address: String,
+    max_frame_size: usize,
+    workers: usize,
+    proof_workers: usize,
+    max_connections: usize,
+    io_timeout: Duration,
+    max_request_size: usize,
+    credentials: Option<Arc<Credentials>>,
+    tls: Option<ServerTls>,
+    history_dir: Option<PathBuf>,
+}
+
+/// How one connection is served.
+#[derive(Clone)]
+struct ConnectionSettings {
+    format: ProofFormat,
+    legacy_protocol: bool,
+    max_frame_size: usize,
+    max_request_size: usize,
+    credentials: Option<Arc<Credentials>>,
+    tls: Option<ServerTls>,
+}
+
+impl ConnectionSettings {
+    /// Checks the request's API token when the node requires credentials.
+    fn authorize(
+        &self,
+        call: &RpcCall,
+        client_certificate: Option<&ClientCertificate>,
+    ) -> std::result::Result<(), RpcResponse> {
+        rpc::authorize(call, self.credentials.as_deref(), client_certificate)
+    }
+}
+
+/// A place among the connections being served or waiting for a worker, freed on drop.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+const BUSY_MESSAGE: &str = "Node is busy, try again later";
+const PROOFS_BUSY_MESSAGE: &str = "Node is busy building proofs, try again later";
+
+impl ConnectionSlot {
+    /// Takes a place when fewer than `limit` are taken.
+    fn take(active: &Arc<AtomicUsize>, limit: usize) -> Option<ConnectionSlot> {
+        if active.fetch_add(1, Ordering::SeqCst) >= limit {
+            active.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnectionSlot(Arc::clone(active)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Threads answering threshold, batch and non-membership proof requests, apart
+/// from the connection workers so slow proofs cannot hold all of them.
+#[derive(Clone)]
+struct ProofPool {
+    pool: ThreadPool,
+    active: Arc<AtomicUsize>,
+    max_requests: usize,
+}
+
+impl ProofPool {
+    fn new(workers: usize) -> ProofPool {
+        ProofPool {
+            pool: ThreadPool::with_name("proofs".to_string(), workers),
+            active: Arc::new(AtomicUsize::new(0)),
+            max_requests: workers * PROOF_REQUESTS_PER_WORKER,
+        }
+    }
+
+    /// A place for one request, `None` when too many are running or waiting.
+    fn reserve(&self) -> Option<ConnectionSlot> {
+        ConnectionSlot::take(&self.active, self.max_requests)
+    }
+
+    /// Answers a request in its reserved place, freeing it when done.
+    fn execute<F: FnOnce() -> Result<()> + Send + 'static>(&self, slot: ConnectionSlot, answer: F) {
+        self.pool.execute(move || {
+            let _slot = slot;
+            answer().unwrap_or_else(|error| eprintln!("{:?}", error));
+        });
+    }
+}
+
+/// A connection that may spend at most its budget blocked in reads and writes,
+/// in total, so a client trickling bytes cannot hold a worker for longer.
+struct DeadlineStream {
+    stream: TcpStream,
+    remaining: Option<Duration>,
+}
+
+impl DeadlineStream {
+    fn new(stream: TcpStream, budget: Option<Duration>) -> DeadlineStream {
+        DeadlineStream {
+            stream,
+            remaining: budget,
+        }
+    }
+
+    fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    fn spend<T>(
+        &mut self,
+        io: impl FnOnce(&mut TcpStream, Option<Duration>) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let remaining = self.remaining;
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Connection ran out of time"));
+        }
+        let started = Instant::now();
+        let result = io(&mut self.stream, remaining);
+        self.remaining = remaining.map(|remaining| remaining.saturating_sub(started.elapsed()));
+        result
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.spend(|stream, timeout| {
+            stream.set_read_timeout(timeout)?;
+            stream.read(buf)
+        })
+    }
+}
+
+impl Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.spend(|stream, timeout| {
+            stream.set_write_timeout(timeout)?;
+            stream.write(buf)
+        })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
 
 impl Server {
     pub fn new() -> Result<Server> {
-        Ok(Server {})
-    }
-
-    pub fn run_server(&self) {
-        fn handle_client(mut stream: TcpStream, bc: Arc<Mutex<Blockchain>>) -> Result<()> {
-            println!("Incoming connection from: {}", stream.peer_addr()?);
-            
-            // Use dynamic reading instead of fixed buffer
-            let mut request = String::new();
-            let mut reader = BufReader::new(&mut stream);
-            reader.read_line(&mut request)?;
-            
-            let bc = bc.lock().map_err(|_| failure::format_err!("Mutex poisoned"))?;
-            let parts = request.trim().split('_').collect::<Vec<&str>>();
-            
-            // Validate request has minimum required parts
-            if parts.is_empty() {
-                return Err(failure::format_err!("Empty request"));
+        Server::with_depth(MAX_LEVELS)
+    }
+
+    pub fn with_depth(depth: usize) -> Result<Server> {
+        Ok(Server {
+            depth,
+            witness_backend: WitnessBackend::default(),
+            circuit_source: CircuitSource::default(),
+            proof_format: ProofFormat::default(),
+            legacy_protocol: false,
+            listen_address: DEFAULT_LISTEN_ADDRESS.to_string(),
+            http_address: DEFAULT_HTTP_ADDRESS.to_string(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            workers: DEFAULT_CONNECTION_WORKERS,
+            proof_workers: DEFAULT_PROOF_REQUEST_WORKERS,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            io_timeout: DEFAULT_IO_TIMEOUT,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            credentials: None,
+            tls: None,
+            history_dir: None,
+        })
+    }
+
+    pub fn set_witness_backend(&mut self, witness_backend: WitnessBackend) {
+        self.witness_backend = witness_backend;
+    }
+
+    pub fn set_circuit_source(&mut self, circuit_source: CircuitSource) {
+        self.circuit_source = circuit_source;
+    }
+
+    pub fn set_proof_format(&mut self, proof_format: ProofFormat) {
+        self.proof_format = proof_format;
+    }
+
+    /// Directory to keep balance history proofs in instead of memory.
+    pub fn set_history_dir(&mut self, history_dir: PathBuf) {
+        self.history_dir = Some(history_dir);
+    }
+
+    /// Serves the underscore-separated text protocol instead of JSON-RPC, for older clients.
+    pub fn set_legacy_protocol(&mut self, legacy_protocol: bool) {
+        self.legacy_protocol = legacy_protocol;
+    }
+
+    /// Address of the TCP protocol listener, e.g. `127.0.0.1:9000`.
+    pub fn set_listen_address(&mut self, listen_address: &str) {
+        self.listen_address = listen_address.to_string();
+    }
+
+    /// Address of the HTTP API.
+    pub fn set_http_address(&mut self, http_address: &str) {
+        self.http_address = http_address.to_string();
+    }
+
+    /// Largest JSON-RPC frame read or written. Larger responses are replaced by an error.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Number of threads serving connections.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    /// Threads answering threshold, batch and non-membership proof requests.
+    /// Four requests per thread may run or wait, further ones are told the node is busy.
+    pub fn set_proof_workers(&mut self, proof_workers: usize) {
+        self.proof_workers = proof_workers.max(1);
+    }
+
+    /// Connections served or waiting for a worker at once. Further ones are
+    /// told the node is busy and closed.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections.max(1);
+    }
+
+    /// How long a connection may spend in reads and writes in total, proof
+    /// building aside. Zero disables the timeout.
+    pub fn set_io_timeout(&mut self, io_timeout: Duration) {
+        self.io_timeout = io_timeout;
+    }
+
+    /// Largest request frame or text protocol line accepted.
+    pub fn set_max_request_size(&mut self, max_request_size: usize) {
+        self.max_request_size = max_request_size;
+    }
+
+    /// Requires an API token on every JSON-RPC and HTTP request, checked against
+    /// the role it belongs to. Without credentials any client may call anything.
+    pub fn set_credentials(&mut self, credentials: Credentials) {
+        self.credentials = Some(Arc::new(credentials));
+    }
+
+    /// Serves the TCP listener over TLS. The HTTP API stays plain HTTP and refuses API tokens.
+    pub fn set_tls(&mut self, tls: ServerTls) {
+        self.tls = Some(tls);
+    }
+
+    fn connection_settings(&self) -> Result<ConnectionSettings> {
+        if self.legacy_protocol && self.credentials.is_some() {
+            return Err(failure::format_err!("The legacy protocol cannot carry API tokens"));
+        }
+        let operator_certificates = self.tls.as_ref().is_some_and(ServerTls::requires_operator_certificates);
+        let credentials = match &self.credentials {
+            Some(credentials) if operator_certificates => {
+                let mut credentials = Credentials::clone(credentials);
+                credentials.require_operator_certificates()?;
+                Some(Arc::new(credentials))
             }
-            let output: Result<String> = match parts[0] {
-                "transfer" => {
-                    if parts.len() < 4 {
-                        Err(failure::format_err!("Transfer requires 4 parameters"))
-                    } else {
-                        transfer(bc, parts[1], parts[2], parts[3])
-                    }
-                },
-                "verify" => get_liabilities_proof(bc),
-                "balance" => {
-                    if parts.len() < 2 {
-                        Err(failure::format_err!("Balance request requires address"))
-                    } else if parts[1] == "history" {
-                        if parts.len() < 3 {
-                            Err(failure::format_err!("Balance history requires address"))
-                        } else {
-                            get_balance_history(bc, parts[2])
-                        }
-                    } else {
-                        get_balance(bc, parts[1])
-                    }
+            None if operator_certificates => {
+                return Err(failure::format_err!("Client certificates identify operators, which needs credentials"));
+            }
+            credentials => credentials.clone(),
+        };
+        Ok(ConnectionSettings {
+            format: self.proof_format,
+            legacy_protocol: self.legacy_protocol,
+            max_frame_size: self.max_frame_size,
+            max_request_size: self.max_request_size,
+            credentials,
+            tls: self.tls.clone(),
+        })
+    }
+
+    fn proof_jobs(&self) -> Result<Arc<ProofJobs>> {
+        let jobs = ProofJobs::new(DEFAULT_PROOF_WORKERS, self.proof_format);
+        if let Some(history_dir) = &self.history_dir {
+            jobs.set_history_dir(history_dir.clone())?;
+        }
+        Ok(Arc::new(jobs))
+    }
+
+    /// The HTTP API is plain HTTP, so on a node serving TLS it refuses API tokens
+    /// rather than have them sent in the clear.
+    fn rest_service(
+        &self,
+        settings: &ConnectionSettings,
+        bc: &Arc<Mutex<Blockchain>>,
+        jobs: &Arc<ProofJobs>,
+    ) -> RestService {
+        let mut rest = RestService::new(Arc::clone(bc), Arc::clone(jobs), self.proof_format);
+        rest.set_workers(self.workers);
+        rest.set_max_body_size(self.max_request_size);
+        rest.set_refuse_tokens(settings.tls.is_some());
+        if let Some(credentials) = &settings.credentials {
+            rest.set_credentials(Arc::clone(credentials));
+        }
+        rest
+    }
+
+    fn get_io_timeout(&self) -> Option<Duration> {
+        Some(self.io_timeout).filter(|timeout| !timeout.is_zero())
+    }
+
+    pub fn run_server(&self) -> Result<()> {
+        fn handle_client<S: Read + Write + Send + 'static>(
+            mut stream: S,
+            bc: Arc<Mutex<Blockchain>>,
+            jobs: Arc<ProofJobs>,
+            settings: ConnectionSettings,
+            client_certificate: Option<ClientCertificate>,
+            proofs: &ProofPool,
+        ) -> Result<()> {
+            if settings.legacy_protocol {
+                let request = match read_request_line(&mut stream, settings.max_request_size) {
+                    Ok(request) => request,
+                    Err(e) => return write_line(stream, &format!("Internal error: {}", e)),
+                };
+                if !legacy::builds_proof(&request) {
+                    return write_line(stream, &answer_legacy(&request, &bc, &jobs, settings.format));
                 }
-                _ => Ok("Wrong command".to_string()),
-            };
-            match output {
-                Ok(output_value) => {
-                    stream.write(output_value.as_bytes())?;
+                match proofs.reserve() {
+                    Some(slot) => proofs.execute(slot, move || {
+                        write_line(stream, &answer_legacy(&request, &bc, &jobs, settings.format))
+                    }),
+                    None => write_line(stream, PROOFS_BUSY_MESSAGE)?,
+                }
+                return Ok(());
+            }
+
+            let request = match read_frame(&mut stream, settings.max_request_size) {
+                Ok((MessageType::Request, payload)) => payload,
+                Ok((kind, _)) => {
+                    let message = format!("Expected a request frame, got {:?}", kind);
+                    return write_frame(&mut stream, MessageType::Error, message.as_bytes(), settings.max_frame_size);
                 }
                 Err(e) => {
-                    let error_msg = format!("Internal error: {}", e);
-                    stream.write(error_msg.as_bytes())?;
+                    // Best effort, the client may already be gone
+                    let message = e.to_string();
+                    let _ = write_frame(&mut stream, MessageType::Error, message.as_bytes(), settings.max_frame_size);
+                    return Err(e);
                 }
+            };
+            let response = match rpc::parse_request(&String::from_utf8_lossy(&request)).and_then(rpc::parse_call) {
+                Ok(call) => match settings.authorize(&call, client_certificate.as_ref()) {
+                    Ok(()) if call.builds_proof() => match proofs.reserve() {
+                        Some(slot) => {
+                            proofs.execute(slot, move || {
+                                let response = rpc::respond(&call, &bc, &jobs, settings.format);
+                                write_response(stream, &response, settings.max_frame_size)
+                            });
+                            return Ok(());
+                        }
+                        None => RpcResponse::failure(
+                            call.get_request().get_id().clone(),
+                            RpcError::new(PROOF_FAILED, PROOFS_BUSY_MESSAGE.to_string()),
+                        ),
+                    },
+                    Ok(()) => rpc::respond(&call, &bc, &jobs, settings.format),
+                    Err(response) => response,
+                },
+                Err(response) => response,
+            };
+            write_response(stream, &response, settings.max_frame_size)
+        }
+
+        /// Turns away a connection over the limit without waiting for its request.
+        fn reject_busy<S: Write>(mut stream: S, settings: &ConnectionSettings) -> Result<()> {
+            if settings.legacy_protocol {
+                write_line(stream, BUSY_MESSAGE)
+            } else {
+                write_frame(&mut stream, MessageType::Error, BUSY_MESSAGE.as_bytes(), settings.max_frame_size)
             }
-            stream.write(&[b'\n'])?;
-            Ok(())
         }
 
-        let bc = Blockchain::create_blockchain()
-            .expect("Failed to create blockchain");
+        let settings = self.connection_settings()?;
+        let listener = TcpListener::bind(&self.listen_address)
+            .map_err(|e| failure::format_err!("Could not bind {}: {}", self.listen_address, e))?;
+        println!("Listening on {}", self.listen_address);
+
+        let mut bc = Blockchain::create_blockchain_with_source(self.depth, self.circuit_source)?;
+        bc.set_witness_backend(self.witness_backend);
         let bc = Arc::new(Mutex::new(bc));
         let bc2 = Arc::clone(&bc);
+        let jobs = self.proof_jobs()?;
         thread::spawn(move || loop {
-            sleep(Duration::new(10, 0));
+            sleep(BLOCK_INTERVAL);
             if let Ok(mut blockchain) = bc.lock() {
                 if let Err(e) = blockchain.add_block() {
                     eprintln!("Failed to add block: {}", e);
@@ -84,19 +435,136 @@ impl Server {
             }
         });
 
-        let listener = TcpListener::bind("0.0.0.0:8888").expect("Could not bind");
+        let rest = self.rest_service(&settings, &bc2, &jobs);
+        let http_address = self.http_address.clone();
+        thread::spawn(move || {
+            if let Err(e) = http::serve(&http_address, rest) {
+                eprintln!("HTTP API stopped: {}", e);
+            }
+        });
+
+        let io_timeout = self.get_io_timeout();
+        let pool = ThreadPool::new(self.workers);
+        let proofs = ProofPool::new(self.proof_workers);
+        let active = Arc::new(AtomicUsize::new(0));
         for stream in listener.incoming() {
-            match stream {
+            let stream = match stream {
+                Ok(stream) => DeadlineStream::new(stream, io_timeout),
                 Err(e) => {
-                    eprintln!("failed: {}", e)
+                    eprintln!("failed: {}", e);
+                    continue;
                 }
-                Ok(stream) => {
-                    let bc = Arc::clone(&bc2);
-                    thread::spawn(move || {
-                        handle_client(stream, bc).unwrap_or_else(|error| eprintln!("{:?}", error));
-                    });
+            };
+            let slot = match ConnectionSlot::take(&active, self.max_connections) {
+                Some(slot) => slot,
+                None => {
+                    // A TLS handshake would hold up the listener, so TLS clients are just disconnected
+                    if settings.tls.is_none() {
+                        reject_busy(stream, &settings).unwrap_or_else(|error| eprintln!("{:?}", error));
+                    }
+                    continue;
                 }
-            }
+            };
+            let bc = Arc::clone(&bc2);
+            let jobs = Arc::clone(&jobs);
+            let settings = settings.clone();
+            let proofs = proofs.clone();
+            pool.execute(move || {
+                let _slot = slot;
+                if let Ok(peer) = stream.get_ref().peer_addr() {
+                    println!("Incoming connection from: {}", peer);
+                }
+                let served = match settings.tls.clone() {
+                    Some(tls) => tls
+                        .accept(stream)
+                        .and_then(|(stream, client_certificate)| {
+                            handle_client(stream, bc, jobs, settings, client_certificate, &proofs)
+                        }),
+                    None => handle_client(stream, bc, jobs, settings, None, &proofs),
+                };
+                served.unwrap_or_else(|error| eprintln!("{:?}", error));
+            });
         }
+        Ok(())
+    }
+}
+
+/// Serializes a response, replacing it by an error when it would not fit in a frame.
+fn encode_response(response: &RpcResponse, max_frame_size: usize) -> Result<Vec<u8>> {
+    let output = serde_json::to_vec(response)?;
+    if output.len() <= max_frame_size {
+        return Ok(output);
+    }
+    let error = RpcError::new(
+        INTERNAL_ERROR,
+        format!(
+            "Response of {} bytes exceeds the {} byte frame limit",
+            output.len(),
+            max_frame_size
+        ),
+    );
+    Ok(serde_json::to_vec(&RpcResponse::failure(response.get_id().clone(), error))?)
+}
+
+fn write_response<W: Write>(mut stream: W, response: &RpcResponse, max_frame_size: usize) -> Result<()> {
+    let output = encode_response(response, max_frame_size)?;
+    write_frame(&mut stream, MessageType::Response, &output, max_frame_size)
+}
+
+/// Answers a text protocol request, errors included, as the line to send back.
+fn answer_legacy(request: &str, bc: &Arc<Mutex<Blockchain>>, jobs: &Arc<ProofJobs>, format: ProofFormat) -> String {
+    legacy::handle_request(request, bc, jobs, format).unwrap_or_else(|e| format!("Internal error: {}", e))
+}
+
+fn write_line<W: Write>(mut stream: W, line: &str) -> Result<()> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(&[b'\n'])?;
+    Ok(())
+}
+
+/// Reads one newline-terminated request, refusing lines longer than `max_request_size`.
+fn read_request_line<R: Read>(reader: R, max_request_size: usize) -> Result<String> {
+    let mut request = String::new();
+    BufReader::new(reader.take(max_request_size as u64 + 1)).read_line(&mut request)?;
+    check_request_line(request, max_request_size)
+}
+
+/// A line read from at most `max_request_size + 1` bytes is too long when it
+/// used them all without reaching a newline.
+fn check_request_line(request: String, max_request_size: usize) -> Result<String> {
+    if request.len() > max_request_size && !request.ends_with('\n') {
+        return Err(BlockchainError::InvalidRequest(format!(
+            "Request line exceeds {} bytes",
+            max_request_size
+        ))
+        .into());
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_line_limit() {
+        assert_eq!(read_request_line("balance_alice\nrest".as_bytes(), 14).unwrap(), "balance_alice\n");
+        assert_eq!(read_request_line("balance_alice".as_bytes(), 14).unwrap(), "balance_alice");
+        assert!(read_request_line("balance_alice_and_more\n".as_bytes(), 14).is_err());
+    }
+
+    #[test]
+    fn test_trickling_client_runs_out_of_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = DeadlineStream::new(listener.accept().unwrap().0, Some(Duration::from_millis(100)));
+        let mut byte = [0u8; 1];
+        client.write_all(b"b").unwrap();
+        assert_eq!(stream.read(&mut byte).unwrap(), 1);
+
+        // Each read waits for what is left of the budget, not a fresh timeout
+        assert!(stream.read(&mut byte).is_err());
+        client.write_all(b"b").unwrap();
+        assert_eq!(stream.read(&mut byte).unwrap_err().kind(), std::io::ErrorKind::TimedOut);
     }
 }
@@ -0,0 +1,322 @@
+use super::{check_request_line, encode_response, ConnectionSettings, Server, BLOCK_INTERVAL, BUSY_MESSAGE};
+use crate::blockchain::blockchain::{Blockchain, StateSnapshot};
+use crate::errors::{BlockchainError, Result};
+use crate::stream::framing::{read_frame_async, write_frame_async, MessageType};
+use crate::stream::http;
+use crate::stream::jobs::ProofJobs;
+use crate::stream::rpc::{RpcCall, RpcResponse};
+use crate::stream::tls::{ClientCertificate, ServerTls};
+use crate::stream::{legacy, rpc};
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Builder;
+use tokio::sync::{watch, Semaphore};
+use tokio::task;
+use tokio::time::{self, MissedTickBehavior};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Latest state snapshot, replaced after every block.
+type Snapshots = watch::Sender<Arc<StateSnapshot>>;
+
+/// Connections over the limit being told the node is busy at once. Telling
+/// costs a TLS handshake on TLS nodes, so further ones are dropped unanswered.
+const MAX_BUSY_REPLIES: usize = 8;
+
+/// Everything a connection needs, cloned from the server for each one.
+struct Connection {
+    bc: Arc<Mutex<Blockchain>>,
+    jobs: Arc<ProofJobs>,
+    snapshots: Arc<Snapshots>,
+    // one per blocking thread but the one kept for block production
+    blocking: Arc<Semaphore>,
+    settings: ConnectionSettings,
+    io_timeout: Option<Duration>,
+}
+
+/// The time a connection may spend in its handshake, reads and writes, in
+/// total, so a client trickling bytes cannot hold it open for longer.
+struct IoBudget {
+    remaining: Option<Duration>,
+}
+
+impl IoBudget {
+    fn new(budget: Option<Duration>) -> IoBudget {
+        IoBudget { remaining: budget }
+    }
+
+    async fn spend<T>(&mut self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(remaining) = self.remaining else {
+            return operation.await;
+        };
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Connection ran out of time").into());
+        }
+        let started = Instant::now();
+        let result = time::timeout(remaining, operation).await;
+        self.remaining = Some(remaining.saturating_sub(started.elapsed()));
+        result.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Connection ran out of time"))?
+    }
+}
+
+impl Server {
+    /// Runs the node on an async runtime. Connections are served by `workers`
+    /// async threads. Requests that lock the blockchain or build proofs run on
+    /// `workers` threads of the blocking pool, while balance queries are answered
+    /// from a state snapshot and never wait for block production. The pool has
+    /// one more thread, so requests filling it cannot hold up adding blocks.
+    pub fn run_async(&self) -> Result<()> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(self.workers)
+            .max_blocking_threads(self.workers + 1)
+            .enable_all()
+            .build()?;
+        runtime.block_on(self.serve_async())
+    }
+
+    async fn serve_async(&self) -> Result<()> {
+        let settings = self.connection_settings()?;
+        let listener = TcpListener::bind(&self.listen_address)
+            .await
+            .map_err(|e| failure::format_err!("Could not bind {}: {}", self.listen_address, e))?;
+        println!("Listening on {}", self.listen_address);
+
+        let mut bc = Blockchain::create_blockchain_with_source(self.depth, self.circuit_source)?;
+        bc.set_witness_backend(self.witness_backend);
+        let snapshots = Arc::new(watch::Sender::new(Arc::new(bc.get_state_snapshot())));
+        let bc = Arc::new(Mutex::new(bc));
+        let jobs = self.proof_jobs()?;
+        tokio::spawn(produce_blocks(Arc::clone(&bc), Arc::clone(&snapshots)));
+
+        let mut rest = self.rest_service(&settings, &bc, &jobs);
+        rest.set_snapshots(snapshots.subscribe());
+        let http_address = self.http_address.clone();
+        thread::spawn(move || {
+            if let Err(e) = http::serve(&http_address, rest) {
+                eprintln!("HTTP API stopped: {}", e);
+            }
+        });
+
+        let io_timeout = self.get_io_timeout();
+        let blocking = Arc::new(Semaphore::new(self.workers));
+        let permits = Arc::new(Semaphore::new(self.max_connections));
+        let busy_replies = Arc::new(Semaphore::new(MAX_BUSY_REPLIES));
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("failed: {}", e);
+                    continue;
+                }
+            };
+            let permit = match Arc::clone(&permits).try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    let Ok(busy_reply) = Arc::clone(&busy_replies).try_acquire_owned() else {
+                        continue;
+                    };
+                    let settings = settings.clone();
+                    tokio::spawn(async move {
+                        let _busy_reply = busy_reply;
+                        reject_busy(stream, settings, IoBudget::new(io_timeout))
+                            .await
+                            .unwrap_or_else(|error| eprintln!("{:?}", error));
+                    });
+                    continue;
+                }
+            };
+            let connection = Connection {
+                bc: Arc::clone(&bc),
+                jobs: Arc::clone(&jobs),
+                snapshots: Arc::clone(&snapshots),
+                blocking: Arc::clone(&blocking),
+                settings: settings.clone(),
+                io_timeout,
+            };
+            tokio::spawn(async move {
+                let _permit = permit;
+                connection
+                    .accept(stream)
+                    .await
+                    .unwrap_or_else(|error| eprintln!("{:?}", error));
+            });
+        }
+    }
+}
+
+impl Connection {
+    async fn accept(self, stream: TcpStream) -> Result<()> {
+        println!("Incoming connection from: {}", stream.peer_addr()?);
+        let mut budget = IoBudget::new(self.io_timeout);
+        match &self.settings.tls {
+            Some(tls) => {
+                let (stream, client_certificate) = accept_tls(tls, stream, &mut budget).await?;
+                self.serve(stream, client_certificate, budget).await
+            }
+            None => self.serve(stream, None, budget).await,
+        }
+    }
+
+    /// Reads the request and writes the response within what is left of `budget`.
+    /// Answering the request does not count against it.
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        self,
+        mut stream: S,
+        client_certificate: Option<ClientCertificate>,
+        mut budget: IoBudget,
+    ) -> Result<()> {
+        if self.settings.legacy_protocol {
+            let output = match self.read_line(&mut stream, &mut budget).await {
+                Ok(request) => match self.respond_legacy(request).await {
+                    Ok(output) => output,
+                    Err(e) => format!("Internal error: {}", e),
+                },
+                Err(e) => format!("Internal error: {}", e),
+            };
+            return budget.spend(async {
+                stream.write_all(output.as_bytes()).await?;
+                stream.write_all(b"\n").await?;
+                Ok::<_, failure::Error>(())
+            })
+            .await;
+        }
+
+        let max_frame_size = self.settings.max_frame_size;
+        let read = budget.spend(read_frame_async(&mut stream, self.settings.max_request_size));
+        let request = match read.await {
+            Ok((MessageType::Request, payload)) => payload,
+            Ok((kind, _)) => {
+                let message = format!("Expected a request frame, got {:?}", kind);
+                let write = write_frame_async(&mut stream, MessageType::Error, message.as_bytes(), max_frame_size);
+                return budget.spend(write).await;
+            }
+            Err(e) => {
+                // Best effort, the client may already be gone
+                let message = e.to_string();
+                let write = write_frame_async(&mut stream, MessageType::Error, message.as_bytes(), max_frame_size);
+                let _ = budget.spend(write).await;
+                return Err(e);
+            }
+        };
+        let response = match rpc::parse_request(&String::from_utf8_lossy(&request)).and_then(rpc::parse_call) {
+            Ok(call) => self.respond(call, client_certificate.as_ref()).await?,
+            Err(response) => response,
+        };
+        let output = encode_response(&response, max_frame_size)?;
+        let write = write_frame_async(&mut stream, MessageType::Response, &output, max_frame_size);
+        budget.spend(write).await
+    }
+
+    async fn read_line<S: AsyncRead + Unpin>(&self, stream: &mut S, budget: &mut IoBudget) -> Result<String> {
+        let max_request_size = self.settings.max_request_size;
+        let mut request = String::new();
+        budget.spend(async {
+            BufReader::new(stream.take(max_request_size as u64 + 1))
+                .read_line(&mut request)
+                .await?;
+            Ok::<_, failure::Error>(())
+        })
+        .await?;
+        check_request_line(request, max_request_size)
+    }
+
+    /// Answers from the snapshot when it covers the method, on the blocking pool otherwise.
+    /// Requests the credentials do not allow are answered with the error.
+    async fn respond(&self, call: RpcCall, client_certificate: Option<&ClientCertificate>) -> Result<RpcResponse> {
+        if let Err(response) = self.settings.authorize(&call, client_certificate) {
+            return Ok(response);
+        }
+        // Not held across an await, the snapshot may be replaced meanwhile
+        let cached = rpc::respond_from_snapshot(&call, &self.snapshots.borrow());
+        if let Some(response) = cached {
+            return Ok(response);
+        }
+        let (bc, jobs) = (Arc::clone(&self.bc), Arc::clone(&self.jobs));
+        let format = self.settings.format;
+        let _permit = self.blocking.acquire().await?;
+        Ok(task::spawn_blocking(move || rpc::respond(&call, &bc, &jobs, format)).await?)
+    }
+
+    async fn respond_legacy(&self, request: String) -> Result<String> {
+        let cached = legacy::respond_from_snapshot(&request, &self.snapshots.borrow());
+        if let Some(output) = cached {
+            return output;
+        }
+        let (bc, jobs) = (Arc::clone(&self.bc), Arc::clone(&self.jobs));
+        let format = self.settings.format;
+        let _permit = self.blocking.acquire().await?;
+        task::spawn_blocking(move || legacy::handle_request(&request, &bc, &jobs, format)).await?
+    }
+}
+
+/// Adds a block every `BLOCK_INTERVAL` on the blocking pool thread that requests
+/// never take, so they cannot hold it up however many are waiting.
+async fn produce_blocks(bc: Arc<Mutex<Blockchain>>, snapshots: Arc<Snapshots>) {
+    let mut interval = time::interval(BLOCK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes at once, the first block is due an interval in
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let (bc, snapshots) = (Arc::clone(&bc), Arc::clone(&snapshots));
+        match task::spawn_blocking(move || add_block(&bc, &snapshots)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("Failed to add block: {}", e),
+            Err(e) => eprintln!("Block production panicked: {}", e),
+        }
+    }
+}
+
+/// Adds a block, then publishes the new state while still holding the lock.
+fn add_block(bc: &Mutex<Blockchain>, snapshots: &Snapshots) -> Result<()> {
+    let mut blockchain = bc.lock().map_err(|_| BlockchainError::MutexPoisoned)?;
+    let added = blockchain.add_block();
+    snapshots.send_replace(Arc::new(blockchain.get_state_snapshot()));
+    added
+}
+
+/// Completes the TLS handshake. Also returns the client's certificate, which
+/// the handshake has verified, when it presented one.
+async fn accept_tls(
+    tls: &ServerTls,
+    stream: TcpStream,
+    budget: &mut IoBudget,
+) -> Result<(TlsStream<TcpStream>, Option<ClientCertificate>)> {
+    let acceptor = TlsAcceptor::from(tls.get_config());
+    let stream = budget.spend(async { acceptor.accept(stream).await.map_err(failure::Error::from) }).await?;
+    let client_certificate = ClientCertificate::from_chain(stream.get_ref().1.peer_certificates());
+    Ok((stream, client_certificate))
+}
+
+/// Turns away a connection over the limit without waiting for its request.
+async fn reject_busy(mut stream: TcpStream, settings: ConnectionSettings, mut budget: IoBudget) -> Result<()> {
+    match &settings.tls {
+        Some(tls) => {
+            let (mut stream, _) = accept_tls(tls, stream, &mut budget).await?;
+            write_busy(&mut stream, &settings, &mut budget).await
+        }
+        None => write_busy(&mut stream, &settings, &mut budget).await,
+    }
+}
+
+async fn write_busy<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    settings: &ConnectionSettings,
+    budget: &mut IoBudget,
+) -> Result<()> {
+    budget.spend(async {
+        if settings.legacy_protocol {
+            stream.write_all(BUSY_MESSAGE.as_bytes()).await?;
+            stream.write_all(b"\n").await?;
+            Ok::<_, failure::Error>(())
+        } else {
+            write_frame_async(stream, MessageType::Error, BUSY_MESSAGE.as_bytes(), settings.max_frame_size).await
+        }
+    })
+    .await
+}
@@ -0,0 +1,260 @@
+use crate::errors::Result;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use webpki::EndEntityCert;
+
+/// A byte stream to or from the node, plain TCP or TLS.
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+/// TLS on the node's TCP listener: its certificate chain and key, and when
+/// operators must prove who they are, the CA their client certificates chain to.
+#[derive(Clone)]
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+    client_ca: bool,
+}
+
+/// A client certificate the handshake has verified against the client CA.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate(CertificateDer<'static>);
+
+/// TLS for the client: the CA the node's certificate chains to, and an optional
+/// client certificate for operator connections.
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+}
+
+impl ServerTls {
+    /// Reads PEM files. With `client_ca`, clients may present a certificate
+    /// signed by that CA, which requests with an operator token then require.
+    pub fn from_files(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<ServerTls> {
+        let builder = ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(client_ca)?), provider())
+                    .allow_unauthenticated()
+                    .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(load_certs(cert)?, load_key(key)?)?;
+        Ok(ServerTls {
+            config: Arc::new(config),
+            client_ca: client_ca.is_some(),
+        })
+    }
+
+    /// Whether operator requests must come with a verified client certificate.
+    pub fn requires_operator_certificates(&self) -> bool {
+        self.client_ca
+    }
+
+    pub fn get_config(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.config)
+    }
+
+    /// Completes the handshake on an accepted connection. Also returns the
+    /// client's certificate when it presented one.
+    pub fn accept<S: Read + Write>(
+        &self,
+        mut stream: S,
+    ) -> Result<(StreamOwned<ServerConnection, S>, Option<ClientCertificate>)> {
+        let mut connection = ServerConnection::new(self.get_config())?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+        let client_certificate = ClientCertificate::from_chain(connection.peer_certificates());
+        Ok((StreamOwned::new(connection, stream), client_certificate))
+    }
+}
+
+impl ClientCertificate {
+    pub fn new(certificate: CertificateDer<'static>) -> ClientCertificate {
+        ClientCertificate(certificate)
+    }
+
+    /// The end-entity certificate of a verified chain.
+    pub fn from_chain(chain: Option<&[CertificateDer<'_>]>) -> Option<ClientCertificate> {
+        chain
+            .and_then(|chain| chain.first())
+            .map(|certificate| ClientCertificate(certificate.clone().into_owned()))
+    }
+
+    /// Whether the certificate is issued to `name`, one of its subject alternative names.
+    pub fn is_issued_to(&self, name: &str) -> bool {
+        let (Ok(certificate), Ok(name)) = (EndEntityCert::try_from(&self.0), ServerName::try_from(name)) else {
+            return false;
+        };
+        certificate.verify_is_valid_for_subject_name(&name).is_ok()
+    }
+}
+
+impl ClientTls {
+    /// Reads PEM files. `identity` is a client certificate chain and its key.
+    pub fn from_files(ca: &Path, identity: Option<(&Path, &Path)>) -> Result<ClientTls> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(ca)?);
+        let config = match identity {
+            Some((cert, key)) => builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(ClientTls {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Opens TLS over `stream` to the node at `node`, whose host part its
+    /// certificate must be issued for.
+    pub fn connect(&self, node: &str, stream: TcpStream) -> Result<StreamOwned<ClientConnection, TcpStream>> {
+        let host = node.rsplit_once(':').map_or(node, |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|e| failure::format_err!("Invalid node name {}: {}", host, e))?;
+        let connection = ClientConnection::new(Arc::clone(&self.config), name)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| failure::format_err!("Cannot read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(failure::format_err!("No certificate in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| failure::format_err!("Cannot read private key from {}: {}", path.display(), e))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::framing::{read_frame, write_frame, MessageType, DEFAULT_MAX_FRAME_SIZE};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::fs;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    /// A self-signed CA with a node certificate for `localhost` and a client certificate, as PEM files.
+    struct TestPki {
+        dir: PathBuf,
+    }
+
+    impl TestPki {
+        fn generate(name: &str) -> TestPki {
+            let dir = std::env::temp_dir().join(format!("bsol-tls-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+            for (file, subject) in [("node", "localhost"), ("operator", "operator")] {
+                let key = KeyPair::generate().unwrap();
+                let cert = CertificateParams::new(vec![subject.to_string()])
+                    .unwrap()
+                    .signed_by(&key, &ca, &ca_key)
+                    .unwrap();
+                fs::write(dir.join(format!("{}.pem", file)), cert.pem()).unwrap();
+                fs::write(dir.join(format!("{}.key", file)), key.serialize_pem()).unwrap();
+            }
+            TestPki { dir }
+        }
+
+        fn path(&self, file: &str) -> PathBuf {
+            self.dir.join(file)
+        }
+
+        fn server(&self) -> ServerTls {
+            ServerTls::from_files(&self.path("node.pem"), &self.path("node.key"), Some(&self.path("ca.pem"))).unwrap()
+        }
+
+        fn client(&self, operator: bool) -> ClientTls {
+            let (cert, key) = (self.path("operator.pem"), self.path("operator.key"));
+            let identity = if operator { Some((cert.as_path(), key.as_path())) } else { None };
+            ClientTls::from_files(&self.path("ca.pem"), identity).unwrap()
+        }
+    }
+
+    impl Drop for TestPki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Echoes one frame over TLS and returns the client certificate the node saw.
+    fn exchange(server: ServerTls, client: &ClientTls, node_name: &str) -> Result<Option<ClientCertificate>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let node = thread::spawn(move || -> Result<Option<ClientCertificate>> {
+            let (stream, _) = listener.accept()?;
+            let (mut stream, client_certificate) = server.accept(stream)?;
+            let (_, payload) = read_frame(&mut stream, DEFAULT_MAX_FRAME_SIZE)?;
+            write_frame(&mut stream, MessageType::Response, &payload, DEFAULT_MAX_FRAME_SIZE)?;
+            Ok(client_certificate)
+        });
+        let address = format!("{}:{}", node_name, port);
+        let mut stream = client.connect(&address, TcpStream::connect(("127.0.0.1", port))?)?;
+        let sent = write_frame(&mut stream, MessageType::Request, b"{\"secret\": 1}", DEFAULT_MAX_FRAME_SIZE)
+            .and_then(|_| read_frame(&mut stream, DEFAULT_MAX_FRAME_SIZE));
+        drop(stream);
+        let client_certificate = node.join().unwrap();
+        assert_eq!(sent?, (MessageType::Response, b"{\"secret\": 1}".to_vec()));
+        client_certificate
+    }
+
+    #[test]
+    fn test_tls_round_trip() {
+        let pki = TestPki::generate("round-trip");
+        assert!(pki.server().requires_operator_certificates());
+        assert!(exchange(pki.server(), &pki.client(false), "localhost").unwrap().is_none());
+        let operator = exchange(pki.server(), &pki.client(true), "localhost").unwrap().unwrap();
+        assert!(operator.is_issued_to("operator"));
+        assert!(!operator.is_issued_to("localhost"));
+    }
+
+    #[test]
+    fn test_tls_rejects_unknown_certificates() {
+        let pki = TestPki::generate("trusted");
+        let other = TestPki::generate("untrusted");
+        // The node's certificate is not issued for that name
+        assert!(exchange(pki.server(), &pki.client(false), "127.0.0.1").is_err());
+        // Nor signed by a CA the client trusts
+        assert!(exchange(pki.server(), &other.client(false), "localhost").is_err());
+        // A client certificate from another CA fails the handshake
+        let (cert, key) = (other.path("operator.pem"), other.path("operator.key"));
+        let impostor = ClientTls::from_files(&pki.path("ca.pem"), Some((&cert, &key))).unwrap();
+        assert!(exchange(pki.server(), &impostor, "localhost").is_err());
+
+        assert!(ClientTls::from_files(&pki.path("missing.pem"), None).is_err());
+    }
+}
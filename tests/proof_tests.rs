@@ -1,8 +1,21 @@
 // Integration tests for ZK proof generation and verification
 
-use blockchain_rust::proofs::inclusion::{InclusionInput, ProofOfInclusion};
+use blockchain_rust::blockchain::blockchain::Blockchain;
+use blockchain_rust::proofs::batch::ProofOfBatchInclusion;
+use blockchain_rust::proofs::inclusion::{InclusionFolding, InclusionInput, ProofOfInclusion};
 use blockchain_rust::proofs::liabilities::{LiabilitiesInput, MerkleSumTreeChange, ProofOfLiabilities};
-use blockchain_rust::proofs::setup::CircuitSetup;
+use blockchain_rust::proofs::native::NativeCircuitSetup;
+use blockchain_rust::proofs::non_membership::ProofOfNonMembership;
+use blockchain_rust::proofs::registry::{CircuitKind, CircuitSource};
+use blockchain_rust::proofs::setup::{CircuitSetup, PP};
+use blockchain_rust::proofs::encoding::{ProofEncoding, ProofFormat};
+use blockchain_rust::proofs::witness::WitnessBackend;
+use blockchain_rust::proofs::bundle::{BlockWrapper, BundleReport, ProofBundle, ProofOfLiabilitiesWrapper};
+use blockchain_rust::proofs::solvency::{AssetReserve, ReservesStatement, SolvencyReport};
+use blockchain_rust::proofs::threshold::ProofOfThreshold;
+use blockchain_rust::proofs::util::field_from_hex;
+use ff::PrimeField;
+use pasta_curves::Fq;
 use merkle_sum_tree::{Leaf, MerkleSumTree};
 use std::sync::Arc;
 
@@ -30,6 +43,25 @@ fn setup_test_tree() -> (Arc<MerkleSumTree>, Arc<MerkleSumTree>) {
     (old_tree, new_tree)
 }
 
+/// A depth-2 tree holding `balances` in order, padded with empty leaves, the
+/// header of block `block_number` for it, and a native setup of `kind`.
+fn native_fixture(
+    balances: &[(&str, i32)],
+    block_number: i32,
+    kind: CircuitKind,
+) -> (MerkleSumTree, BlockWrapper, NativeCircuitSetup) {
+    let mut leafs: Vec<Leaf> = balances.iter().map(|(id, balance)| Leaf::new(id.to_string(), *balance)).collect();
+    leafs.resize(4, Leaf::new("0".to_string(), 0));
+    let tree = MerkleSumTree::new(leafs).unwrap();
+    let header = BlockWrapper::new(
+        tree.get_root_hash().unwrap().to_string(),
+        tree.get_root_sum().unwrap(),
+        block_number,
+        "now".to_string(),
+    );
+    (tree, header, NativeCircuitSetup::new(kind, 2, 1).unwrap())
+}
+
 #[test]
 fn test_liabilities_proof() {
     let (old_tree, new_tree) = setup_test_tree();
@@ -51,10 +83,304 @@ fn test_inclusion_proof() {
 
     let inclusion_input = InclusionInput::new(&tree, 0).unwrap();
 
+    let claim = inclusion_input.get_claim();
+
     let circuit_setup = CircuitSetup::new("inclusion");
     let (proof, pp) = ProofOfInclusion::new(vec![inclusion_input], &circuit_setup).unwrap();
 
+    // Only the user's leaf and the roots leave the node, not the sibling hashes
+    assert_eq!(proof.get_claims(), &vec![claim]);
+    let bytes = proof.to_bytes().unwrap();
+    for neighbor in tree.get_proof(0).unwrap().get_path() {
+        let hash = field_from_hex(&neighbor.get_node().get_hash().to_string()).to_repr();
+        assert!(!bytes.windows(hash.len()).any(|window| window == hash.as_ref()));
+    }
+
     let result = proof.verify(pp);
     assert!(result.is_ok(), "Inclusion proof verification failed: {:?}", result.err());
     println!("Inclusion proof verified successfully");
 }
+
+#[test]
+fn test_inclusion_proof_extends_with_new_trees() {
+    let (old_tree, new_tree) = setup_test_tree();
+    let circuit_setup = CircuitSetup::new("inclusion");
+    let pp = PP::from_circuit_setup(&circuit_setup);
+    let folding = InclusionFolding::new_with_backend(
+        vec![InclusionInput::new(&old_tree, 0).unwrap()],
+        &circuit_setup,
+        &pp,
+        WitnessBackend::default(),
+    )
+    .unwrap();
+
+    let new_input = InclusionInput::new(&new_tree, 0).unwrap();
+    let new_claim = new_input.get_claim();
+    let folding = folding.extend(vec![new_input], &circuit_setup, &pp).unwrap();
+    let proof = folding.compress(&pp).unwrap();
+    assert_eq!(proof.get_claims().len(), 2);
+    assert_eq!(proof.get_claims().last(), Some(&new_claim));
+
+    // Every claim is bound, not only the last
+    let tampered = ProofOfInclusion::from_json(&proof.to_json().unwrap().replace("\"user_balance\":50", "\"user_balance\":51")).unwrap();
+    assert!(tampered.verify(PP::from_circuit_setup(&circuit_setup)).is_err());
+
+    let result = proof.verify(pp);
+    assert!(result.is_ok(), "Extended inclusion proof verification failed: {:?}", result.err());
+}
+
+#[test]
+fn test_proofs_with_rust_witness() {
+    let (old_tree, new_tree) = setup_test_tree();
+
+    let change = MerkleSumTreeChange::new(0, old_tree, Arc::clone(&new_tree));
+    let liabilities_input = LiabilitiesInput::new(vec![change]).unwrap();
+    let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+    let (proof, pp) =
+        ProofOfLiabilities::new_with_backend(vec![liabilities_input], &circuit_setup, WitnessBackend::Rust)
+            .unwrap();
+    let result = proof.verify(pp);
+    assert!(result.is_ok(), "Liabilities proof verification failed: {:?}", result.err());
+
+    let inclusion_input = InclusionInput::new(&new_tree, 0).unwrap();
+    let circuit_setup = CircuitSetup::new("inclusion");
+    let (proof, pp) =
+        ProofOfInclusion::new_with_backend(vec![inclusion_input], &circuit_setup, WitnessBackend::Rust)
+            .unwrap();
+    let result = proof.verify(pp);
+    assert!(result.is_ok(), "Inclusion proof verification failed: {:?}", result.err());
+}
+
+#[test]
+fn test_proofs_with_native_circuits() {
+    let (old_tree, new_tree) = setup_test_tree();
+
+    let change = MerkleSumTreeChange::new(0, old_tree, Arc::clone(&new_tree));
+    let liabilities_input = LiabilitiesInput::new(vec![change]).unwrap();
+    let circuit_setup = NativeCircuitSetup::new(CircuitKind::LiabilitiesChangesFolding, 2, 1).unwrap();
+    let (proof, pp) = ProofOfLiabilities::new_native(vec![liabilities_input], &circuit_setup).unwrap();
+    let result = proof.verify(pp);
+    assert!(result.is_ok(), "Liabilities proof verification failed: {:?}", result.err());
+
+    let inclusion_input = InclusionInput::new(&new_tree, 0).unwrap();
+    let circuit_setup = NativeCircuitSetup::new(CircuitKind::Inclusion, 2, 1).unwrap();
+    let (proof, pp) = ProofOfInclusion::new_native(vec![inclusion_input], &circuit_setup).unwrap();
+    let result = proof.verify(pp);
+    assert!(result.is_ok(), "Inclusion proof verification failed: {:?}", result.err());
+}
+
+#[test]
+fn test_exported_bundle_verifies_offline() {
+    let (old_tree, new_tree) = setup_test_tree();
+
+    let change = MerkleSumTreeChange::new(0, old_tree, new_tree);
+    let liabilities_input = LiabilitiesInput::new(vec![change]).unwrap();
+    let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+    let (proof, pp) = ProofOfLiabilities::new(vec![liabilities_input], &circuit_setup).unwrap();
+    let header = BlockWrapper::new(
+        proof.get_final_root_hash().to_string(),
+        proof.get_final_root_sum(),
+        2,
+        "now".to_string(),
+    );
+
+    let wrapper = ProofOfLiabilitiesWrapper::new(proof, pp, Some(header));
+    let bundle = ProofBundle::from_liabilities(wrapper).unwrap();
+    let path = std::env::temp_dir().join("blockchain_solvency_liabilities.proof");
+    bundle.write(&path, ProofFormat::Binary).unwrap();
+
+    let report = ProofBundle::read(&path).unwrap().verify();
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(report, Ok(BundleReport::Liabilities(_))), "Bundle verification failed: {:?}", report.err());
+}
+
+#[test]
+fn test_liabilities_bundle_without_header_is_rejected() {
+    let (old_tree, new_tree) = setup_test_tree();
+
+    let change = MerkleSumTreeChange::new(0, old_tree, new_tree);
+    let liabilities_input = LiabilitiesInput::new(vec![change]).unwrap();
+    let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+    let (proof, pp) = ProofOfLiabilities::new(vec![liabilities_input], &circuit_setup).unwrap();
+
+    let bundle = ProofBundle::from_liabilities(ProofOfLiabilitiesWrapper::new(proof, pp, None)).unwrap();
+    let error = bundle.verify().err().unwrap();
+    assert!(error.to_string().contains("no block header"), "Unexpected error: {}", error);
+}
+
+#[test]
+fn test_relabelled_parameters_are_rejected() {
+    let (old_tree, new_tree) = setup_test_tree();
+
+    let change = MerkleSumTreeChange::new(0, old_tree, new_tree);
+    let liabilities_input = LiabilitiesInput::new(vec![change]).unwrap();
+    let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+    let (proof, _) = ProofOfLiabilities::new(vec![liabilities_input], &circuit_setup).unwrap();
+
+    // Parameters of another circuit, labelled as the liabilities build
+    let inclusion_setup = CircuitSetup::new("inclusion");
+    let pp = PP::new(inclusion_setup.get_r1cs(), circuit_setup.get_circuit_id().clone());
+    let bundle = ProofBundle::from_liabilities(ProofOfLiabilitiesWrapper::new(proof, pp, None)).unwrap();
+    let error = bundle.verify().err().unwrap();
+    assert!(error.to_string().contains("hash to"), "Unexpected error: {}", error);
+}
+
+#[test]
+fn test_native_node_liabilities_proof_verifies() {
+    let mut bc = Blockchain::create_blockchain_with_source(2, CircuitSource::Native).unwrap();
+    bc.add_transaction("", "alice", 100).unwrap();
+    bc.add_block().unwrap();
+
+    // Clients decode the default wrapper whatever circuits the node proves with
+    let encoded = bc.get_liabilities_proof(ProofFormat::Binary).unwrap().unwrap();
+    let wrapper = <ProofOfLiabilitiesWrapper>::decode(&encoded).unwrap();
+    assert!(wrapper.get_circuit_id().is_native());
+    let report = ProofBundle::from_liabilities(wrapper).and_then(ProofBundle::verify);
+    assert!(matches!(report, Ok(BundleReport::Liabilities(_))), "Bundle verification failed: {:?}", report.err());
+}
+
+#[test]
+fn test_threshold_proof_verifies_against_older_linked_header() {
+    let mut bc = Blockchain::create_blockchain_with_source(2, CircuitSource::Native).unwrap();
+    bc.add_transaction("", "alice", 100).unwrap();
+    bc.add_block().unwrap();
+    let block = bc.get_head_block().unwrap();
+    let header = BlockWrapper::from_block(block);
+    let inclusion_input = InclusionInput::new(block.get_merkle_sum_tree(), 0).unwrap();
+    let circuit_setup = NativeCircuitSetup::new(CircuitKind::InclusionThreshold, 2, 1).unwrap();
+    let proof = ProofOfThreshold::new(&inclusion_input, 50, header.clone(), &circuit_setup).unwrap();
+
+    // The liabilities are exported later, the proof's block is linked to their head
+    bc.add_transaction("", "bob", 30).unwrap();
+    bc.add_block().unwrap();
+    let encoded = bc.get_liabilities_proof(ProofFormat::Binary).unwrap().unwrap();
+    let bundle = ProofBundle::from_liabilities(<ProofOfLiabilitiesWrapper>::decode(&encoded).unwrap()).unwrap();
+    let linked = bundle.get_header(header.get_block_number()).cloned().unwrap();
+    assert_eq!(linked.get_hash(), header.get_hash());
+    assert!(matches!(bundle.verify(), Ok(BundleReport::Liabilities(_))));
+    assert!(proof.verify(&linked).is_ok());
+
+    // A header that does not hash into the next one breaks the chain
+    let mut wrapper = <ProofOfLiabilitiesWrapper>::decode(&encoded).unwrap();
+    wrapper.set_chain(vec![BlockWrapper::new(header.get_root_hash().to_string(), 1000, 2, "now".to_string())]);
+    assert!(ProofBundle::from_liabilities(wrapper).and_then(ProofBundle::verify).is_err());
+}
+
+#[test]
+fn test_solvency_with_hidden_margin() {
+    let liabilities = || {
+        let (old_tree, new_tree) = setup_test_tree();
+        let change = MerkleSumTreeChange::new(0, old_tree, new_tree);
+        let liabilities_input = LiabilitiesInput::new(vec![change]).unwrap();
+        let circuit_setup = CircuitSetup::new("liabilities_changes_folding");
+        let (proof, pp) = ProofOfLiabilities::new(vec![liabilities_input], &circuit_setup).unwrap();
+        let header = BlockWrapper::new(proof.get_final_root_hash().to_string(), proof.get_final_root_sum(), 5, "now".to_string());
+        ProofBundle::from_liabilities(ProofOfLiabilitiesWrapper::new(proof, pp, Some(header))).unwrap()
+    };
+    let reserves = vec![AssetReserve::new("native", 1000), AssetReserve::new("usdc", 20)];
+    let statement = ReservesStatement::sign(5, &reserves, &[1u8; 32]);
+    let operator_keys = vec![statement.get_public_key().to_string()];
+    let report = SolvencyReport::new(liabilities(), "native", &statement, true).unwrap();
+    assert!(report.get_statement().get_entry("native").unwrap().get_amount().is_none());
+
+    // Neither the reserves nor the salt of the commitment are in the report
+    let bytes = report.to_bytes().unwrap();
+    let salt = statement.get_entry("native").unwrap().get_salt().unwrap();
+    for secret in [Fq::from(1000).to_repr(), field_from_hex(salt).to_repr()] {
+        assert!(!bytes.windows(secret.len()).any(|window| window == secret.as_ref()));
+    }
+
+    let decoded = SolvencyReport::from_bytes(&report.to_bytes().unwrap()).unwrap();
+    let output = decoded.verify(&operator_keys);
+    assert!(output.is_ok(), "Solvency verification failed: {:?}", output.err());
+    let output = output.unwrap();
+    assert_eq!(output.get_liabilities(), 100);
+    assert_eq!(output.get_margin(), None);
+
+    // The liabilities of 100 exceed the usdc reserves
+    assert!(SolvencyReport::new(liabilities(), "usdc", &statement, true).is_err());
+}
+
+#[test]
+fn test_balance_threshold_proof_hides_balance() {
+    let (tree, header, circuit_setup) = native_fixture(&[("alice", 100)], 2, CircuitKind::InclusionThreshold);
+    let inclusion_input = InclusionInput::new(&tree, 0).unwrap();
+
+    let proof = ProofOfThreshold::new(&inclusion_input, 80, header.clone(), &circuit_setup).unwrap();
+    let decoded = ProofOfThreshold::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+    let output = decoded.verify(&header);
+    assert!(output.is_ok(), "Threshold proof verification failed: {:?}", output.err());
+    assert_eq!(output.unwrap().get_threshold(), 80);
+
+    // The embedded header is only believed when it matches a trusted one
+    let forged = BlockWrapper::new(header.get_root_hash().to_string(), 1000, 2, "now".to_string());
+    assert!(decoded.verify(&forged).is_err());
+
+    // alice holds 100, so a threshold above it cannot be proven
+    assert!(ProofOfThreshold::new(&inclusion_input, 101, header, &circuit_setup).is_err());
+}
+
+#[test]
+fn test_non_membership_over_every_leaf() {
+    // Leaves are in insertion order, not sorted
+    let (tree, header, circuit_setup) =
+        native_fixture(&[("carol", 30), ("alice", 100)], 3, CircuitKind::NonMembership);
+
+    let proof = ProofOfNonMembership::new(&tree, "bob", header.clone(), &circuit_setup).unwrap();
+    let decoded = ProofOfNonMembership::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+    let output = decoded.verify(&header);
+    assert!(output.is_ok(), "Non-membership proof verification failed: {:?}", output.err());
+    assert_eq!(output.unwrap().get_leaf_id(), "bob");
+
+    // The proof only holds for the trusted block it was made for
+    let other = BlockWrapper::new(header.get_root_hash().to_string(), header.get_root_sum() + 1, 2, "now".to_string());
+    assert!(decoded.verify(&other).is_err());
+
+    // The proof only holds for the ID it was made for
+    let json = proof.to_json().unwrap().replace("\"leaf_id\":\"bob\"", "\"leaf_id\":\"carol\"");
+    assert!(ProofOfNonMembership::from_json(&json).unwrap().verify(&header).is_err());
+    assert!(ProofOfNonMembership::new(&tree, "carol", header, &circuit_setup).is_err());
+}
+
+#[test]
+fn test_batch_inclusion_binds_every_leaf() {
+    let (tree, header, circuit_setup) =
+        native_fixture(&[("alice", 100), ("bob", 40), ("carol", 30)], 4, CircuitKind::BatchInclusion);
+    let inputs: Vec<InclusionInput> = (0..3).map(|index| InclusionInput::new(&tree, index).unwrap()).collect();
+
+    let proof = ProofOfBatchInclusion::new(&inputs, header.clone(), &circuit_setup).unwrap();
+
+    // Only the claimed leaves and the root leave the node, not the sibling hashes
+    let bytes = proof.to_bytes().unwrap();
+    for index in 0..3 {
+        for neighbor in tree.get_proof(index).unwrap().get_path() {
+            let hash = field_from_hex(&neighbor.get_node().get_hash().to_string()).to_repr();
+            assert!(!bytes.windows(hash.len()).any(|window| window == hash.as_ref()));
+        }
+    }
+
+    let decoded = ProofOfBatchInclusion::from_bytes(&bytes).unwrap();
+    let claims = decoded.verify(&header);
+    assert!(claims.is_ok(), "Batch inclusion proof verification failed: {:?}", claims.err());
+    let balances: Vec<i32> = claims.unwrap().iter().map(|claim| claim.get_user_balance()).collect();
+    assert_eq!(balances, vec![100, 40, 30]);
+
+    // The proof only holds for the trusted block it was made for
+    let other = BlockWrapper::new(header.get_root_hash().to_string(), header.get_root_sum(), 2, "now".to_string());
+    assert!(decoded.verify(&other).is_err());
+}
+
+#[test]
+fn test_tampered_batch_inclusion_is_rejected() {
+    let (tree, header, circuit_setup) =
+        native_fixture(&[("alice", 100), ("bob", 40)], 4, CircuitKind::BatchInclusion);
+    let inputs: Vec<InclusionInput> = (0..2).map(|index| InclusionInput::new(&tree, index).unwrap()).collect();
+    let json = ProofOfBatchInclusion::new(&inputs, header.clone(), &circuit_setup).unwrap().to_json().unwrap();
+
+    // Moving balance between two claims keeps the sum but breaks the accumulator
+    let tampered = json
+        .replace("\"user_balance\":100", "\"user_balance\":90")
+        .replace("\"user_balance\":40", "\"user_balance\":50");
+    assert_ne!(tampered, json);
+    assert!(ProofOfBatchInclusion::from_json(&tampered).unwrap().verify(&header).is_err());
+}
@@ -0,0 +1,47 @@
+// Verification paths that must stay usable without the node, run with
+// `cargo test --test verifier_tests --no-default-features --features verifier`
+
+use blockchain_rust::proofs::batch::ProofOfBatchInclusion;
+use blockchain_rust::proofs::encoding::ProofEncoding;
+use blockchain_rust::proofs::native::NativeCircuitSetup;
+use blockchain_rust::proofs::non_membership::ProofOfNonMembership;
+use blockchain_rust::proofs::registry::CircuitKind;
+use blockchain_rust::proofs::solvency::{commit, ReservesStatement, SolvencyReport};
+use blockchain_rust::proofs::threshold::ProofOfThreshold;
+use ed25519_dalek::SigningKey;
+use pasta_curves::Fq;
+use serde_json::json;
+
+#[test]
+fn test_native_params_without_node() {
+    for kind in [CircuitKind::InclusionThreshold, CircuitKind::BatchInclusion, CircuitKind::NonMembership] {
+        let circuit_setup = NativeCircuitSetup::new(kind, 2, 1).unwrap();
+        let circuit_id = circuit_setup.params_circuit_id().unwrap();
+        assert!(circuit_id.is_native());
+        assert_ne!(circuit_id, *circuit_setup.get_circuit_id());
+    }
+    assert!(ProofOfThreshold::from_bytes(&[1, 2, 3]).is_err());
+    assert!(ProofOfBatchInclusion::from_bytes(&[1, 2, 3]).is_err());
+    assert!(ProofOfNonMembership::from_bytes(&[1, 2, 3]).is_err());
+    assert!(SolvencyReport::from_bytes(&[1, 2, 3]).is_err());
+}
+
+#[test]
+fn test_statement_checks_without_node() {
+    let public_key = hex::encode(SigningKey::from_bytes(&[7u8; 32]).verifying_key().to_bytes());
+    let statement: ReservesStatement = serde_json::from_value(json!({
+        "block_number": 3,
+        "entries": [{
+            "asset": "native",
+            "commitment": format!("{:?}", commit(500, Fq::from(9))),
+            "amount": null,
+            "salt": null,
+        }],
+        "public_key": public_key,
+        "signature": hex::encode([0u8; 64]),
+    }))
+    .unwrap();
+    assert!(statement.check_signature().is_err());
+    assert!(statement.verify(&[public_key]).is_err());
+    assert!(statement.verify(&[]).is_err());
+}